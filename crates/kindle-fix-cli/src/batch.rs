@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use rayon::prelude::*;
+
+/// Run `work` on every file, on `jobs` worker threads (0 means one per CPU).
+///
+/// Results are handed to `emit` in input order no matter which worker
+/// finishes first, so output and summaries do not depend on scheduling.
+/// With more than one job an aggregate progress bar is drawn on stderr
+/// when `progress` is set.
+pub fn run<T, W, E>(files: &[PathBuf], jobs: usize, progress: bool, work: W, mut emit: E)
+where
+    T: Send,
+    W: Fn(&Path) -> T + Sync,
+    E: FnMut(&Path, T),
+{
+    if jobs == 1 {
+        for path in files {
+            emit(path, work(path));
+        }
+        return;
+    }
+
+    let pool = match rayon::ThreadPoolBuilder::new().num_threads(jobs).build() {
+        Ok(pool) => pool,
+        Err(e) => {
+            log::warn!(
+                "Could not start worker pool, processing sequentially: {}",
+                e
+            );
+            return run(files, 1, progress, work, emit);
+        }
+    };
+
+    let bar = progress_bar(files.len() as u64, progress);
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|scope| {
+        let work = &work;
+        scope.spawn(move || {
+            pool.install(|| {
+                files
+                    .par_iter()
+                    .enumerate()
+                    .for_each_with(tx, |tx, (index, path)| {
+                        // The receiver only goes away if emitting panicked
+                        let _ = tx.send((index, work(path)));
+                    });
+            });
+        });
+
+        // Hold back results that finish early until everything before them is done
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (index, result) in rx {
+            bar.inc(1);
+            bar.set_message(file_name(&files[index]));
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&next) {
+                bar.suspend(|| emit(&files[next], result));
+                next += 1;
+            }
+        }
+    });
+
+    bar.finish_and_clear();
+}
+
+fn progress_bar(len: u64, visible: bool) -> ProgressBar {
+    if !visible {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::with_draw_target(Some(len), ProgressDrawTarget::stderr());
+    if let Ok(style) =
+        ProgressStyle::with_template("{bar:40.cyan/blue} {pos}/{len} {elapsed} {wide_msg}")
+    {
+        bar.set_style(style);
+    }
+    bar
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use colored::Colorize;
+use glob::{MatchOptions, Pattern};
+use kindle_fix_core::naming::FIXED_PREFIX;
+use kindle_fix_core::{validate_file, FileFormat};
+use walkdir::WalkDir;
+
+const SUPPORTED_EXTENSIONS: [&str; 3] = ["epub", "mobi", "azw3"];
+
+/// Which files found in directories are processed.
+#[derive(Args, Debug, Clone)]
+pub struct FileSelection {
+    /// Descend into subdirectories
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Only process files in directories whose path matches one of these globs
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<Pattern>,
+
+    /// Skip files in directories whose path matches one of these globs
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<Pattern>,
+
+    /// Follow symbolic links found in directories (they are skipped otherwise)
+    #[arg(long)]
+    pub follow_symlinks: bool,
+}
+
+/// The files to process, in a stable order, and how many were skipped
+/// because they are the output of an earlier run.
+#[derive(Debug, Default)]
+pub struct Collected {
+    pub files: Vec<PathBuf>,
+    pub already_fixed: usize,
+    /// Directory of each file found by walking a directory, relative to it.
+    relative_dirs: HashMap<PathBuf, PathBuf>,
+}
+
+/// Collect the files named by `paths`. Files are taken as given; directories
+/// contribute the supported ebooks they contain, filtered by `selection`.
+/// Globs match the path relative to the directory, so `*` also matches `/`.
+///
+/// Walks never enter `output_dir`, so books written there by an earlier run
+/// are not picked up again, whatever they were named.
+pub fn collect_files(
+    paths: &[PathBuf],
+    selection: &FileSelection,
+    output_dir: Option<&Path>,
+) -> Collected {
+    let mut collected = Collected::default();
+    let output_dir = output_dir.and_then(|dir| fs::canonicalize(dir).ok());
+
+    for path in paths {
+        if path.is_dir() {
+            let max_depth = if selection.recursive { usize::MAX } else { 1 };
+            let walker = WalkDir::new(path)
+                .follow_links(selection.follow_symlinks)
+                .max_depth(max_depth)
+                .sort_by_file_name()
+                .into_iter()
+                .filter_entry(|entry| {
+                    !entry.file_type().is_dir()
+                        || output_dir.is_none()
+                        || fs::canonicalize(entry.path()).ok() != output_dir
+                });
+
+            for entry in walker {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        eprintln!("{}: {}", "Warning".yellow(), e);
+                        continue;
+                    }
+                };
+                // Without --follow-symlinks, links are reported as symlinks, not files
+                if !entry.file_type().is_file() || !is_supported(entry.path()) {
+                    continue;
+                }
+                let relative = entry.path().strip_prefix(path).unwrap_or(entry.path());
+                if selection.is_selected(relative) {
+                    let relative_dir = relative.parent().unwrap_or(Path::new("")).to_path_buf();
+                    let path = entry.into_path();
+                    collected.relative_dirs.insert(path.clone(), relative_dir);
+                    collected.push(path);
+                }
+            }
+        } else if path.is_file() {
+            collected.push(path.clone());
+        } else {
+            eprintln!("{}: {} not found", "Warning".yellow(), path.display());
+        }
+    }
+
+    collected
+}
+
+impl FileSelection {
+    fn is_selected(&self, relative: &Path) -> bool {
+        let options = MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: false,
+            require_literal_leading_dot: false,
+        };
+        let matches = |pattern: &Pattern| pattern.matches_path_with(relative, options);
+
+        let included = self.include.is_empty() || self.include.iter().any(matches);
+        included && !self.exclude.iter().any(matches)
+    }
+}
+
+impl Collected {
+    /// Where `path` sits below the directory it was found in, if it was
+    /// found by walking one.
+    pub fn relative_dir(&self, path: &Path) -> Option<&Path> {
+        self.relative_dirs.get(path).map(PathBuf::as_path)
+    }
+
+    fn push(&mut self, path: PathBuf) {
+        if is_fixed_output(&path) {
+            log::info!("Skipping {}: already fixed", path.display());
+            self.already_fixed += 1;
+        } else {
+            self.files.push(path);
+        }
+    }
+}
+
+fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| SUPPORTED_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Whether `path` was written by an earlier run with the default name
+/// template; such files are never picked up again. Books named by a custom
+/// template are only left alone when they were written to `--output`.
+pub fn is_fixed_output(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with(FIXED_PREFIX))
+}
+
+/// Write `data` to `path` without ever leaving a half-written book behind.
+///
+/// The data goes to a temporary file in the same directory, which must
+/// re-open as a valid `format` book before it is renamed over `path`. With
+/// `backup`, the file being replaced is first copied to `<path><suffix>`.
+pub fn write_atomically(
+    path: &Path,
+    data: &[u8],
+    format: FileFormat,
+    backup: Option<&str>,
+) -> Result<(), String> {
+    let write_error = |e: io::Error| format!("Could not write {}: {}", path.display(), e);
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut temp = tempfile::Builder::new()
+        .prefix(".kindle-file-fix-")
+        .suffix(".tmp")
+        .tempfile_in(dir)
+        .map_err(write_error)?;
+    temp.write_all(data)
+        .and_then(|_| temp.as_file().sync_all())
+        .map_err(write_error)?;
+
+    let written = fs::read(temp.path()).map_err(write_error)?;
+    validate_file(&written, format).map_err(|e| {
+        format!(
+            "Left {} untouched, the fixed book did not re-open: {}",
+            path.display(),
+            e
+        )
+    })?;
+
+    if let Ok(metadata) = fs::metadata(path) {
+        // The replacement keeps the permissions of the file it replaces
+        fs::set_permissions(temp.path(), metadata.permissions()).map_err(write_error)?;
+        if let Some(suffix) = backup {
+            let backup_path = backup_path(path, suffix);
+            fs::copy(path, &backup_path).map_err(|e| {
+                format!(
+                    "Could not back up {} to {}: {}",
+                    path.display(),
+                    backup_path.display(),
+                    e
+                )
+            })?;
+        }
+    }
+
+    temp.persist(path).map_err(|e| write_error(e.error))?;
+    Ok(())
+}
+
+/// Where `--backup` keeps the original of `path`.
+pub fn backup_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
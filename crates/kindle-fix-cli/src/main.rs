@@ -1,26 +1,48 @@
+mod batch;
+mod files;
 mod output;
 
+use std::collections::HashSet;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-use clap::Parser;
+use clap::builder::NonEmptyStringValueParser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use colored::Colorize;
 use dialoguer::Input;
 
-use kindle_fix_core::{process_file, FixOptions};
+use kindle_fix_core::formats::epub::fixes;
+use kindle_fix_core::formats::epub::writer::EntryOrder;
+use kindle_fix_core::naming::DEFAULT_TEMPLATE;
+use kindle_fix_core::{
+    check_file, process_file, DiagnosticCode, FixOptions, FixReport, NameTemplate,
+};
+
+use files::{collect_files, write_atomically, Collected, FileSelection};
+use output::{CheckSummary, FileResult, JsonOutput, Summary};
 
 #[derive(Parser, Debug)]
 #[command(
     name = "kindle-file-fix",
     about = "Fix ebook files for Kindle compatibility",
-    version
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Input files or directories to process
-    #[arg(required = true)]
+    #[arg(required_unless_present = "list_fixes")]
     files: Vec<PathBuf>,
 
-    /// Output directory (default: same as input file)
+    #[command(flatten)]
+    selection: FileSelection,
+
+    /// Output directory (default: same as input file); books found in
+    /// subdirectories keep their place below it
     #[arg(short, long)]
     output: Option<PathBuf>,
 
@@ -32,10 +54,87 @@ struct Cli {
     #[arg(long)]
     keep_name: bool,
 
+    /// Name fixed files from a template with {stem}, {ext}, {title}, {author},
+    /// {lang} and {date}; a "/" sorts them into subdirectories. Later runs only
+    /// recognise such files when they were written to --output; otherwise use
+    /// --stamp or --exclude so they are not fixed again
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = DEFAULT_TEMPLATE,
+        conflicts_with_all = ["keep_name", "in_place"]
+    )]
+    name_template: NameTemplate,
+
+    /// Replace each input with its fixed version, once it has been validated
+    #[arg(long, conflicts_with_all = ["output", "keep_name", "to_epub"])]
+    in_place: bool,
+
+    /// With --in-place, keep each original as <file><SUFFIX> (default: .bak)
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ".bak",
+        value_name = "SUFFIX",
+        value_parser = NonEmptyStringValueParser::new(),
+        requires = "in_place"
+    )]
+    backup: Option<String>,
+
+    /// Also write books that need no fixes, as byte-for-byte copies
+    #[arg(long, conflicts_with = "in_place")]
+    copy_unchanged: bool,
+
+    /// Record the version and the fixes run in each fixed EPUB, so later
+    /// runs only apply fixes added since
+    #[arg(long)]
+    stamp: bool,
+
+    /// Run every fix, even on books stamped by an earlier run
+    #[arg(long)]
+    force: bool,
+
     /// Show fixes without writing files
     #[arg(long)]
     dry_run: bool,
 
+    /// Show a unified diff of every changed file in the book (implies --dry-run)
+    #[arg(long)]
+    diff: bool,
+
+    /// Convert MOBI/AZW3 books to EPUB
+    #[arg(long)]
+    to_epub: bool,
+
+    /// Write byte-for-byte reproducible EPUBs (honours SOURCE_DATE_EPOCH)
+    #[arg(long)]
+    reproducible: bool,
+
+    /// Entry order of reproducible EPUBs
+    #[arg(long, value_enum, default_value = "original", requires = "reproducible")]
+    entry_order: OrderArg,
+
+    /// Run only these fixes (comma-separated ids, see --list-fixes)
+    #[arg(long, value_delimiter = ',', value_name = "IDS")]
+    only: Option<Vec<String>>,
+
+    /// Skip these fixes (comma-separated ids, see --list-fixes)
+    #[arg(long, value_delimiter = ',', value_name = "IDS")]
+    skip: Vec<String>,
+
+    /// Output format: coloured text, one JSON document, or one JSON object per file
+    #[arg(long, value_enum, default_value = "text", global = true)]
+    format: OutputFormat,
+
+    /// Number of files to process at once (0 uses one job per CPU)
+    #[arg(short, long, default_value_t = 1, value_name = "N", global = true)]
+    jobs: usize,
+
+    /// List the available fixes and exit
+    #[arg(long)]
+    list_fixes: bool,
+
     /// Verbose output
     #[arg(short, long)]
     verbose: bool,
@@ -45,8 +144,73 @@ struct Cli {
     quiet: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Report Kindle compatibility problems without writing anything.
+    /// Exits with code 3 when problems are found.
+    Check(CheckArgs),
+}
+
+#[derive(Args, Debug)]
+struct CheckArgs {
+    /// Input files or directories to check
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+
+    #[command(flatten)]
+    selection: FileSelection,
+
+    /// Language code to check against instead of the book's own
+    #[arg(short, long)]
+    language: Option<String>,
+
+    /// Run only these checks (comma-separated fix ids, see --list-fixes)
+    #[arg(long, value_delimiter = ',', value_name = "IDS")]
+    only: Option<Vec<String>>,
+
+    /// Skip these checks (comma-separated fix ids, see --list-fixes)
+    #[arg(long, value_delimiter = ',', value_name = "IDS")]
+    skip: Vec<String>,
+
+    /// Only print files with problems
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+/// Exit code of `check` when a file has problems.
+const EXIT_PROBLEMS: i32 = 3;
+
+/// Output format for `--format`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Ndjson,
+}
+
+/// Entry order for `--entry-order`.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OrderArg {
+    /// Keep the order of the original archive
+    Original,
+    /// Sort entries by name
+    Sorted,
+}
+
+impl From<OrderArg> for EntryOrder {
+    fn from(order: OrderArg) -> Self {
+        match order {
+            OrderArg::Original => EntryOrder::Original,
+            OrderArg::Sorted => EntryOrder::Sorted,
+        }
+    }
+}
+
 fn main() {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    if cli.keep_name {
+        cli.name_template = NameTemplate::keep_name();
+    }
 
     if cli.verbose {
         env_logger::Builder::from_default_env()
@@ -56,174 +220,373 @@ fn main() {
         env_logger::init();
     }
 
-    let files = collect_files(&cli.files);
+    if cli.list_fixes {
+        output::print_fixes();
+        return;
+    }
 
-    if files.is_empty() {
-        eprintln!("{}", "No supported files found.".red());
-        std::process::exit(1);
+    if let Some(Command::Check(args)) = &cli.command {
+        check(args, cli.format, cli.jobs);
+        return;
     }
 
+    validate_fix_ids(cli.only.iter().flatten().chain(&cli.skip));
+
+    let collected = gather_files(
+        &cli.files,
+        &cli.selection,
+        cli.output.as_deref(),
+        cli.quiet,
+    );
+    let files = &collected.files;
+    // Where books have been written so far, so that no two are saved to the same path
+    let written = Mutex::new(HashSet::new());
+
+    // Machine-readable output replaces every other message on stdout
+    let text = cli.format == OutputFormat::Text;
+    let quiet = cli.quiet || !text;
+    let mut json = JsonOutput::new(cli.format == OutputFormat::Ndjson);
+
+    let options = FixOptions {
+        language: cli.language.clone(),
+        keep_name: cli.keep_name,
+        dry_run: cli.dry_run || cli.diff,
+        convert_to_epub: cli.to_epub,
+        reproducible: cli.reproducible.then(|| cli.entry_order.into()),
+        only: cli.only.clone(),
+        skip: cli.skip.clone(),
+        diff: cli.diff,
+        stamp: cli.stamp,
+        force: cli.force,
+    };
+    // Workers cannot share the terminal, so only a sequential run announces
+    // each file before fixing it and prompts
+    let announce = !quiet && cli.jobs == 1;
+    let prompt = announce && cli.language.is_none();
+
     let mut total_fixes = 0;
     let mut processed = 0;
+    let mut unchanged = 0;
     let mut errors = 0;
 
-    for path in &files {
-        let filename = path.file_name().unwrap_or_default().to_string_lossy();
-
-        if !cli.quiet {
-            println!("{} {}", "Processing:".bold(), filename);
-        }
-
-        let data = match fs::read(path) {
-            Ok(d) => d,
-            Err(e) => {
-                eprintln!("  {} Could not read {}: {}", "[ERROR]".red(), filename, e);
-                errors += 1;
-                continue;
+    batch::run(
+        files,
+        cli.jobs,
+        !quiet,
+        |path| {
+            let output = Output {
+                cli: &cli,
+                relative_dir: collected.relative_dir(path),
+                written: &written,
+            };
+            if announce {
+                println!("{} {}", "Processing:".bold(), path.display());
+            }
+            fix_path(path, &options, &output, prompt)
+        },
+        |path, result| {
+            if !quiet && !announce {
+                println!("{} {}", "Processing:".bold(), path.display());
+            }
+            if let Some(report) = &result.report {
+                output::print_report(report, quiet, cli.verbose);
+                if text {
+                    output::print_diffs(&result.diffs);
+                }
+                total_fixes += report.fixes_applied.len();
+                processed += 1;
+            }
+            if result.unchanged {
+                unchanged += 1;
+                if text && result.output_path.is_none() && !options.dry_run {
+                    println!(
+                        "  {} nothing to fix, not written",
+                        "Unchanged:".blue().bold()
+                    );
+                }
             }
-        };
-
-        let options = FixOptions {
-            language: cli.language.clone(),
-            keep_name: cli.keep_name,
-            dry_run: cli.dry_run,
-        };
-
-        match process_file(&data, &filename, &options) {
-            Ok(result) => {
-                // Handle unsupported language warnings with interactive prompt
-                for warning in &result.report.warnings {
-                    if warning.contains("not supported by Kindle")
-                        && cli.language.is_none()
-                        && !cli.quiet
-                    {
-                        eprintln!("  {} {}", "[WARN]".yellow().bold(), warning);
-                        if let Ok(lang) = Input::<String>::new()
-                            .with_prompt("  Enter language code (e.g., en, fr, ja)")
-                            .default("en".into())
-                            .interact_text()
-                        {
-                            let new_options = FixOptions {
-                                language: Some(lang),
-                                keep_name: cli.keep_name,
-                                dry_run: cli.dry_run,
-                            };
-                            if let Ok(new_result) = process_file(&data, &filename, &new_options) {
-                                output::print_report(&new_result.report, cli.quiet);
-                                if !cli.dry_run {
-                                    write_output(
-                                        path,
-                                        &new_result.data,
-                                        cli.keep_name,
-                                        &cli.output,
-                                    );
-                                }
-                                total_fixes += new_result.report.fixes_applied.len();
-                                processed += 1;
-                                continue;
-                            }
-                        }
-                    }
+            if let Some(written) = result.output_path.as_ref().filter(|_| text) {
+                println!("  {} {}", "Saved:".green().bold(), written.display());
+            }
+            if let Some(e) = &result.error {
+                if text {
+                    eprintln!("  {} {}", "[ERROR]".red().bold(), e);
                 }
+                errors += 1;
+            }
+            if !quiet {
+                println!();
+            }
+            json.push(result);
+        },
+    );
 
-                output::print_report(&result.report, cli.quiet);
+    if !quiet {
+        println!(
+            "{}",
+            format!(
+                "Processed {} file(s), {} fix(es) applied, {} unchanged, {} error(s).",
+                processed, total_fixes, unchanged, errors
+            )
+            .bold()
+        );
+    }
+    if !text {
+        json.finish(Summary {
+            processed,
+            fixes_applied: total_fixes,
+            unchanged,
+            errors,
+        });
+    }
 
-                if !cli.dry_run && !result.data.is_empty() {
-                    write_output(path, &result.data, cli.keep_name, &cli.output);
-                }
+    if errors > 0 {
+        std::process::exit(1);
+    }
+}
 
-                total_fixes += result.report.fixes_applied.len();
-                processed += 1;
+fn check(args: &CheckArgs, format: OutputFormat, jobs: usize) {
+    validate_fix_ids(args.only.iter().flatten().chain(&args.skip));
+
+    let files = gather_files(&args.files, &args.selection, None, args.quiet).files;
+
+    let options = FixOptions {
+        language: args.language.clone(),
+        only: args.only.clone(),
+        skip: args.skip.clone(),
+        ..Default::default()
+    };
+
+    let text = format == OutputFormat::Text;
+    let mut json = JsonOutput::new(format == OutputFormat::Ndjson);
+    let mut with_problems = 0;
+    let mut errors = 0;
+
+    batch::run(
+        &files,
+        jobs,
+        text && !args.quiet,
+        |path| {
+            let filename = path.file_name().unwrap_or_default().to_string_lossy();
+            fs::read(path)
+                .map_err(|e| format!("Could not read {}: {}", filename, e))
+                .and_then(|data| check_file(&data, &filename, &options).map_err(|e| e.to_string()))
+        },
+        |path, result| match result {
+            Ok(report) => {
+                if report.has_problems() {
+                    with_problems += 1;
+                }
+                if text {
+                    output::print_check_report(path, &report, args.quiet);
+                }
+                json.push(FileResult {
+                    path: path.to_path_buf(),
+                    output_path: None,
+                    report: Some(report),
+                    diffs: Vec::new(),
+                    unchanged: false,
+                    error: None,
+                });
             }
             Err(e) => {
-                eprintln!("  {} {}", "[ERROR]".red().bold(), e);
+                if text {
+                    println!("{} {}", "Checking:".bold(), path.display());
+                    eprintln!("  {} {}", "[ERROR]".red().bold(), e);
+                }
+                json.push(FileResult::error(path, e));
                 errors += 1;
             }
-        }
-
-        if !cli.quiet {
-            println!();
-        }
-    }
+        },
+    );
 
-    if !cli.quiet {
+    if text && !args.quiet {
         println!(
             "{}",
             format!(
-                "Processed {} file(s), {} fix(es) applied, {} error(s).",
-                processed, total_fixes, errors
+                "Checked {} file(s), {} with problems, {} error(s).",
+                files.len() - errors,
+                with_problems,
+                errors
             )
             .bold()
         );
     }
+    if !text {
+        json.finish(CheckSummary {
+            checked: files.len() - errors,
+            with_problems,
+            errors,
+        });
+    }
 
     if errors > 0 {
         std::process::exit(1);
     }
+    if with_problems > 0 {
+        std::process::exit(EXIT_PROBLEMS);
+    }
 }
 
-fn collect_files(paths: &[PathBuf]) -> Vec<PathBuf> {
-    let supported_extensions = ["epub", "mobi", "azw3"];
-    let mut result = Vec::new();
-
-    for path in paths {
-        if path.is_dir() {
-            if let Ok(entries) = fs::read_dir(path) {
-                for entry in entries.flatten() {
-                    let p = entry.path();
-                    if let Some(ext) = p.extension() {
-                        if supported_extensions
-                            .contains(&ext.to_string_lossy().to_lowercase().as_str())
-                        {
-                            result.push(p);
-                        }
-                    }
-                }
-            }
-        } else if path.is_file() {
-            result.push(path.clone());
-        } else {
-            eprintln!("{}: {} not found", "Warning".yellow(), path.display());
-        }
+/// Collect the files to process, exiting if there are none.
+fn gather_files(
+    paths: &[PathBuf],
+    selection: &FileSelection,
+    output_dir: Option<&Path>,
+    quiet: bool,
+) -> Collected {
+    let collected = collect_files(paths, selection, output_dir);
+
+    if collected.already_fixed > 0 && !quiet {
+        eprintln!(
+            "{} {} file(s) already fixed by an earlier run.",
+            "Skipped".yellow(),
+            collected.already_fixed
+        );
+    }
+    if collected.files.is_empty() {
+        eprintln!("{}", "No supported files found.".red());
+        std::process::exit(1);
     }
 
-    result
+    collected
 }
 
-fn write_output(input_path: &Path, data: &[u8], keep_name: bool, output_dir: &Option<PathBuf>) {
-    let filename = input_path
-        .file_name()
-        .unwrap_or_default()
-        .to_string_lossy();
+/// Exit with a usage error if any id does not name a fix.
+fn validate_fix_ids<'a>(ids: impl IntoIterator<Item = &'a String>) {
+    for id in ids {
+        if fixes::find(id).is_none() {
+            eprintln!(
+                "{} Unknown fix '{}'. Use --list-fixes to see the available fixes.",
+                "Error:".red().bold(),
+                id
+            );
+            std::process::exit(2);
+        }
+    }
+}
 
-    let output_filename = if keep_name {
-        filename.to_string()
-    } else {
-        format!("(fixed) {}", filename)
-    };
+/// Where and how one fixed file is written.
+struct Output<'a> {
+    cli: &'a Cli,
+    /// Subdirectory of `--output` the file goes to, for files found by
+    /// walking a directory.
+    relative_dir: Option<&'a Path>,
+    written: &'a Mutex<HashSet<PathBuf>>,
+}
 
-    let output_path = if let Some(dir) = output_dir {
-        fs::create_dir_all(dir).ok();
-        dir.join(&output_filename)
-    } else {
-        input_path.with_file_name(&output_filename)
+/// Read, fix and write one file. Runs on a worker thread, so everything
+/// except the language prompt is left to the caller to print.
+fn fix_path(
+    path: &Path,
+    options: &FixOptions,
+    output: &Output,
+    prompt: bool,
+) -> FileResult<FixReport> {
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+
+    let data = match fs::read(path) {
+        Ok(d) => d,
+        Err(e) => return FileResult::error(path, format!("Could not read {}: {}", filename, e)),
     };
 
-    match fs::write(&output_path, data) {
-        Ok(_) => {
-            println!(
-                "  {} {}",
-                "Saved:".green().bold(),
-                output_path.display()
-            );
+    let mut result = process_file(&data, &filename, options);
+
+    // Handle unsupported language warnings with interactive prompt
+    if let Ok(output) = &result {
+        let unsupported = output
+            .report
+            .warnings
+            .iter()
+            .find(|warning| warning.code == DiagnosticCode::UnsupportedLanguage);
+        if let Some(warning) = unsupported.filter(|_| prompt) {
+            eprintln!("  {} {}: {}", "[WARN]".yellow().bold(), filename, warning);
+            if let Ok(lang) = Input::<String>::new()
+                .with_prompt("  Enter language code (e.g., en, fr, ja)")
+                .default("en".into())
+                .interact_text()
+            {
+                let new_options = FixOptions {
+                    language: Some(lang),
+                    ..options.clone()
+                };
+                if let Ok(new_result) = process_file(&data, &filename, &new_options) {
+                    result = Ok(new_result);
+                }
+            }
         }
-        Err(e) => {
-            eprintln!(
-                "  {} Could not write {}: {}",
-                "[ERROR]".red(),
-                output_path.display(),
-                e
-            );
+    }
+
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => return FileResult::error(path, e.to_string()),
+    };
+
+    let mut output_path = None;
+    let mut error = None;
+    let skip_write = result.unchanged && !output.cli.copy_unchanged;
+    if !options.dry_run && !skip_write && !result.data.is_empty() {
+        match write_output(path, &result.data, &result.report, output) {
+            Ok(written) => output_path = Some(written),
+            Err(e) => error = Some(e),
         }
     }
+
+    FileResult {
+        path: path.to_path_buf(),
+        output_path,
+        report: Some(result.report),
+        diffs: result.diffs,
+        unchanged: result.unchanged,
+        error,
+    }
+}
+
+/// Write a fixed file over the input, next to it or into the output
+/// directory, returning where it was written. Refuses to overwrite a book
+/// written earlier in the same run.
+fn write_output(
+    input_path: &Path,
+    data: &[u8],
+    report: &FixReport,
+    output: &Output,
+) -> Result<PathBuf, String> {
+    let cli = output.cli;
+    if cli.in_place {
+        write_atomically(
+            input_path,
+            data,
+            report.output_format,
+            cli.backup.as_deref(),
+        )?;
+        return Ok(input_path.to_path_buf());
+    }
+
+    let filename = input_path.file_name().unwrap_or_default().to_string_lossy();
+    let output_name = cli.name_template.render(&filename, report);
+
+    let output_path = match &cli.output {
+        Some(dir) => dir
+            .join(output.relative_dir.unwrap_or(Path::new("")))
+            .join(&output_name),
+        None => input_path.with_file_name(&output_name),
+    };
+    let claimed = output
+        .written
+        .lock()
+        .expect("output paths lock")
+        .insert(output_path.clone());
+    if !claimed {
+        return Err(format!(
+            "Not writing {}: another book in this run was already saved there",
+            output_path.display()
+        ));
+    }
+    if let Some(dir) = output_path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    }
+
+    write_atomically(&output_path, data, report.output_format, None)?;
+    Ok(output_path)
 }
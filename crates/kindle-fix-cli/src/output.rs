@@ -1,20 +1,234 @@
-use colored::Colorize;
-use kindle_fix_core::FixReport;
+use std::path::{Path, PathBuf};
 
-pub fn print_report(report: &FixReport, quiet: bool) {
+use colored::{ColoredString, Colorize};
+use kindle_fix_core::formats::epub::fixes::registry;
+use kindle_fix_core::{Change, CheckReport, Diagnostic, FileDiff, FixReport, Severity};
+use serde::Serialize;
+
+/// Longest snippet of a change shown in verbose output.
+const SNIPPET_LEN: usize = 60;
+
+pub fn print_report(report: &FixReport, quiet: bool, verbose: bool) {
     if quiet {
         return;
     }
 
+    if let Some(stamp) = report
+        .stamp
+        .as_ref()
+        .filter(|_| !report.already_applied.is_empty())
+    {
+        println!(
+            "  {} Already fixed by version {}: {}",
+            "[SKIP]".cyan().bold(),
+            stamp.version,
+            report.already_applied.join(", ")
+        );
+    }
+
     for fix in &report.fixes_applied {
         println!("  {} {}", "[FIXED]".green().bold(), fix.details);
+        if verbose {
+            for change in &fix.changes {
+                print_change(change);
+            }
+        }
     }
 
     for warning in &report.warnings {
-        println!("  {} {}", "[WARN]".yellow().bold(), warning);
+        println!("  {} {}{}", label(warning), warning, location(warning));
     }
 
     if report.fixes_applied.is_empty() && report.warnings.is_empty() {
         println!("  {} No issues found", "[OK]".blue().bold());
     }
 }
+
+pub fn print_check_report(path: &Path, report: &CheckReport, quiet: bool) {
+    if quiet && !report.has_problems() {
+        return;
+    }
+
+    println!("{} {}", "Checking:".bold(), path.display());
+
+    for problem in &report.problems {
+        let fix = problem
+            .fix
+            .as_ref()
+            .map(|fix| format!(" (fix: {})", fix))
+            .unwrap_or_default();
+        println!(
+            "  {} {} {}{}{}",
+            label(problem),
+            problem.code.as_str().dimmed(),
+            problem.message,
+            location(problem),
+            fix
+        );
+        if let Some(suggestion) = &problem.suggestion {
+            println!("          {}", suggestion.dimmed());
+        }
+    }
+
+    if !report.has_problems() {
+        println!("  {} No issues found", "[OK]".blue().bold());
+    }
+}
+
+pub fn print_diffs(diffs: &[FileDiff]) {
+    for diff in diffs {
+        println!();
+        for line in diff.diff.lines() {
+            let line = if line.starts_with("+++") || line.starts_with("---") {
+                line.bold()
+            } else if line.starts_with('+') {
+                line.green()
+            } else if line.starts_with('-') {
+                line.red()
+            } else if line.starts_with("@@") {
+                line.cyan()
+            } else {
+                line.normal()
+            };
+            println!("{}", line);
+        }
+    }
+}
+
+fn print_change(change: &Change) {
+    let location = match (change.line, change.column) {
+        (Some(line), Some(column)) => format!("{}:{}:{}", change.file, line, column),
+        _ => change.file.clone(),
+    };
+    println!(
+        "      {} {} {} {}",
+        location.dimmed(),
+        snippet(&change.original).red(),
+        "->".dimmed(),
+        snippet(&change.replacement).green()
+    );
+}
+
+/// Quote `text` for display, shortened to `SNIPPET_LEN` characters.
+fn snippet(text: &str) -> String {
+    if text.chars().count() > SNIPPET_LEN {
+        let short: String = text.chars().take(SNIPPET_LEN).collect();
+        format!("{:?}...", short)
+    } else {
+        format!("{:?}", text)
+    }
+}
+
+fn label(diagnostic: &Diagnostic) -> ColoredString {
+    match diagnostic.severity {
+        Severity::Error => "[ERROR]".red().bold(),
+        Severity::Warning => "[WARN]".yellow().bold(),
+    }
+}
+
+fn location(diagnostic: &Diagnostic) -> String {
+    diagnostic
+        .location()
+        .map(|location| format!(" ({})", location))
+        .unwrap_or_default()
+}
+
+pub fn print_fixes() {
+    for fix in registry() {
+        let default = if fix.enabled_by_default() {
+            ""
+        } else {
+            " (off by default)"
+        };
+        println!("  {:<12} {}{}", fix.id().bold(), fix.description(), default);
+    }
+}
+
+/// Outcome for one input file in `--format json` and `ndjson` output.
+#[derive(Serialize)]
+pub struct FileResult<R> {
+    pub path: PathBuf,
+    pub output_path: Option<PathBuf>,
+    pub report: Option<R>,
+    /// Unified diffs of the changed files, with `--diff`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub diffs: Vec<FileDiff>,
+    /// Nothing needed fixing, so the book was not rewritten.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub unchanged: bool,
+    pub error: Option<String>,
+}
+
+impl<R> FileResult<R> {
+    pub fn error(path: &Path, error: String) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            output_path: None,
+            report: None,
+            diffs: Vec::new(),
+            unchanged: false,
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Summary {
+    pub processed: usize,
+    pub fixes_applied: usize,
+    pub unchanged: usize,
+    pub errors: usize,
+}
+
+#[derive(Serialize)]
+pub struct CheckSummary {
+    pub checked: usize,
+    pub with_problems: usize,
+    pub errors: usize,
+}
+
+#[derive(Serialize)]
+struct Document<R, S> {
+    files: Vec<FileResult<R>>,
+    summary: S,
+}
+
+/// Writes file results as NDJSON as they arrive, or collects them into one
+/// JSON document printed by `finish`.
+pub struct JsonOutput<R> {
+    ndjson: bool,
+    files: Vec<FileResult<R>>,
+}
+
+impl<R: Serialize> JsonOutput<R> {
+    pub fn new(ndjson: bool) -> Self {
+        Self {
+            ndjson,
+            files: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, result: FileResult<R>) {
+        if self.ndjson {
+            println!("{}", to_json(&result));
+        } else {
+            self.files.push(result);
+        }
+    }
+
+    /// Print the JSON document. NDJSON output has no summary line, so this
+    /// does nothing for it.
+    pub fn finish<S: Serialize>(self, summary: S) {
+        if !self.ndjson {
+            let document = Document {
+                files: self.files,
+                summary,
+            };
+            println!("{}", to_json(&document));
+        }
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("reports serialize to JSON")
+}
@@ -1,9 +1,24 @@
-use std::io::Write;
+use std::io::{Read, Seek, Write};
 use std::process::Command;
 
 use tempfile::NamedTempFile;
 
 fn build_test_epub() -> Vec<u8> {
+    build_epub_with_chapter(b"<html><body>No encoding declaration</body></html>")
+}
+
+/// A book that needs no fixes.
+fn build_clean_epub() -> Vec<u8> {
+    build_epub_with_chapter(
+        br#"<?xml version="1.0" encoding="UTF-8"?><html><body>Clean</body></html>"#,
+    )
+}
+
+fn build_epub_with_chapter(chapter: &[u8]) -> Vec<u8> {
+    build_epub("en", chapter)
+}
+
+fn build_epub(language: &str, chapter: &[u8]) -> Vec<u8> {
     use std::io::Cursor;
     use zip::write::SimpleFileOptions;
     use zip::ZipWriter;
@@ -31,24 +46,85 @@ fn build_test_epub() -> Vec<u8> {
 
     zip.start_file("OEBPS/content.opf", deflated).unwrap();
     zip.write_all(
-        br#"<?xml version="1.0" encoding="UTF-8"?>
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
 <package xmlns="http://www.idpf.org/2007/opf" version="3.0">
   <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
     <dc:title>Test</dc:title>
-    <dc:language>en</dc:language>
+    <dc:language>{}</dc:language>
   </metadata>
-  <manifest/><spine/>
+  <manifest>
+    <item id="chapter1" href="chapter1.xhtml" media-type="application/xhtml+xml"/>
+  </manifest><spine/>
 </package>"#,
+            language
+        )
+        .as_bytes(),
     )
     .unwrap();
 
     zip.start_file("OEBPS/chapter1.xhtml", deflated).unwrap();
-    zip.write_all(b"<html><body>No encoding declaration</body></html>")
-        .unwrap();
+    zip.write_all(chapter).unwrap();
 
     zip.finish().unwrap().into_inner()
 }
 
+/// A MOBI 6 book with a supported language, so converting it is its only fix.
+fn build_clean_mobi() -> Vec<u8> {
+    let text = b"<html><body><p>Clean</p></body></html>";
+    let put = |rec: &mut [u8], offset: usize, value: u32| {
+        rec[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    };
+
+    let mut exth = b"EXTH".to_vec();
+    exth.extend_from_slice(&24u32.to_be_bytes());
+    exth.extend_from_slice(&1u32.to_be_bytes());
+    exth.extend_from_slice(&524u32.to_be_bytes());
+    exth.extend_from_slice(&10u32.to_be_bytes());
+    exth.extend_from_slice(b"en\0\0");
+
+    let mut record0 = vec![0u8; 16 + 0xE8];
+    record0[0..2].copy_from_slice(&1u16.to_be_bytes());
+    put(&mut record0, 4, text.len() as u32);
+    record0[8..10].copy_from_slice(&1u16.to_be_bytes());
+    record0[10..12].copy_from_slice(&4096u16.to_be_bytes());
+    record0[16..20].copy_from_slice(b"MOBI");
+    put(&mut record0, 0x14, 0xE8);
+    put(&mut record0, 0x18, 2);
+    put(&mut record0, 0x1C, 65001);
+    put(&mut record0, 0x24, 6);
+    for offset in (0x28..0x50).step_by(4) {
+        put(&mut record0, offset, 0xFFFF_FFFF);
+    }
+    put(&mut record0, 0x50, 2);
+    put(&mut record0, 0x68, 6);
+    put(&mut record0, 0x6C, 0xFFFF_FFFF);
+    put(&mut record0, 0x80, 0x40);
+    put(&mut record0, 0xF4, 0xFFFF_FFFF);
+    record0.extend_from_slice(&exth);
+    let name_offset = record0.len() as u32;
+    put(&mut record0, 0x54, name_offset);
+    put(&mut record0, 0x58, 4);
+    record0.extend_from_slice(b"Test\0\0\0\0");
+
+    let records = [record0, text.to_vec(), vec![0xE9, 0x8E, 0x0D, 0x0A]];
+    let mut out = vec![0u8; 78];
+    out[..4].copy_from_slice(b"Test");
+    out[60..68].copy_from_slice(b"BOOKMOBI");
+    out[76..78].copy_from_slice(&(records.len() as u16).to_be_bytes());
+    let mut offset = 78 + records.len() * 8 + 2;
+    for (i, record) in records.iter().enumerate() {
+        out.extend_from_slice(&(offset as u32).to_be_bytes());
+        out.extend_from_slice(&((2 * i) as u32).to_be_bytes());
+        offset += record.len();
+    }
+    out.extend_from_slice(&[0, 0]);
+    for record in &records {
+        out.extend_from_slice(record);
+    }
+    out
+}
+
 #[test]
 fn cli_processes_epub_file() {
     let epub_data = build_test_epub();
@@ -112,3 +188,536 @@ fn cli_fails_on_nonexistent_file() {
 
     assert!(!output.status.success());
 }
+
+#[test]
+fn cli_reproducible_output_is_identical_across_runs() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("book.epub");
+    std::fs::write(&input, build_test_epub()).unwrap();
+
+    let run = |out: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+            .arg(&input)
+            .args(["--reproducible", "--entry-order", "sorted", "--output"])
+            .arg(dir.path().join(out))
+            .env("SOURCE_DATE_EPOCH", "1700000000")
+            .output()
+            .expect("failed to execute");
+        assert!(
+            output.status.success(),
+            "stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        std::fs::read(dir.path().join(out).join("(fixed) book.epub")).unwrap()
+    };
+
+    assert_eq!(run("first"), run("second"));
+}
+
+#[test]
+fn cli_lists_fixes() {
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg("--list-fixes")
+        .output()
+        .expect("failed to execute");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for id in ["body_id", "language", "stray_img", "encoding"] {
+        assert!(stdout.contains(id), "missing {} in {}", id, stdout);
+    }
+}
+
+#[test]
+fn cli_rejects_unknown_fix() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("book.epub");
+    std::fs::write(&input, build_test_epub()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg(&input)
+        .args(["--skip", "not_a_fix"])
+        .output()
+        .expect("failed to execute");
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not_a_fix"));
+}
+
+#[test]
+fn cli_check_reports_problems_and_writes_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("book.epub");
+    std::fs::write(&input, build_test_epub()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg("check")
+        .arg(&input)
+        .output()
+        .expect("failed to execute");
+
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[WARN]"));
+    assert!(stdout.contains("encoding"));
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn cli_check_succeeds_without_problems() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("book.epub");
+    std::fs::write(&input, build_test_epub()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .args(["check", "--only", "body_id,stray_img"])
+        .arg(&input)
+        .output()
+        .expect("failed to execute");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("No issues found"));
+}
+
+#[test]
+fn cli_prints_json_report() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("book.epub");
+    std::fs::write(&input, build_test_epub()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg(&input)
+        .args(["--format", "json"])
+        .output()
+        .expect("failed to execute");
+
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let file = &json["files"][0];
+    assert_eq!(file["report"]["format"], "epub");
+    assert_eq!(file["report"]["fixes_applied"][0]["name"], "encoding");
+    let written = file["output_path"].as_str().unwrap();
+    assert!(std::path::Path::new(written).exists());
+    assert_eq!(json["summary"]["processed"], 1);
+}
+
+#[test]
+fn cli_prints_ndjson_line_per_file() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.epub"), build_test_epub()).unwrap();
+    std::fs::write(dir.path().join("b.epub"), b"not an ebook").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg(dir.path().join("a.epub"))
+        .arg(dir.path().join("b.epub"))
+        .args(["--format", "ndjson", "--dry-run"])
+        .output()
+        .expect("failed to execute");
+
+    assert_eq!(output.status.code(), Some(1));
+    let lines: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0]["error"].is_null());
+    assert!(lines[0]["output_path"].is_null());
+    assert!(lines[1]["report"].is_null());
+    assert!(lines[1]["error"].as_str().unwrap().contains("b.epub"));
+}
+
+#[test]
+fn cli_verbose_lists_changes() {
+    let mut file = NamedTempFile::with_suffix(".epub").unwrap();
+    file.write_all(&build_test_epub()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg(file.path())
+        .args(["--dry-run", "--verbose"])
+        .output()
+        .expect("failed to execute");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("OEBPS/chapter1.xhtml:1:1"), "{}", stdout);
+}
+
+#[test]
+fn cli_diff_shows_changes_without_writing() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("book.epub");
+    std::fs::write(&input, build_test_epub()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg(&input)
+        .arg("--diff")
+        .output()
+        .expect("failed to execute");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("+++ b/OEBPS/chapter1.xhtml"), "{}", stdout);
+    assert!(stdout.contains("+<?xml version=\"1.0\" encoding=\"utf-8\"?>"));
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+/// Paths of the files processed in a `--format ndjson` run, relative to `root`.
+fn processed_paths(root: &std::path::Path, args: &[&str]) -> Vec<String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .args(["check", "--format", "ndjson"])
+        .args(args)
+        .arg(root)
+        .output()
+        .expect("failed to execute");
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| {
+            let json: serde_json::Value = serde_json::from_str(line).unwrap();
+            let path = std::path::PathBuf::from(json["path"].as_str().unwrap());
+            path.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/")
+        })
+        .collect()
+}
+
+fn build_library() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let nested = dir.path().join("Author").join("Title (1)");
+    std::fs::create_dir_all(&nested).unwrap();
+    std::fs::write(nested.join("book.epub"), build_test_epub()).unwrap();
+    std::fs::write(nested.join("(fixed) book.epub"), build_test_epub()).unwrap();
+    std::fs::write(nested.join("cover.jpg"), b"jpeg").unwrap();
+    std::fs::write(dir.path().join("top.epub"), build_test_epub()).unwrap();
+    dir
+}
+
+#[test]
+fn cli_walks_directories_recursively() {
+    let library = build_library();
+
+    assert_eq!(processed_paths(library.path(), &[]), ["top.epub"]);
+    assert_eq!(
+        processed_paths(library.path(), &["--recursive"]),
+        ["Author/Title (1)/book.epub", "top.epub"]
+    );
+}
+
+#[test]
+fn cli_filters_with_globs() {
+    let library = build_library();
+
+    assert_eq!(
+        processed_paths(library.path(), &["-r", "--include", "Author/**"]),
+        ["Author/Title (1)/book.epub"]
+    );
+    assert_eq!(
+        processed_paths(library.path(), &["-r", "--exclude", "*/Title*/*"]),
+        ["top.epub"]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn cli_follows_symlinks_only_when_asked() {
+    let library = build_library();
+    std::os::unix::fs::symlink(
+        library.path().join("top.epub"),
+        library.path().join("link.epub"),
+    )
+    .unwrap();
+
+    assert_eq!(processed_paths(library.path(), &[]), ["top.epub"]);
+    assert_eq!(
+        processed_paths(library.path(), &["--follow-symlinks"]),
+        ["link.epub", "top.epub"]
+    );
+}
+
+#[test]
+fn cli_rerun_does_not_fix_fixed_files_again() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("book.epub"), build_test_epub()).unwrap();
+
+    for _ in 0..2 {
+        let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+            .arg(dir.path())
+            .output()
+            .expect("failed to execute");
+        assert!(output.status.success());
+    }
+
+    let mut names: Vec<String> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["(fixed) book.epub", "book.epub"]);
+}
+
+#[test]
+fn cli_keeps_subdirectories_under_output_dir() {
+    let library = build_library();
+    std::fs::write(library.path().join("book.epub"), build_test_epub()).unwrap();
+    let out = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg(library.path())
+        .arg("--recursive")
+        .arg("-o")
+        .arg(out.path())
+        .args(["--jobs", "4"])
+        .output()
+        .expect("failed to execute");
+
+    assert!(output.status.success());
+    assert!(out.path().join("(fixed) book.epub").exists());
+    assert!(out.path().join("(fixed) top.epub").exists());
+    assert!(out
+        .path()
+        .join("Author")
+        .join("Title (1)")
+        .join("(fixed) book.epub")
+        .exists());
+}
+
+#[test]
+fn cli_refuses_to_save_two_books_to_one_path() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["a", "b"] {
+        std::fs::create_dir(dir.path().join(name)).unwrap();
+        std::fs::write(dir.path().join(name).join("book.epub"), build_test_epub()).unwrap();
+    }
+    let out = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg(dir.path().join("a").join("book.epub"))
+        .arg(dir.path().join("b").join("book.epub"))
+        .arg("-o")
+        .arg(out.path())
+        .args(["--format", "json"])
+        .output()
+        .expect("failed to execute");
+
+    assert_eq!(output.status.code(), Some(1));
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(json["files"][0]["error"].is_null());
+    assert!(json["files"][1]["error"]
+        .as_str()
+        .unwrap()
+        .contains("already saved there"));
+    assert_eq!(std::fs::read_dir(out.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn cli_rerun_skips_templated_books_in_output_dir() {
+    let library = build_library();
+    let out = library.path().join("fixed");
+
+    for _ in 0..2 {
+        let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+            .arg(library.path())
+            .arg("--recursive")
+            .arg("-o")
+            .arg(&out)
+            .args(["--name-template", "{title}.{ext}", "--format", "json"])
+            .output()
+            .expect("failed to execute");
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(json["summary"]["processed"], 2);
+    }
+
+    assert!(out.join("Test.epub").exists());
+    assert!(!out.join("fixed").exists());
+}
+
+#[test]
+fn cli_announces_file_before_language_prompt() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("klingon.epub");
+    std::fs::write(&input, build_epub("tlh", b"<html/>")).unwrap();
+    let mut log = tempfile::tempfile().unwrap();
+
+    Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg(&input)
+        .arg("--dry-run")
+        .stdout(log.try_clone().unwrap())
+        .stderr(log.try_clone().unwrap())
+        .status()
+        .expect("failed to execute");
+
+    let mut text = String::new();
+    log.rewind().unwrap();
+    log.read_to_string(&mut text).unwrap();
+    let header = text.find("Processing:").expect("header printed");
+    let warning = text.find("[WARN]").expect("warning printed");
+    assert!(header < warning, "{}", text);
+}
+
+#[test]
+fn cli_parallel_jobs_match_sequential_run() {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..8 {
+        let name = format!("book{}.epub", i);
+        std::fs::write(dir.path().join(name), build_test_epub()).unwrap();
+    }
+    std::fs::write(dir.path().join("broken.epub"), b"not a zip").unwrap();
+
+    let run = |jobs: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+            .arg(dir.path())
+            .args(["--dry-run", "--format", "json", "--jobs", jobs])
+            .output()
+            .expect("failed to execute");
+        assert_eq!(output.status.code(), Some(1));
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+
+    let sequential = run("1");
+    let parallel = run("4");
+    assert_eq!(parallel, sequential);
+    assert_eq!(parallel["summary"]["processed"], 8);
+    assert_eq!(parallel["summary"]["errors"], 1);
+    assert!(parallel["files"][8]["error"].is_string());
+}
+
+#[test]
+fn cli_in_place_replaces_original_and_keeps_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("book.epub");
+    let original = build_test_epub();
+    std::fs::write(&input, &original).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg(&input)
+        .args(["--in-place", "--backup=.orig"])
+        .output()
+        .expect("failed to execute");
+
+    assert!(output.status.success());
+    let fixed = std::fs::read(&input).unwrap();
+    assert_ne!(fixed, original);
+    assert_eq!(std::fs::read(dir.path().join("book.epub.orig")).unwrap(), original);
+
+    let mut names: Vec<String> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["book.epub", "book.epub.orig"]);
+}
+
+#[test]
+fn cli_backup_requires_in_place() {
+    let input = NamedTempFile::with_suffix(".epub").unwrap();
+    std::fs::write(input.path(), build_test_epub()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg(input.path())
+        .arg("--backup")
+        .output()
+        .expect("failed to execute");
+
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn cli_names_output_from_template() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("book.epub");
+    std::fs::write(&input, build_test_epub()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg(&input)
+        .args(["--name-template", "{lang}/{title} - {author}.{ext}"])
+        .output()
+        .expect("failed to execute");
+
+    assert!(output.status.success());
+    assert!(dir.path().join("en").join("Test - Unknown.epub").exists());
+}
+
+#[test]
+fn cli_does_not_write_unchanged_books() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("clean.epub");
+    std::fs::write(&input, build_clean_epub()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg(&input)
+        .args(["--format", "json"])
+        .output()
+        .expect("failed to execute");
+
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["files"][0]["unchanged"], true);
+    assert!(json["files"][0]["output_path"].is_null());
+    assert_eq!(json["summary"]["unchanged"], 1);
+    assert!(!dir.path().join("(fixed) clean.epub").exists());
+}
+
+#[test]
+fn cli_copies_unchanged_books_byte_for_byte() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("clean.epub");
+    let original = build_clean_epub();
+    std::fs::write(&input, &original).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg(&input)
+        .arg("--copy-unchanged")
+        .output()
+        .expect("failed to execute");
+
+    assert!(output.status.success());
+    let copy = std::fs::read(dir.path().join("(fixed) clean.epub")).unwrap();
+    assert_eq!(copy, original);
+}
+
+#[test]
+fn cli_in_place_rerun_with_stamp_is_a_no_op() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("book.epub");
+    std::fs::write(&input, build_test_epub()).unwrap();
+
+    let run = || {
+        Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+            .arg(&input)
+            .args(["--in-place", "--stamp"])
+            .output()
+            .expect("failed to execute")
+    };
+
+    assert!(run().status.success());
+    let fixed = std::fs::read(&input).unwrap();
+
+    let output = run();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Already fixed by version"));
+    assert!(stdout.contains("1 unchanged"));
+    assert_eq!(std::fs::read(&input).unwrap(), fixed);
+}
+
+#[test]
+fn cli_writes_epub_for_cleanly_converted_mobi() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("book.mobi");
+    std::fs::write(&input, build_clean_mobi()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kindle-file-fix"))
+        .arg(&input)
+        .args(["--to-epub", "--format", "json"])
+        .output()
+        .expect("failed to execute");
+
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let file = &json["files"][0];
+    assert!(file["unchanged"].is_null());
+    assert_eq!(file["report"]["fixes_applied"][0]["name"], "convert");
+    let written = std::path::PathBuf::from(file["output_path"].as_str().unwrap());
+    assert_eq!(written.extension().unwrap(), "epub");
+    assert!(std::fs::read(written).unwrap().starts_with(b"PK"));
+}
@@ -5,6 +5,9 @@ pub enum KindleFixError {
     #[error("Invalid EPUB: {0}")]
     InvalidEpub(String),
 
+    #[error("Invalid MOBI: {0}")]
+    InvalidMobi(String),
+
     #[error("ZIP error: {0}")]
     Zip(#[from] zip::result::ZipError),
 
@@ -19,6 +22,9 @@ pub enum KindleFixError {
 
     #[error("Unsupported format: {0}")]
     UnsupportedFormat(String),
+
+    #[error("Invalid name template: {0}")]
+    InvalidTemplate(String),
 }
 
 pub type Result<T> = std::result::Result<T, KindleFixError>;
@@ -1,18 +1,18 @@
 use crate::error::Result;
+use crate::formats::mobi::book::MobiKind;
+use crate::formats::mobi::fix_palmdb_book;
 use crate::formats::FileFixer;
 use crate::types::{FixOptions, FixOutput};
-use crate::KindleFixError;
 
 pub struct Azw3Fixer;
 
 impl FileFixer for Azw3Fixer {
+    /// Matches standalone KF8 books, whose first MOBI header has file version 8.
     fn detect(data: &[u8]) -> bool {
-        data.len() > 68 && &data[60..68] == b"BOOKMOBI"
+        MobiKind::detect(data) == Some(MobiKind::Kf8)
     }
 
-    fn fix(&self, _data: &[u8], _options: &FixOptions) -> Result<FixOutput> {
-        Err(KindleFixError::UnsupportedFormat(
-            "AZW3 support coming soon".into(),
-        ))
+    fn fix(&self, data: &[u8], options: &FixOptions) -> Result<FixOutput> {
+        fix_palmdb_book(data, options)
     }
 }
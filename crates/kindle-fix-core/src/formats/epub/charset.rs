@@ -0,0 +1,117 @@
+//! Charset detection for text files inside EPUBs that are not UTF-8.
+
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use regex::bytes::Regex;
+
+/// How far into a file charset declarations are looked for.
+const DECLARATION_SCAN_LEN: usize = 1024;
+
+/// Detect the charset of a text file. In order of precedence: a byte order
+/// mark, the charset named by an XML declaration, `<meta>` tag or CSS
+/// `@charset` rule, valid UTF-8, UTF-8 with a few malformed sequences, and
+/// finally a statistical guess. Non-ASCII text that is valid UTF-8 is taken
+/// as UTF-8 even when it declares a single-byte charset.
+pub fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+    detect(bytes).0
+}
+
+/// How the charset of a text file was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Bom,
+    Declared,
+    Utf8,
+    MostlyUtf8,
+    Guess,
+}
+
+fn detect(bytes: &[u8]) -> (&'static Encoding, Source) {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return (encoding, Source::Bom);
+    }
+
+    // A declaration readable as ASCII cannot be UTF-16, whatever it says.
+    let declared =
+        declared_charset(bytes).filter(|encoding| *encoding != UTF_16LE && *encoding != UTF_16BE);
+    let is_utf8 = std::str::from_utf8(bytes).is_ok();
+    match declared {
+        Some(encoding) if encoding.is_single_byte() && is_utf8 && !bytes.is_ascii() => {
+            return (UTF_8, Source::Utf8)
+        }
+        Some(encoding) if encoding != UTF_8 => return (encoding, Source::Declared),
+        _ if is_utf8 => return (UTF_8, Source::Utf8),
+        _ if is_mostly_utf8(bytes) => return (UTF_8, Source::MostlyUtf8),
+        _ => {}
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    (detector.guess(None, true), Source::Guess)
+}
+
+/// Whether `bytes` are UTF-8 apart from a few malformed sequences, that is
+/// fewer of them than well-formed non-ASCII characters.
+fn is_mostly_utf8(bytes: &[u8]) -> bool {
+    let mut non_ascii = 0;
+    let mut malformed = 0;
+    for chunk in bytes.utf8_chunks() {
+        non_ascii += chunk.valid().chars().filter(|c| !c.is_ascii()).count();
+        malformed += usize::from(!chunk.invalid().is_empty());
+    }
+    malformed < non_ascii
+}
+
+/// Decode a text file to UTF-8, returning the charset it was stored in.
+/// Malformed sequences are replaced with U+FFFD.
+pub fn decode_text(bytes: &[u8]) -> (String, &'static Encoding) {
+    let encoding = detect_encoding(bytes);
+    let (text, _, _) = encoding.decode(bytes);
+    (text.into_owned(), encoding)
+}
+
+/// A text file decoded to UTF-8 by `try_decode_text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    pub text: String,
+    /// The charset the file was stored in.
+    pub encoding: &'static Encoding,
+    /// The charset was guessed rather than found from a byte order mark, a
+    /// declaration or valid UTF-8, so the text may be garbled.
+    pub guessed: bool,
+}
+
+/// Decode a text file to UTF-8 like `decode_text`, but return `None` if it
+/// contains sequences that are malformed in its detected charset. Files that
+/// are UTF-8 apart from a few malformed sequences are still decoded.
+pub fn try_decode_text(bytes: &[u8]) -> Option<Decoded> {
+    let (encoding, source) = detect(bytes);
+    let (text, _, had_errors) = encoding.decode(bytes);
+    (!had_errors || source == Source::MostlyUtf8).then(|| Decoded {
+        text: text.into_owned(),
+        encoding,
+        guessed: matches!(source, Source::MostlyUtf8 | Source::Guess),
+    })
+}
+
+/// The charset named at the start of a file by an XML declaration, a `<meta>`
+/// tag or a CSS `@charset` rule, if it is one `encoding_rs` knows.
+pub fn declared_charset(bytes: &[u8]) -> Option<&'static Encoding> {
+    let head = &bytes[..bytes.len().min(DECLARATION_SCAN_LEN)];
+    let patterns = [
+        r#"^\s*<\?xml[^>]*?\bencoding\s*=\s*["']([A-Za-z0-9._:-]+)["']"#,
+        r#"(?i)<meta\b[^>]*?\bcharset\s*=\s*["']?([A-Za-z0-9._:-]+)"#,
+        r#"^@charset\s+["']([A-Za-z0-9._:-]+)["']"#,
+    ];
+
+    patterns.iter().find_map(|pattern| {
+        let regex = Regex::new(pattern).expect("charset regex is valid");
+        let label = regex.captures(head)?.get(1)?.as_bytes();
+        Encoding::for_label(label)
+    })
+}
+
+/// Whether a charset label names UTF-8.
+pub fn is_utf8_label(label: &str) -> bool {
+    Encoding::for_label(label.as_bytes()) == Some(UTF_8)
+}
@@ -0,0 +1,42 @@
+//! Unified diffs between the original and fixed text files of an EPUB.
+
+use std::collections::HashMap;
+
+use similar::TextDiff;
+
+use crate::types::FileDiff;
+
+/// Lines of unchanged context around each hunk.
+const CONTEXT_LINES: usize = 3;
+
+/// Diff every file whose text differs between `before` and `after`, in name
+/// order. Files missing from one side are diffed against empty text.
+pub fn diff_files(
+    before: &HashMap<String, String>,
+    after: &HashMap<String, String>,
+) -> Vec<FileDiff> {
+    let mut names: Vec<&String> = before.keys().chain(after.keys()).collect();
+    names.sort_unstable();
+    names.dedup();
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let old = before.get(name).map_or("", String::as_str);
+            let new = after.get(name).map_or("", String::as_str);
+            (old != new).then(|| FileDiff {
+                file: name.clone(),
+                diff: unified_diff(name, old, new),
+            })
+        })
+        .collect()
+}
+
+/// Unified diff of one file, with `a/` and `b/` prefixed headers like `git diff`.
+pub fn unified_diff(name: &str, old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .header(&format!("a/{}", name), &format!("b/{}", name))
+        .to_string()
+}
@@ -1,55 +1,116 @@
 use std::collections::HashMap;
 
-use regex::Regex;
+use quick_xml::events::Event;
+use quick_xml::Reader;
 
-use super::is_html_file;
+use super::links::{decode, encode, find_links, has_links, resolve};
+use super::{is_html_file, EpubContents, Fix};
+use crate::types::{Change, DiagnosticCode, FixDescription, FixOptions, FixReport};
 
 /// Fix body ID link references that Kindle rejects as unresolved hyperlinks.
-pub fn fix_body_id_links(files: &mut HashMap<String, String>) -> Vec<String> {
-    let body_id_regex =
-        Regex::new(r#"<body\b[^>]*\bid\s*=\s*["']([^"']+)["'][^>]*>"#).expect("valid regex");
+///
+/// Every `href`/`src` in the XHTML documents, NCX and OPF is resolved
+/// against the document it is in; only links whose fragment is the `id` of
+/// the target's `<body>` lose the fragment. Returns every link rewritten.
+pub fn fix_body_id_links(files: &mut HashMap<String, String>) -> Vec<Change> {
+    let body_ids: HashMap<String, String> = files
+        .iter()
+        .filter(|(name, _)| is_html_file(name))
+        .filter_map(|(name, content)| Some((name.clone(), body_id(content)?)))
+        .collect();
 
-    let mut body_id_map: Vec<(String, String)> = Vec::new();
+    if body_ids.is_empty() {
+        return Vec::new();
+    }
 
-    let filenames: Vec<String> = files.keys().cloned().collect();
+    let mut filenames: Vec<String> = files.keys().filter(|n| has_links(n)).cloned().collect();
+    filenames.sort();
+
+    let mut fixes = Vec::new();
     for filename in &filenames {
-        if !is_html_file(filename) {
-            continue;
+        let content = &files[filename];
+        let mut modified = String::with_capacity(content.len());
+        let mut last = 0;
+
+        for link in find_links(content) {
+            let (path, Some(fragment)) = link.split() else {
+                continue;
+            };
+            let target = resolve(filename, path);
+            let is_body = target
+                .and_then(|target| body_ids.get(&target))
+                .is_some_and(|id| *id == decode(fragment));
+            if !is_body {
+                continue;
+            }
+
+            // A bare "#id" points at the document itself
+            let replacement = if path.is_empty() {
+                encode(filename.rsplit('/').next().unwrap_or(filename))
+            } else {
+                path.to_string()
+            };
+            modified.push_str(&content[last..link.offset]);
+            modified.push_str(&replacement);
+            last = link.offset + link.value.len();
+            fixes.push(
+                Change::new(filename.as_str(), link.value, replacement)
+                    .at_offset(content, link.offset),
+            );
         }
 
-        let content = &files[filename];
-        if let Some(caps) = body_id_regex.captures(content) {
-            let body_id = &caps[1];
-            let basename = filename.rsplit('/').next().unwrap_or(filename);
-            let src = format!("{}#{}", basename, body_id);
-            body_id_map.push((src, basename.to_string()));
+        if last > 0 {
+            modified.push_str(&content[last..]);
+            files.insert(filename.clone(), modified);
         }
     }
 
-    if body_id_map.is_empty() {
-        return Vec::new();
-    }
+    fixes
+}
 
-    let mut fixes = Vec::new();
+/// The `id` of the `<body>` element of an XHTML document.
+fn body_id(content: &str) -> Option<String> {
+    let mut reader = Reader::from_str(content);
+    reader.config_mut().check_end_names = false;
 
-    for filename in &filenames {
-        let content = files.get(filename).unwrap().clone();
-        let mut modified = content.clone();
-
-        for (src, target) in &body_id_map {
-            if modified.contains(src.as_str()) {
-                modified = modified.replace(src.as_str(), target.as_str());
-                fixes.push(format!(
-                    "Replaced link target {} with {} in {}",
-                    src, target, filename
-                ));
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e))
+                if e.local_name().as_ref().eq_ignore_ascii_case(b"body") =>
+            {
+                let id = e.try_get_attribute("id").ok().flatten()?;
+                let id = id.unescape_value().ok()?;
+                let id = id.trim();
+                return (!id.is_empty()).then(|| id.to_string());
             }
+            Ok(Event::Eof) | Err(_) => return None,
+            _ => {}
         }
+    }
+}
 
-        if modified != content {
-            files.insert(filename.clone(), modified);
-        }
+pub struct BodyIdFix;
+
+impl Fix for BodyIdFix {
+    fn id(&self) -> &'static str {
+        "body_id"
     }
 
-    fixes
+    fn description(&self) -> &'static str {
+        "Remove links to <body> element IDs, which Kindle rejects"
+    }
+
+    fn code(&self) -> DiagnosticCode {
+        DiagnosticCode::BodyIdLink
+    }
+
+    fn apply(&self, contents: &mut EpubContents, _options: &FixOptions, report: &mut FixReport) {
+        let fixed = fix_body_id_links(&mut contents.text_files);
+        if !fixed.is_empty() {
+            let details = format!("Removed {} body ID link reference(s)", fixed.len());
+            report
+                .fixes_applied
+                .push(FixDescription::from_changes(self.id(), details, fixed));
+        }
+    }
 }
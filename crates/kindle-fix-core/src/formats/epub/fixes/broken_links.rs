@@ -0,0 +1,357 @@
+use std::collections::{HashMap, HashSet};
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use regex::Regex;
+
+use super::links::{decode, encode, find_links, relative, resolve, Link};
+use super::{is_html_file, EpubContents, Fix};
+use crate::types::{Change, Diagnostic, DiagnosticCode, FixDescription, FixOptions, FixReport};
+
+/// The link targets of an XHTML document.
+#[derive(Debug, Default)]
+struct Targets {
+    /// Every `id`, and the `name` of every `<a>`.
+    ids: HashSet<String>,
+    /// The `id` of the `<body>`.
+    body: Option<String>,
+}
+
+/// The patterns `unwrap` matches tags with, compiled once per run.
+struct UnwrapPatterns {
+    /// An `id` or `name` attribute, which makes an anchor a link target.
+    target: Regex,
+    /// The name of the `href` attribute, up to its value.
+    href: Regex,
+    /// The end tag of an anchor.
+    close: Regex,
+}
+
+impl UnwrapPatterns {
+    fn new() -> Self {
+        Self {
+            target: Regex::new(r#"(?i)\s(?:id|name)\s*="#).expect("valid regex"),
+            href: Regex::new(r#"(?i)\s+(?:[\w-]+:)?href\s*=\s*["']?$"#).expect("valid regex"),
+            close: Regex::new(r"(?i)</a\s*>").expect("valid regex"),
+        }
+    }
+}
+
+/// Where a broken link should point instead.
+struct Repair {
+    file: String,
+    fragment: Option<String>,
+}
+
+/// Repair or remove the `<a href>` links of the XHTML documents that point at
+/// a file or fragment ID that does not exist.
+///
+/// The ids of every XHTML document are indexed first. A link is repaired
+/// when exactly one target fits: the file differs only in case or lives in
+/// another directory, or the fragment differs only in case or is defined in
+/// exactly one other document. A fragment that turns out to be a `<body>`
+/// ID is dropped. Links with no such match are unwrapped, keeping their
+/// text, and reported as warnings.
+///
+/// `files` is every file of the book that can be linked to, text or not.
+pub fn fix_broken_links(
+    text_files: &mut HashMap<String, String>,
+    files: &HashSet<String>,
+) -> (Vec<Change>, Vec<Diagnostic>) {
+    let targets: HashMap<String, Targets> = text_files
+        .iter()
+        .filter(|(name, _)| is_html_file(name))
+        .map(|(name, content)| (name.clone(), targets(content)))
+        .collect();
+
+    let mut documents: Vec<String> = targets.keys().cloned().collect();
+    documents.sort();
+
+    let patterns = UnwrapPatterns::new();
+    let mut changes = Vec::new();
+    let mut warnings = Vec::new();
+    for document in &documents {
+        let content = &text_files[document];
+        let mut edits: Vec<(usize, usize, String)> = Vec::new();
+
+        for link in find_links(content) {
+            if !link.element.eq_ignore_ascii_case("a") {
+                continue;
+            }
+            let (path, fragment) = link.split();
+            let Some(target) = resolve(document, path) else {
+                continue;
+            };
+            let fragment = fragment.map(decode).filter(|f| !f.is_empty());
+            if is_valid(&target, fragment.as_deref(), files, &targets) {
+                continue;
+            }
+
+            match repair(&target, fragment.as_deref(), files, &targets) {
+                Some(repair) => {
+                    let replacement = relative_href(document, path, &target, &repair);
+                    changes.push(
+                        Change::new(document.as_str(), link.value, replacement.as_str())
+                            .at_offset(content, link.offset),
+                    );
+                    edits.push((link.offset, link.offset + link.value.len(), replacement));
+                }
+                None => {
+                    let unwrapped = unwrap(content, &link, &patterns);
+                    let outcome = if unwrapped.is_empty() {
+                        "left it as it was"
+                    } else {
+                        "removed it"
+                    };
+                    warnings.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::BrokenLink,
+                            format!("Link to '{}' points at nothing; {}", link.value, outcome),
+                        )
+                        .with_file(document.as_str())
+                        .at_offset(content, link.offset),
+                    );
+                    for (start, end, replacement) in unwrapped {
+                        changes.push(
+                            Change::new(document.as_str(), &content[start..end], "")
+                                .at_offset(content, start),
+                        );
+                        edits.push((start, end, replacement));
+                    }
+                }
+            }
+        }
+
+        if edits.is_empty() {
+            continue;
+        }
+        edits.sort_by_key(|(start, _, _)| *start);
+        let mut modified = String::with_capacity(content.len());
+        let mut last = 0;
+        for (start, end, replacement) in edits {
+            if start < last {
+                continue;
+            }
+            modified.push_str(&content[last..start]);
+            modified.push_str(&replacement);
+            last = end;
+        }
+        modified.push_str(&content[last..]);
+        text_files.insert(document.clone(), modified);
+    }
+
+    (changes, warnings)
+}
+
+/// Whether a link resolving to `target` and `fragment` points at something.
+/// Fragments are only checked in XHTML documents.
+fn is_valid(
+    target: &str,
+    fragment: Option<&str>,
+    files: &HashSet<String>,
+    targets: &HashMap<String, Targets>,
+) -> bool {
+    if !files.contains(target) {
+        return false;
+    }
+    match (fragment, targets.get(target)) {
+        (Some(fragment), Some(document)) => document.ids.contains(fragment),
+        _ => true,
+    }
+}
+
+/// The unique target a broken link most likely meant.
+fn repair(
+    target: &str,
+    fragment: Option<&str>,
+    files: &HashSet<String>,
+    targets: &HashMap<String, Targets>,
+) -> Option<Repair> {
+    let file = find_file(target, files);
+
+    let Some(fragment) = fragment else {
+        return file.map(|file| Repair {
+            file,
+            fragment: None,
+        });
+    };
+
+    let in_file = file.as_ref().and_then(|file| {
+        let document = targets.get(file)?;
+        if document.ids.contains(fragment) {
+            return Some(fragment.to_string());
+        }
+        unique(
+            document
+                .ids
+                .iter()
+                .filter(|id| id.eq_ignore_ascii_case(fragment)),
+        )
+        .cloned()
+    });
+    let (file, fragment) = match (file, in_file) {
+        (Some(file), Some(fragment)) => (file, fragment),
+        // The fragment is defined somewhere else
+        _ => {
+            let owners = |matches: &dyn Fn(&String) -> bool| {
+                let found: Vec<(&String, &String)> = targets
+                    .iter()
+                    .flat_map(|(file, document)| document.ids.iter().map(move |id| (file, id)))
+                    .filter(|(_, id)| matches(id))
+                    .collect();
+                unique(found.into_iter())
+            };
+            let (file, id) = owners(&|id| id == fragment)
+                .or_else(|| owners(&|id| id.eq_ignore_ascii_case(fragment)))?;
+            (file.clone(), id.clone())
+        }
+    };
+
+    let is_body = targets[&file].body.as_deref() == Some(fragment.as_str());
+    Some(Repair {
+        file,
+        fragment: (!is_body).then_some(fragment),
+    })
+}
+
+/// The file a missing `target` most likely is: the only one with the same
+/// path in another case, or else the only one with the same name.
+fn find_file(target: &str, files: &HashSet<String>) -> Option<String> {
+    if files.contains(target) {
+        return Some(target.to_string());
+    }
+    let name = |path: &str| path.rsplit('/').next().unwrap_or(path).to_lowercase();
+    unique(
+        files
+            .iter()
+            .filter(|file| file.eq_ignore_ascii_case(target)),
+    )
+    .or_else(|| unique(files.iter().filter(|file| name(file) == name(target))))
+    .cloned()
+}
+
+/// The only item of `items`, if there is exactly one.
+fn unique<T>(mut items: impl Iterator<Item = T>) -> Option<T> {
+    let first = items.next()?;
+    items.next().is_none().then_some(first)
+}
+
+/// The `href` for `repair` as seen from `document`. The path is kept as
+/// written when it already resolved to the right file.
+fn relative_href(document: &str, path: &str, target: &str, repair: &Repair) -> String {
+    let mut href = if repair.file == target {
+        path.to_string()
+    } else {
+        relative(document, &repair.file)
+    };
+    if let Some(fragment) = &repair.fragment {
+        href.push('#');
+        href.push_str(&encode(fragment));
+    }
+    href
+}
+
+/// The edits that remove a broken link: its `<a>` start tag and the next
+/// `</a>`, or only the `href` attribute when the anchor is itself a target.
+/// Returns no edits when that attribute cannot be found.
+fn unwrap(content: &str, link: &Link, patterns: &UnwrapPatterns) -> Vec<(usize, usize, String)> {
+    let tag = &content[link.tag.clone()];
+
+    if patterns.target.is_match(tag) {
+        let before = &content[link.tag.start..link.offset];
+        let Some(name) = patterns.href.find(before) else {
+            return Vec::new();
+        };
+        let mut end = link.offset + link.value.len();
+        if content[end..].starts_with(['"', '\'']) {
+            end += 1;
+        }
+        return vec![(link.tag.start + name.start(), end, String::new())];
+    }
+
+    let mut edits = vec![(link.tag.start, link.tag.end, String::new())];
+    if !tag.ends_with("/>") {
+        if let Some(close) = patterns.close.find(&content[link.tag.end..]) {
+            let start = link.tag.end + close.start();
+            edits.push((start, link.tag.end + close.end(), String::new()));
+        }
+    }
+    edits
+}
+
+/// Index the ids of an XHTML document.
+fn targets(content: &str) -> Targets {
+    let mut reader = Reader::from_str(content);
+    let config = reader.config_mut();
+    config.check_end_names = false;
+    config.allow_unmatched_ends = true;
+
+    let mut targets = Targets::default();
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e)) => {
+                let local = e.local_name();
+                let element = local.as_ref();
+                for attr in e.attributes().flatten() {
+                    let key = attr.key.local_name();
+                    let key = key.as_ref();
+                    let is_target =
+                        key == b"id" || (key == b"name" && element.eq_ignore_ascii_case(b"a"));
+                    if !is_target {
+                        continue;
+                    }
+                    let Ok(value) = attr.unescape_value() else {
+                        continue;
+                    };
+                    let value = value.trim().to_string();
+                    if key == b"id" && element.eq_ignore_ascii_case(b"body") {
+                        targets.body = Some(value.clone());
+                    }
+                    targets.ids.insert(value);
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+    targets
+}
+
+pub struct BrokenLinksFix;
+
+impl Fix for BrokenLinksFix {
+    fn id(&self) -> &'static str {
+        "broken_links"
+    }
+
+    fn description(&self) -> &'static str {
+        "Repair or remove links to missing files and fragment IDs"
+    }
+
+    fn code(&self) -> DiagnosticCode {
+        DiagnosticCode::BrokenLink
+    }
+
+    fn apply(&self, contents: &mut EpubContents, _options: &FixOptions, report: &mut FixReport) {
+        let files: HashSet<String> = contents
+            .text_files
+            .keys()
+            .chain(contents.binary_files.keys())
+            .cloned()
+            .collect();
+        let (changes, warnings) = fix_broken_links(&mut contents.text_files, &files);
+        if !changes.is_empty() {
+            let removed = warnings.len();
+            let repaired =
+                changes.len() - changes.iter().filter(|c| c.replacement.is_empty()).count();
+            let details = format!(
+                "Repaired {} and removed {} broken link(s)",
+                repaired, removed
+            );
+            report
+                .fixes_applied
+                .push(FixDescription::from_changes(self.id(), details, changes));
+        }
+        report.warnings.extend(warnings);
+    }
+}
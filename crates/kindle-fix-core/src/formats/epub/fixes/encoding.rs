@@ -1,36 +1,164 @@
 use std::collections::HashMap;
 
-use regex::Regex;
+use regex::{Captures, Regex};
 
-use super::is_html_file;
+use super::{is_html_file, EpubContents, Fix};
+use crate::formats::epub::charset::is_utf8_label;
+use crate::types::{Change, DiagnosticCode, FixDescription, FixOptions, FixReport, Severity};
 
 const ENCODING_DECLARATION: &str = r#"<?xml version="1.0" encoding="utf-8"?>"#;
 
-/// Fix missing UTF-8 encoding declarations in HTML/XHTML files.
-/// Returns a list of filenames that were fixed.
-pub fn fix_encoding(files: &mut HashMap<String, String>) -> Vec<String> {
-    let regex = Regex::new(
-        r#"^<\?xml\s+version=["'][\d.]+["']\s+encoding=["'][a-zA-Z\d\-.]+["'].*?\?>"#,
-    )
-    .expect("encoding regex is valid");
+/// Fix UTF-8 encoding declarations. HTML/XHTML files missing one get an XML
+/// declaration, and declarations naming another charset are rewritten to
+/// UTF-8, which is what every text file is written as.
+/// Returns every declaration that was added or rewritten.
+pub fn fix_encoding(files: &mut HashMap<String, String>) -> Vec<Change> {
+    let xml_declaration =
+        Regex::new(r#"^<\?xml\s+version=["'][\d.]+["'](\s+encoding=["']([a-zA-Z\d\-._:]+)["'])?"#)
+            .expect("encoding regex is valid");
+    let meta_charset = Regex::new(r#"(?i)(<meta\b[^>]*?\bcharset\s*=\s*["']?)([a-z\d\-._:]+)"#)
+        .expect("meta charset regex is valid");
+    let css_charset = Regex::new(r#"^@charset\s+(["'])([a-zA-Z\d\-._:]+)["']"#)
+        .expect("css charset regex is valid");
 
     let mut fixed = Vec::new();
 
-    let filenames: Vec<String> = files.keys().cloned().collect();
+    let mut filenames: Vec<String> = files.keys().cloned().collect();
+    filenames.sort_unstable();
     for filename in filenames {
-        if !is_html_file(&filename) {
-            continue;
+        let is_html = is_html_file(&filename);
+        let content = &files[&filename];
+        let mut changes = Vec::new();
+        let mut new_content = None;
+
+        // <meta> tags come after the XML declaration, so rewriting them first
+        // leaves the declaration where it was in the original file.
+        if is_html {
+            let rewritten = meta_charset.replace_all(content, |caps: &Captures| {
+                if is_utf8_label(&caps[2]) {
+                    caps[0].to_string()
+                } else {
+                    let replacement = format!("{}utf-8", &caps[1]);
+                    let start = caps.get(0).map_or(0, |m| m.start());
+                    changes.push(
+                        Change::new(filename.as_str(), &caps[0], replacement.as_str())
+                            .at_offset(content, start),
+                    );
+                    replacement
+                }
+            });
+            if rewritten != content.as_str() {
+                new_content = Some(rewritten.into_owned());
+            }
         }
 
-        let content = &files[&filename];
-        let trimmed = content.trim_start();
+        if is_html || is_xml_file(&filename) {
+            let current = new_content.as_deref().unwrap_or(content);
+            let trimmed = current.trim_start();
+            let lead = current.len() - trimmed.len();
+            match xml_declaration.captures(trimmed) {
+                Some(caps) => match caps.get(2) {
+                    Some(label) if !is_utf8_label(label.as_str()) => {
+                        let declaration = caps.get(0).map_or("", |m| m.as_str());
+                        let replacement = format!(
+                            "{}utf-8{}",
+                            &trimmed[..label.start()],
+                            &trimmed[label.end()..declaration.len()]
+                        );
+                        changes.push(
+                            Change::new(filename.as_str(), declaration, replacement.as_str())
+                                .at_offset(content, lead),
+                        );
+                        new_content = Some(format!(
+                            "{}utf-8{}",
+                            &trimmed[..label.start()],
+                            &trimmed[label.end()..]
+                        ));
+                    }
+                    Some(_) => {}
+                    // XML defaults to UTF-8, so only HTML needs an explicit encoding
+                    None if !is_html => {}
+                    None => {
+                        let version_end = caps.get(0).map_or(0, |m| m.end());
+                        changes.push(
+                            Change::new(filename.as_str(), "", r#" encoding="utf-8""#)
+                                .at_offset(content, lead + version_end),
+                        );
+                        new_content = Some(format!(
+                            r#"{} encoding="utf-8"{}"#,
+                            &trimmed[..version_end],
+                            &trimmed[version_end..]
+                        ));
+                    }
+                },
+                None if is_html => {
+                    changes.push(
+                        Change::new(filename.as_str(), "", ENCODING_DECLARATION)
+                            .at_offset(content, 0),
+                    );
+                    new_content = Some(format!("{}\n{}", ENCODING_DECLARATION, trimmed));
+                }
+                None => {}
+            }
+        }
+
+        if filename.to_lowercase().ends_with(".css") {
+            if let Some(caps) = css_charset.captures(content) {
+                if !is_utf8_label(&caps[2]) {
+                    let quote = &caps[1];
+                    let rule = format!("@charset {}utf-8{}", quote, quote);
+                    changes.push(
+                        Change::new(filename.as_str(), &caps[0], rule.as_str())
+                            .at_offset(content, 0),
+                    );
+                    new_content = Some(css_charset.replace(content, rule.as_str()).into_owned());
+                }
+            }
+        }
 
-        if !regex.is_match(trimmed) {
-            let new_content = format!("{}\n{}", ENCODING_DECLARATION, trimmed);
-            files.insert(filename.clone(), new_content);
-            fixed.push(filename);
+        if let Some(new_content) = new_content {
+            files.insert(filename, new_content);
+            fixed.append(&mut changes);
         }
     }
 
     fixed
 }
+
+/// Check if a filename is an XML document other than HTML/XHTML.
+fn is_xml_file(filename: &str) -> bool {
+    let ext = filename.rsplit('.').next().unwrap_or("").to_lowercase();
+    matches!(ext.as_str(), "opf" | "ncx" | "xml" | "svg")
+}
+
+pub struct EncodingFix;
+
+impl Fix for EncodingFix {
+    fn id(&self) -> &'static str {
+        "encoding"
+    }
+
+    fn description(&self) -> &'static str {
+        "Add or correct UTF-8 encoding declarations"
+    }
+
+    fn code(&self) -> DiagnosticCode {
+        DiagnosticCode::EncodingDeclaration
+    }
+
+    fn severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    fn apply(&self, contents: &mut EpubContents, _options: &FixOptions, report: &mut FixReport) {
+        let fixed = fix_encoding(&mut contents.text_files);
+        if !fixed.is_empty() {
+            let mut description = FixDescription::from_changes(self.id(), String::new(), fixed);
+            description.details = format!(
+                "Fixed UTF-8 encoding declaration in {} file(s)",
+                description.files_affected
+            );
+            report.fixes_applied.push(description);
+        }
+    }
+}
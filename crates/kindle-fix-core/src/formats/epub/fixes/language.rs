@@ -1,7 +1,12 @@
 use std::collections::HashMap;
+use std::sync::LazyLock;
 
-use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
-use quick_xml::{Reader, Writer};
+use regex::Regex;
+
+use super::links::resolve;
+use super::{is_html_file, EpubContents, Fix};
+use crate::formats::epub::package::{Container, Metadata, Package};
+use crate::types::{Change, Diagnostic, DiagnosticCode, FixDescription, FixOptions, FixReport};
 
 pub const SUPPORTED_LANGUAGES: &[&str] = &[
     // ISO 639-1
@@ -15,6 +20,15 @@ pub const SUPPORTED_LANGUAGES: &[&str] = &[
     "spa", "swe", "tam", "cym", "wel",
 ];
 
+/// The `<html>` start tag of an XHTML document.
+static HTML_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)<html\b[^>]*>").expect("valid regex"));
+
+/// A `lang` or `xml:lang` attribute and its value.
+static LANG_ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\s(?:xml:)?lang\s*=\s*(?:"([^"]*)"|'([^']*)')"#).expect("valid regex")
+});
+
 #[derive(Debug)]
 pub enum LanguageFixResult {
     Valid(String),
@@ -28,197 +42,366 @@ fn simplify_language(lang: &str) -> String {
     lang.split('-').next().unwrap_or(lang).to_lowercase()
 }
 
-fn is_supported(lang: &str) -> bool {
+pub(crate) fn is_supported(lang: &str) -> bool {
     let simplified = simplify_language(lang);
     SUPPORTED_LANGUAGES.contains(&simplified.as_str())
 }
 
-fn find_opf_path(container_xml: &str) -> Option<String> {
-    let mut reader = Reader::from_str(container_xml);
-    loop {
-        match reader.read_event() {
-            Ok(Event::Empty(ref e)) | Ok(Event::Start(ref e))
-                if e.name().as_ref() == b"rootfile" =>
-            {
-                let mut media_type = None;
-                let mut full_path = None;
-                for attr in e.attributes().flatten() {
-                    match attr.key.as_ref() {
-                        b"media-type" => {
-                            media_type =
-                                Some(String::from_utf8_lossy(&attr.value).to_string());
-                        }
-                        b"full-path" => {
-                            full_path =
-                                Some(String::from_utf8_lossy(&attr.value).to_string());
-                        }
-                        _ => {}
-                    }
-                }
-                if media_type.as_deref() == Some("application/oebps-package+xml") {
-                    return full_path;
-                }
-            }
-            Ok(Event::Eof) => break,
-            Err(_) => break,
-            _ => {}
+/// The outcome of the language fix for one package document.
+#[derive(Debug)]
+pub struct PackageLanguage {
+    /// Path of the package document, `None` when the container names none.
+    pub opf_path: Option<String>,
+    pub result: LanguageFixResult,
+    /// Edits beyond the primary language: empty and repeated `dc:language`
+    /// entries removed, and `xml:lang`/`lang` attributes of the package and
+    /// its XHTML documents brought in line with the primary language.
+    pub changes: Vec<Change>,
+}
+
+impl PackageLanguage {
+    fn error(opf_path: Option<String>, message: impl Into<String>) -> Self {
+        Self {
+            opf_path,
+            result: LanguageFixResult::Error(message.into()),
+            changes: Vec::new(),
         }
     }
-    None
 }
 
-fn extract_language(opf: &str) -> Option<String> {
-    let mut reader = Reader::from_str(opf);
-    let mut in_language = false;
-
-    loop {
-        match reader.read_event() {
-            Ok(Event::Start(ref e)) => {
-                let name = e.name();
-                let local = name.as_ref();
-                if local == b"dc:language"
-                    || local.ends_with(b":language")
-                    || local == b"language"
-                {
-                    in_language = true;
-                }
+/// Fix the language of every package document and return the result for
+/// the first, the one reading systems open.
+pub fn fix_language(
+    files: &mut HashMap<String, String>,
+    language_override: Option<String>,
+) -> LanguageFixResult {
+    fix_languages(files, language_override)
+        .into_iter()
+        .next()
+        .map_or_else(
+            || LanguageFixResult::Error("No package document was checked".into()),
+            |package| package.result,
+        )
+}
+
+/// Fix the language of each package document listed in the container.
+///
+/// The first non-empty `dc:language` is the primary language. A missing one
+/// is added (the override, or `en`); an unsupported one is replaced by the
+/// override, or else by the first supported secondary language, which is
+/// moved to the front.
+pub fn fix_languages(
+    files: &mut HashMap<String, String>,
+    language_override: Option<String>,
+) -> Vec<PackageLanguage> {
+    let Some(container) = files.get("META-INF/container.xml") else {
+        return vec![PackageLanguage::error(
+            None,
+            "Missing META-INF/container.xml",
+        )];
+    };
+    let paths: Vec<String> = Container::parse(container)
+        .map(|container| container.package_paths().map(str::to_string).collect())
+        .unwrap_or_default();
+    if paths.is_empty() {
+        return vec![PackageLanguage::error(
+            None,
+            "Could not find OPF file path in container.xml",
+        )];
+    }
+
+    paths
+        .into_iter()
+        .map(|path| fix_package_language(files, path, language_override.as_deref()))
+        .collect()
+}
+
+fn fix_package_language(
+    files: &mut HashMap<String, String>,
+    opf_path: String,
+    language_override: Option<&str>,
+) -> PackageLanguage {
+    let Some(opf) = files.get(&opf_path) else {
+        let message = format!("OPF file not found: {}", opf_path);
+        return PackageLanguage::error(Some(opf_path), message);
+    };
+    let mut package = match Package::parse(opf) {
+        Ok(package) => package,
+        Err(e) => return PackageLanguage::error(Some(opf_path), e.to_string()),
+    };
+
+    let mut changes = Vec::new();
+    let result = choose_language(
+        &mut package.metadata,
+        language_override,
+        &opf_path,
+        &mut changes,
+    );
+    let (chosen, replaced) = match &result {
+        LanguageFixResult::Valid(lang) | LanguageFixResult::Added(lang) => (Some(lang), None),
+        LanguageFixResult::Changed { from, to } => (Some(to), Some(from.as_str())),
+        _ => (None, None),
+    };
+
+    if let Some(chosen) = chosen {
+        if let Some(current) = package.attribute("xml:lang") {
+            if !current.eq_ignore_ascii_case(chosen)
+                && (!is_supported(current) || is_replaced(current, replaced))
+            {
+                changes.push(Change::new(&opf_path, current, chosen.as_str()));
+                package.set_attribute("xml:lang", chosen.as_str());
             }
-            Ok(Event::Text(ref e)) if in_language => {
-                return Some(e.unescape().unwrap_or_default().trim().to_string());
+        }
+    }
+
+    let edited = !changes.is_empty()
+        || matches!(
+            result,
+            LanguageFixResult::Added(_) | LanguageFixResult::Changed { .. }
+        );
+    if edited {
+        files.insert(opf_path.clone(), package.to_xml());
+    }
+
+    if let Some(chosen) = chosen {
+        for item in &package.manifest.items {
+            let Some(path) = resolve(&opf_path, &item.href) else {
+                continue;
+            };
+            if !is_html_file(&path) {
+                continue;
             }
-            Ok(Event::End(_)) if in_language => {
-                in_language = false;
+            if let Some(content) = files.get_mut(&path) {
+                changes.extend(sync_html_language(&path, content, chosen, replaced));
             }
-            Ok(Event::Eof) => break,
-            Err(_) => break,
-            _ => {}
         }
     }
-    None
+
+    PackageLanguage {
+        opf_path: Some(opf_path),
+        result,
+        changes,
+    }
 }
 
-fn replace_language_in_opf(opf: &str, new_lang: &str) -> String {
-    let mut reader = Reader::from_str(opf);
-    let mut writer = Writer::new(Vec::new());
-    let mut in_language = false;
-
-    loop {
-        match reader.read_event() {
-            Ok(Event::Start(ref e)) => {
-                let name = e.name();
-                let local = name.as_ref();
-                if local == b"dc:language"
-                    || local.ends_with(b":language")
-                    || local == b"language"
-                {
-                    in_language = true;
-                }
-                writer.write_event(Event::Start(e.clone())).ok();
-            }
-            Ok(Event::Text(ref _e)) if in_language => {
-                writer
-                    .write_event(Event::Text(BytesText::new(new_lang)))
-                    .ok();
-            }
-            Ok(Event::End(ref e)) if in_language => {
-                in_language = false;
-                writer.write_event(Event::End(e.clone())).ok();
+/// Apply the primary-language rules to the `dc:language` entries of
+/// `metadata`, recording removed entries in `changes`.
+fn choose_language(
+    metadata: &mut Metadata,
+    language_override: Option<&str>,
+    opf_path: &str,
+    changes: &mut Vec<Change>,
+) -> LanguageFixResult {
+    remove_redundant_languages(metadata, opf_path, changes);
+    let languages: Vec<String> = metadata
+        .dc("language")
+        .map(|e| e.text().trim().to_string())
+        .collect();
+
+    let Some(primary) = languages.first().cloned() else {
+        let lang = language_override.unwrap_or("en").to_string();
+        metadata.add_dc("language", &lang);
+        return LanguageFixResult::Added(lang);
+    };
+    if is_supported(&primary) {
+        return LanguageFixResult::Valid(primary);
+    }
+
+    if let Some(lang) = language_override {
+        metadata.dc_mut("language")[0].set_text(lang);
+        // The override may already be listed as a secondary language
+        remove_redundant_languages(metadata, opf_path, changes);
+        return LanguageFixResult::Changed {
+            from: primary,
+            to: lang.to_string(),
+        };
+    }
+
+    match languages.iter().position(|lang| is_supported(lang)) {
+        Some(index) => {
+            let mut entries = metadata.dc_mut("language");
+            for i in (0..index).rev() {
+                let (before, after) = entries.split_at_mut(i + 1);
+                std::mem::swap(&mut *before[i], &mut *after[0]);
             }
-            Ok(Event::Eof) => break,
-            Ok(e) => {
-                writer.write_event(e).ok();
+            LanguageFixResult::Changed {
+                from: primary,
+                to: languages[index].clone(),
             }
-            Err(_) => break,
         }
+        None => LanguageFixResult::Unsupported(primary),
     }
+}
 
-    String::from_utf8(writer.into_inner()).unwrap_or_else(|_| opf.to_string())
+/// Remove empty `dc:language` entries and repeats of an earlier one, which
+/// reading systems that only look at the first entry trip over.
+fn remove_redundant_languages(metadata: &mut Metadata, opf_path: &str, changes: &mut Vec<Change>) {
+    let mut seen: Vec<String> = Vec::new();
+    metadata.retain_dc("language", |element| {
+        let lang = element.text().trim().to_lowercase();
+        let keep = !lang.is_empty() && !seen.contains(&lang);
+        if !keep {
+            let original = format!("<{0}>{1}</{0}>", element.name, element.text());
+            changes.push(Change::new(opf_path, original, ""));
+        }
+        seen.push(lang);
+        keep
+    });
 }
 
-fn add_language_to_opf(opf: &str, lang: &str) -> Option<String> {
-    let mut reader = Reader::from_str(opf);
-    let mut writer = Writer::new(Vec::new());
-    let mut added = false;
-
-    loop {
-        match reader.read_event() {
-            Ok(Event::End(ref e)) if !added && e.name().as_ref() == b"metadata" => {
-                writer
-                    .write_event(Event::Start(BytesStart::new("dc:language")))
-                    .ok();
-                writer
-                    .write_event(Event::Text(BytesText::new(lang)))
-                    .ok();
-                writer
-                    .write_event(Event::End(BytesEnd::new("dc:language")))
-                    .ok();
-                writer.write_event(Event::End(e.clone())).ok();
-                added = true;
-            }
-            Ok(Event::Eof) => break,
-            Ok(e) => {
-                writer.write_event(e).ok();
-            }
-            Err(_) => break,
+fn is_replaced(lang: &str, replaced: Option<&str>) -> bool {
+    let lang = lang.trim();
+    lang.is_empty() || replaced.is_some_and(|replaced| lang.eq_ignore_ascii_case(replaced))
+}
+
+/// Update the `lang` and `xml:lang` attributes of the `<html>` element of an
+/// XHTML document that are empty, name the language that was replaced, or
+/// disagree with each other when one of them is `chosen`.
+fn sync_html_language(
+    path: &str,
+    content: &mut String,
+    chosen: &str,
+    replaced: Option<&str>,
+) -> Vec<Change> {
+    let Some(tag) = HTML_TAG.find(content) else {
+        return Vec::new();
+    };
+    let values: Vec<(usize, &str)> = LANG_ATTRIBUTE
+        .captures_iter(tag.as_str())
+        .filter_map(|caps| caps.get(1).or_else(|| caps.get(2)))
+        .map(|value| (tag.start() + value.start(), value.as_str()))
+        .collect();
+    let agreed = values
+        .iter()
+        .any(|(_, value)| value.eq_ignore_ascii_case(chosen));
+
+    let mut changes = Vec::new();
+    let mut edits = Vec::new();
+    for &(offset, value) in &values {
+        if value.eq_ignore_ascii_case(chosen) || !(is_replaced(value, replaced) || agreed) {
+            continue;
         }
+        changes.push(Change::new(path, value, chosen).at_offset(content, offset));
+        edits.push(offset..offset + value.len());
+    }
+    for range in edits.into_iter().rev() {
+        content.replace_range(range, chosen);
+    }
+    changes
+}
+
+pub struct LanguageFix;
+
+impl Fix for LanguageFix {
+    fn id(&self) -> &'static str {
+        "language"
+    }
+
+    fn description(&self) -> &'static str {
+        "Add missing language metadata and replace languages Kindle does not support"
+    }
+
+    fn code(&self) -> DiagnosticCode {
+        DiagnosticCode::MissingLanguage
     }
 
-    if added {
-        Some(String::from_utf8(writer.into_inner()).unwrap_or_else(|_| opf.to_string()))
-    } else {
-        None
+    fn apply(&self, contents: &mut EpubContents, options: &FixOptions, report: &mut FixReport) {
+        for package in fix_languages(&mut contents.text_files, options.language.clone()) {
+            let files = &contents.text_files;
+            let opf = package
+                .opf_path
+                .as_deref()
+                .and_then(|path| Some((path, files.get(path)?.as_str())));
+            report_language_fix(report, &package.result, opf);
+            if !package.changes.is_empty() {
+                let details = format!(
+                    "Made {} language entries and attributes consistent",
+                    package.changes.len()
+                );
+                report.fixes_applied.push(FixDescription::from_changes(
+                    self.id(),
+                    details,
+                    package.changes,
+                ));
+            }
+        }
     }
 }
 
-pub fn fix_language(
-    files: &mut HashMap<String, String>,
-    language_override: Option<String>,
-) -> LanguageFixResult {
-    let container = match files.get("META-INF/container.xml") {
-        Some(c) => c.clone(),
-        None => return LanguageFixResult::Error("Missing META-INF/container.xml".into()),
-    };
+/// Byte offset of the first language element in an OPF document, preferring
+/// `<dc:language>` over languages in other vocabularies.
+fn language_offset(opf: &str) -> Option<usize> {
+    let dc = Regex::new(r"<(?:dc:)?language\b").expect("language regex is valid");
+    let element = Regex::new(r"<(?:[\w-]+:)?language\b").expect("language regex is valid");
+    dc.find(opf).or_else(|| element.find(opf)).map(|m| m.start())
+}
 
-    let opf_path = match find_opf_path(&container) {
-        Some(p) => p,
-        None => {
-            return LanguageFixResult::Error(
-                "Could not find OPF file path in container.xml".into(),
-            )
+/// Record the outcome of a language check in `report`. Shared with the MOBI
+/// fixer so both formats report language problems the same way. `opf` is the
+/// path and contents of the OPF document, used to locate diagnostics.
+pub(crate) fn report_language_fix(
+    report: &mut FixReport,
+    result: &LanguageFixResult,
+    opf: Option<(&str, &str)>,
+) {
+    let locate = |mut diagnostic: Diagnostic| {
+        if let Some((path, content)) = opf {
+            diagnostic = diagnostic.with_file(path);
+            if let Some(offset) = language_offset(content) {
+                diagnostic = diagnostic.at_offset(content, offset);
+            }
         }
+        diagnostic
     };
 
-    let opf_content = match files.get(&opf_path) {
-        Some(c) => c.clone(),
-        None => return LanguageFixResult::Error(format!("OPF file not found: {}", opf_path)),
+    // MOBI metadata has no file to point at, so only EPUBs list their changes
+    let change = |original: String, replacement: String| {
+        opf.map(|(path, content)| {
+            let change = Change::new(path, original, replacement);
+            match language_offset(content) {
+                Some(offset) => change.at_offset(content, offset),
+                None => change,
+            }
+        })
     };
 
-    let current_language = extract_language(&opf_content);
-
-    match current_language {
-        None => {
-            let lang = language_override.unwrap_or_else(|| "en".to_string());
-            match add_language_to_opf(&opf_content, &lang) {
-                Some(new_opf) => {
-                    files.insert(opf_path, new_opf);
-                    LanguageFixResult::Added(lang)
-                }
-                None => LanguageFixResult::Error("Failed to add language tag to OPF".into()),
-            }
+    match result {
+        LanguageFixResult::Added(lang) => {
+            let element = format!("<dc:language>{}</dc:language>", lang);
+            report.fixes_applied.push(FixDescription {
+                name: LanguageFix.id().to_string(),
+                details: format!("Added missing language tag: {}", lang),
+                files_affected: 1,
+                changes: change(String::new(), element).into_iter().collect(),
+            });
         }
-        Some(lang) => {
-            if is_supported(&lang) {
-                LanguageFixResult::Valid(lang)
-            } else if let Some(override_lang) = language_override {
-                let new_opf = replace_language_in_opf(&opf_content, &override_lang);
-                files.insert(opf_path, new_opf);
-                LanguageFixResult::Changed {
-                    from: lang,
-                    to: override_lang,
-                }
-            } else {
-                LanguageFixResult::Unsupported(lang)
-            }
+        LanguageFixResult::Changed { from, to } => {
+            report.fixes_applied.push(FixDescription {
+                name: LanguageFix.id().to_string(),
+                details: format!("Changed language from {} to {}", from, to),
+                files_affected: 1,
+                changes: change(from.clone(), to.clone()).into_iter().collect(),
+            });
+        }
+        LanguageFixResult::Unsupported(lang) => {
+            let diagnostic = Diagnostic::warning(
+                DiagnosticCode::UnsupportedLanguage,
+                format!("Language '{}' is not supported by Kindle.", lang),
+            )
+            .with_suggestion("Use --language to override.")
+            .with_fix(LanguageFix.id());
+            report.warnings.push(locate(diagnostic));
+        }
+        LanguageFixResult::Error(msg) => {
+            let diagnostic = Diagnostic::warning(
+                DiagnosticCode::LanguageCheckFailed,
+                format!("Language check failed: {}", msg),
+            );
+            report.warnings.push(match opf {
+                Some((path, _)) => diagnostic.with_file(path),
+                None => diagnostic,
+            });
         }
+        LanguageFixResult::Valid(_) => {}
     }
 }
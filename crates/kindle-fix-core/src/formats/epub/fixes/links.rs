@@ -0,0 +1,168 @@
+//! Finding the links in the documents of a book and resolving them to the
+//! files they point at.
+
+use std::ops::Range;
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use regex::Regex;
+
+use super::is_html_file;
+
+/// An `href` or `src` attribute (namespaced ones such as `xlink:href`
+/// included) found in a tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Link<'a> {
+    /// Name of the element the attribute is on, as written.
+    pub element: &'a str,
+    /// Byte range of the whole start tag in the document.
+    pub tag: Range<usize>,
+    /// Byte offset of the attribute value in the document.
+    pub offset: usize,
+    /// The attribute value as written, entities not expanded.
+    pub value: &'a str,
+}
+
+impl<'a> Link<'a> {
+    /// The path part of the value and its fragment, without the `#`.
+    pub fn split(&self) -> (&'a str, Option<&'a str>) {
+        match self.value.split_once('#') {
+            Some((path, fragment)) => (path, Some(fragment)),
+            None => (self.value, None),
+        }
+    }
+}
+
+/// Whether links in `filename` are followed: XHTML documents (the EPUB 3
+/// navigation document among them), the NCX and the OPF.
+pub(crate) fn has_links(filename: &str) -> bool {
+    let lower = filename.to_lowercase();
+    is_html_file(filename) || lower.ends_with(".ncx") || lower.ends_with(".opf")
+}
+
+/// Every link attribute in the tags of `content`, in document order. Text,
+/// comments and CDATA sections are never looked at.
+pub(crate) fn find_links(content: &str) -> Vec<Link<'_>> {
+    let attribute = Regex::new(r#"\s+([^\s=/>]+)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+)))?"#)
+        .expect("valid regex");
+
+    let mut reader = Reader::from_str(content);
+    let config = reader.config_mut();
+    config.check_end_names = false;
+    config.allow_unmatched_ends = true;
+
+    let mut links = Vec::new();
+    loop {
+        let start = reader.buffer_position() as usize;
+        match reader.read_event() {
+            Ok(Event::Start(_)) | Ok(Event::Empty(_)) => {
+                let end = reader.buffer_position() as usize;
+                let tag = &content[start..end];
+                let element = tag[1..]
+                    .split(|c: char| c.is_whitespace() || c == '/' || c == '>')
+                    .next()
+                    .unwrap_or_default();
+                for caps in attribute.captures_iter(tag) {
+                    let name = &caps[1];
+                    let local = name.rsplit(':').next().unwrap_or(name);
+                    if !local.eq_ignore_ascii_case("href") && !local.eq_ignore_ascii_case("src") {
+                        continue;
+                    }
+                    if let Some(value) = caps.get(2).or(caps.get(3)).or(caps.get(4)) {
+                        links.push(Link {
+                            element,
+                            tag: start..end,
+                            offset: start + value.start(),
+                            value: value.as_str(),
+                        });
+                    }
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+    links
+}
+
+/// Resolve the path of a link against the path of the document it is in,
+/// giving the name of the target inside the book. An empty path points at
+/// the document itself. External and absolute links resolve to `None`.
+pub(crate) fn resolve(document: &str, path: &str) -> Option<String> {
+    let path = path.split('?').next().unwrap_or(path);
+    if path.is_empty() {
+        return Some(document.to_string());
+    }
+    let first = path.split('/').next().unwrap_or(path);
+    if path.starts_with('/') || first.contains(':') {
+        return None;
+    }
+
+    let mut parts: Vec<String> = document.split('/').map(str::to_string).collect();
+    parts.pop();
+    for part in decode(path).split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part.to_string()),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// The link path from the document `from` to `target`, both names inside
+/// the book, percent-escaped.
+pub(crate) fn relative(from: &str, target: &str) -> String {
+    let from: Vec<&str> = from.split('/').collect();
+    let to: Vec<&str> = target.split('/').collect();
+    let from = &from[..from.len() - 1];
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut parts: Vec<String> = vec!["..".to_string(); from.len() - common];
+    parts.extend(to[common..].iter().map(|part| encode(part)));
+    parts.join("/")
+}
+
+/// Expand the entities and percent-escapes of a link path or fragment.
+pub(crate) fn decode(value: &str) -> String {
+    let unescaped = quick_xml::escape::unescape(value)
+        .map(|v| v.into_owned())
+        .unwrap_or_else(|_| value.to_string());
+
+    let bytes = unescaped.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|h| h.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Percent-escape a file name for use as a link path.
+pub(crate) fn encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
@@ -0,0 +1,448 @@
+use std::collections::{HashMap, HashSet};
+
+use super::links::{relative, resolve};
+use super::{EpubContents, Fix};
+use crate::formats::epub::package::{find_opf_path, Item, Node, Package};
+use crate::types::{Change, Diagnostic, DiagnosticCode, FixDescription, FixOptions, FixReport};
+
+/// Media types of the files a book is made of, by extension. The first one
+/// is used for new items; the others are also accepted.
+const MEDIA_TYPES: &[(&str, &[&str])] = &[
+    ("xhtml", &["application/xhtml+xml"]),
+    ("html", &["application/xhtml+xml"]),
+    ("htm", &["application/xhtml+xml"]),
+    ("ncx", &["application/x-dtbncx+xml"]),
+    ("css", &["text/css"]),
+    ("jpg", &["image/jpeg"]),
+    ("jpeg", &["image/jpeg"]),
+    ("png", &["image/png"]),
+    ("gif", &["image/gif"]),
+    ("svg", &["image/svg+xml"]),
+    ("webp", &["image/webp"]),
+    (
+        "ttf",
+        &[
+            "font/ttf",
+            "application/x-font-ttf",
+            "application/x-font-truetype",
+            "application/font-sfnt",
+        ],
+    ),
+    (
+        "otf",
+        &[
+            "font/otf",
+            "application/vnd.ms-opentype",
+            "application/x-font-otf",
+            "application/font-sfnt",
+        ],
+    ),
+    ("woff", &["font/woff", "application/font-woff"]),
+    ("woff2", &["font/woff2"]),
+    (
+        "js",
+        &[
+            "application/javascript",
+            "text/javascript",
+            "application/ecmascript",
+        ],
+    ),
+    ("smil", &["application/smil+xml"]),
+    ("mp3", &["audio/mpeg"]),
+    ("m4a", &["audio/mp4"]),
+    ("mp4", &["video/mp4", "audio/mp4"]),
+];
+
+/// What `fix_manifest` changed in the OPF, and what it left alone.
+#[derive(Debug, Default)]
+pub struct ManifestChanges {
+    /// Items added for files the manifest did not list.
+    pub added: Vec<Change>,
+    /// Items removed because their file does not exist, or because they
+    /// repeat another item.
+    pub removed: Vec<Change>,
+    /// `href`s that only matched their file in another case.
+    pub relinked: Vec<Change>,
+    /// `media-type`s that did not fit their file.
+    pub retyped: Vec<Change>,
+    /// Item ids renamed because an earlier item had them.
+    pub renamed: Vec<Change>,
+    /// Problems that were not repaired.
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl ManifestChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.relinked.is_empty()
+            && self.retyped.is_empty()
+            && self.renamed.is_empty()
+    }
+}
+
+/// Make the manifest of the OPF match the files of the book.
+///
+/// Files the manifest does not list get an item with the media type of their
+/// extension. Items whose file does not exist are removed, unless something
+/// else in the OPF (the spine, a cover meta, a fallback) refers to them, in
+/// which case they are only reported. `href`s that differ from their file
+/// only in case are corrected, as are `media-type`s that do not fit the
+/// file. An item that repeats the id of an earlier one is removed when it
+/// points at the same file and gets a fresh id otherwise.
+///
+/// `files` is every file of the book, text or not.
+pub fn fix_manifest(
+    text_files: &mut HashMap<String, String>,
+    files: &HashSet<String>,
+) -> ManifestChanges {
+    let mut result = ManifestChanges::default();
+    let Some(opf_path) = text_files
+        .get("META-INF/container.xml")
+        .and_then(|container| find_opf_path(container))
+    else {
+        return result;
+    };
+    let Some(Ok(mut package)) = text_files.get(&opf_path).map(|opf| Package::parse(opf)) else {
+        return result;
+    };
+
+    let referenced = referenced_ids(&package);
+    let mut used_ids = all_ids(&package);
+    let mut listed: HashSet<String> = HashSet::new();
+    let mut seen: HashMap<String, Option<String>> = HashMap::new();
+    let target = |item: &Item| {
+        let href = item.href.trim();
+        (!href.is_empty())
+            .then(|| resolve(&opf_path, href))
+            .flatten()
+    };
+
+    let unlisted: Vec<&String> = {
+        let hrefs: HashSet<String> = package.manifest.items.iter().filter_map(target).collect();
+        let mut unlisted: Vec<&String> = files
+            .iter()
+            .filter(|file| needs_item(file, &opf_path) && !hrefs.contains(*file))
+            .collect();
+        unlisted.sort();
+        unlisted
+    };
+
+    let prefix = package.prefix.clone();
+    let items = std::mem::take(&mut package.manifest.items);
+    for mut item in items {
+        let id = item.id.trim().to_string();
+        let mut path = target(&item);
+
+        // An earlier item with the same id and file makes this one redundant
+        let first = (!id.is_empty()).then(|| seen.get(&id)).flatten().cloned();
+        if first.as_ref() == Some(&path) {
+            result
+                .removed
+                .push(Change::new(opf_path.as_str(), item_tag(&prefix, &item), ""));
+            continue;
+        }
+
+        if let Some(missing) = path.clone().filter(|path| !files.contains(path)) {
+            let same_but_case: Vec<&&String> = unlisted
+                .iter()
+                .filter(|file| file.eq_ignore_ascii_case(&missing) && !listed.contains(**file))
+                .collect();
+            if let [file] = same_but_case[..] {
+                let replacement = relative(&opf_path, file);
+                result.relinked.push(Change::new(
+                    opf_path.as_str(),
+                    item.href.as_str(),
+                    replacement.as_str(),
+                ));
+                item.href = replacement;
+                path = Some((*file).clone());
+            } else if !id.is_empty() && referenced.contains(&id) {
+                result.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::ManifestMismatch,
+                        format!(
+                            "Manifest item '{}' points at missing file '{}' but is \
+                             still referenced, so it was kept",
+                            id, item.href
+                        ),
+                    )
+                    .with_file(opf_path.as_str()),
+                );
+                path = None;
+            } else {
+                result
+                    .removed
+                    .push(Change::new(opf_path.as_str(), item_tag(&prefix, &item), ""));
+                continue;
+            }
+        }
+
+        if first.is_some() {
+            let fresh = fresh_id(&id, &used_ids);
+            used_ids.insert(fresh.clone());
+            result
+                .renamed
+                .push(Change::new(opf_path.as_str(), id.as_str(), fresh.as_str()));
+            item.id = fresh;
+        } else if !id.is_empty() {
+            seen.insert(id, path.clone());
+        }
+
+        if let Some(path) = path {
+            if let Some(expected) = media_types(&path) {
+                let current = item.media_type.split(';').next().unwrap_or_default().trim();
+                if !expected.iter().any(|t| t.eq_ignore_ascii_case(current)) {
+                    result.retyped.push(Change::new(
+                        opf_path.as_str(),
+                        item.media_type.as_str(),
+                        expected[0],
+                    ));
+                    item.media_type = expected[0].to_string();
+                }
+            }
+            listed.insert(path);
+        }
+        package.manifest.items.push(item);
+    }
+
+    // Files nothing in the manifest points at
+    for file in unlisted.into_iter().filter(|file| !listed.contains(*file)) {
+        let Some(media_type) = media_types(file).map(|types| types[0]) else {
+            result.warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::ManifestMismatch,
+                    format!(
+                        "'{}' is not in the manifest and its media type is unknown",
+                        file
+                    ),
+                )
+                .with_file(opf_path.as_str()),
+            );
+            continue;
+        };
+        let name = file.rsplit('/').next().unwrap_or(file);
+        let id = fresh_id(&id_from_name(name), &used_ids);
+        used_ids.insert(id.clone());
+        let item = Item::new(id, relative(&opf_path, file), media_type);
+        result
+            .added
+            .push(Change::new(opf_path.as_str(), "", item_tag(&prefix, &item)));
+        package.manifest.items.push(item);
+    }
+
+    if !result.is_empty() {
+        text_files.insert(opf_path, package.to_xml());
+    }
+    result
+}
+
+/// The start tag of `item`, for the changes that list it.
+fn item_tag(prefix: &str, item: &Item) -> String {
+    format!(
+        r#"<{}item id="{}" href="{}" media-type="{}"/>"#,
+        prefix, item.id, item.href, item.media_type
+    )
+}
+
+/// Every `id` in the OPF, so new ones do not clash with any of them.
+fn all_ids(package: &Package) -> HashSet<String> {
+    fn collect(nodes: &[Node], ids: &mut HashSet<String>) {
+        for node in nodes {
+            if let Node::Element(element) = node {
+                ids.extend(element.attribute("id").map(|id| id.trim().to_string()));
+                collect(&element.children, ids);
+            }
+        }
+    }
+
+    let mut ids = HashSet::new();
+    collect(&package.metadata.children, &mut ids);
+    collect(&package.manifest.other, &mut ids);
+    collect(&package.spine.other, &mut ids);
+    collect(&package.other, &mut ids);
+    ids.extend(package.attribute("id").map(str::to_string));
+    ids.extend(
+        package
+            .manifest
+            .items
+            .iter()
+            .map(|item| item.id.trim().to_string()),
+    );
+    let attributes = package
+        .spine
+        .itemrefs
+        .iter()
+        .map(|itemref| &itemref.attributes)
+        .chain(package.guide.iter().flat_map(|guide| {
+            guide
+                .references
+                .iter()
+                .map(|reference| &reference.attributes)
+        }));
+    for attributes in attributes {
+        ids.extend(
+            attributes
+                .iter()
+                .filter(|(key, _)| key == "id")
+                .map(|(_, id)| id.trim().to_string()),
+        );
+    }
+    ids
+}
+
+/// The item ids something besides the item itself refers to: the spine, the
+/// NCX reference, fallbacks, media overlays and `<meta content>` (the cover).
+fn referenced_ids(package: &Package) -> HashSet<String> {
+    let spine = package.spine.toc.iter().map(String::as_str).chain(
+        package
+            .spine
+            .itemrefs
+            .iter()
+            .map(|itemref| itemref.idref.as_str()),
+    );
+    let items = package.manifest.items.iter().flat_map(|item| {
+        [
+            item.fallback.as_deref(),
+            item.media_overlay.as_deref(),
+            item.attributes
+                .iter()
+                .find(|(key, _)| key == "fallback-style")
+                .map(|(_, value)| value.as_str()),
+        ]
+        .into_iter()
+        .flatten()
+    });
+    let metas = package
+        .metadata
+        .elements()
+        .filter_map(|element| element.attribute("content"));
+    spine
+        .chain(items)
+        .chain(metas)
+        .map(|id| id.trim().to_string())
+        .collect()
+}
+
+/// Whether `file` belongs in the manifest: everything but the `mimetype`,
+/// `META-INF`, the OPF itself and files operating systems and tools leave
+/// behind when a book is unpacked and zipped again.
+fn needs_item(file: &str, opf_path: &str) -> bool {
+    file != "mimetype" && !file.starts_with("META-INF/") && file != opf_path && !is_leftover(file)
+}
+
+/// Whether `file` is left over from packaging rather than part of the book:
+/// macOS resource forks and folder settings, Windows thumbnail caches and
+/// iTunes metadata.
+fn is_leftover(file: &str) -> bool {
+    const NAMES: &[&str] = &[
+        ".DS_Store",
+        "Thumbs.db",
+        "desktop.ini",
+        "iTunesMetadata.plist",
+        "iTunesArtwork",
+    ];
+    let mut segments = file.split('/');
+    let name = segments.next_back().unwrap_or(file);
+    segments.any(|segment| segment == "__MACOSX")
+        || name.starts_with("._")
+        || NAMES
+            .iter()
+            .any(|leftover| name.eq_ignore_ascii_case(leftover))
+}
+
+/// The media types accepted for `file`, the preferred one first.
+fn media_types(file: &str) -> Option<&'static [&'static str]> {
+    let (_, ext) = file.rsplit_once('.')?;
+    let ext = ext.to_lowercase();
+    MEDIA_TYPES
+        .iter()
+        .find(|(e, _)| *e == ext)
+        .map(|(_, types)| *types)
+}
+
+/// An XML name made from a file name.
+fn id_from_name(name: &str) -> String {
+    let mut id: String = name
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' => c,
+            _ => '_',
+        })
+        .collect();
+    if !id.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        id.insert(0, 'x');
+    }
+    id
+}
+
+/// `id`, or `id-2`, `id-3` and so on, whichever is not used yet.
+fn fresh_id(id: &str, used: &HashSet<String>) -> String {
+    if !used.contains(id) {
+        return id.to_string();
+    }
+    (2..)
+        .map(|n| format!("{}-{}", id, n))
+        .find(|candidate| !used.contains(candidate))
+        .expect("some suffix is free")
+}
+
+pub struct ManifestFix;
+
+impl Fix for ManifestFix {
+    fn id(&self) -> &'static str {
+        "manifest"
+    }
+
+    fn description(&self) -> &'static str {
+        "Make the OPF manifest list every file of the book, and only those, with the right media types"
+    }
+
+    fn code(&self) -> DiagnosticCode {
+        DiagnosticCode::ManifestMismatch
+    }
+
+    fn apply(&self, contents: &mut EpubContents, _options: &FixOptions, report: &mut FixReport) {
+        let files: HashSet<String> = contents
+            .text_files
+            .keys()
+            .chain(contents.binary_files.keys())
+            .cloned()
+            .collect();
+        let mut result = fix_manifest(&mut contents.text_files, &files);
+
+        report.warnings.append(&mut result.warnings);
+        if result.is_empty() {
+            return;
+        }
+
+        let counts = [
+            ("added", result.added.len(), "missing item(s)"),
+            (
+                "removed",
+                result.removed.len(),
+                "dangling or repeated item(s)",
+            ),
+            ("fixed", result.relinked.len(), "href(s) in the wrong case"),
+            ("fixed", result.retyped.len(), "media type(s)"),
+            ("renamed", result.renamed.len(), "duplicate id(s)"),
+        ];
+        let parts: Vec<String> = counts
+            .iter()
+            .filter(|(_, count, _)| *count > 0)
+            .map(|(verb, count, what)| format!("{} {} {}", verb, count, what))
+            .collect();
+        let details = format!("Manifest: {}", parts.join(", "));
+        let changes = [
+            result.added,
+            result.removed,
+            result.relinked,
+            result.retyped,
+            result.renamed,
+        ]
+        .concat();
+        report
+            .fixes_applied
+            .push(FixDescription::from_changes(self.id(), details, changes));
+    }
+}
@@ -1,8 +1,84 @@
 pub mod body_id;
+pub mod broken_links;
 pub mod encoding;
 pub mod language;
+pub(crate) mod links;
+pub mod manifest;
 pub mod stray_img;
 
+use std::collections::HashMap;
+
+use crate::types::{DiagnosticCode, FixOptions, FixReport, Severity};
+
+use self::body_id::BodyIdFix;
+use self::broken_links::BrokenLinksFix;
+use self::encoding::EncodingFix;
+use self::language::LanguageFix;
+use self::manifest::ManifestFix;
+use self::stray_img::StrayImgFix;
+
+/// The decoded files of an EPUB that fixes work on.
+#[derive(Debug, Clone, Default)]
+pub struct EpubContents {
+    pub text_files: HashMap<String, String>,
+    pub binary_files: HashMap<String, Vec<u8>>,
+}
+
+/// A fix that can be applied to an EPUB. Available fixes are listed by
+/// `registry()` and selected by id through `FixOptions::only` and `FixOptions::skip`.
+pub trait Fix: Sync {
+    /// Stable identifier, also used as the name of the reported fix.
+    fn id(&self) -> &'static str;
+
+    /// One-line description shown when listing fixes.
+    fn description(&self) -> &'static str;
+
+    /// Code of the diagnostic `check_file` reports for what this fix repairs.
+    fn code(&self) -> DiagnosticCode;
+
+    /// Whether the fix runs unless it is skipped or other fixes are selected.
+    fn enabled_by_default(&self) -> bool {
+        true
+    }
+
+    /// Severity of the diagnostic `check_file` reports for what this fix repairs.
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    /// Apply the fix, recording changes and warnings in `report`.
+    fn apply(&self, contents: &mut EpubContents, options: &FixOptions, report: &mut FixReport);
+}
+
+static REGISTRY: &[&dyn Fix] = &[
+    &BodyIdFix,
+    &BrokenLinksFix,
+    &LanguageFix,
+    &StrayImgFix,
+    &EncodingFix,
+    &ManifestFix,
+];
+
+/// Every available fix, in the order they are applied.
+pub fn registry() -> &'static [&'static dyn Fix] {
+    REGISTRY
+}
+
+/// Look up a fix by id.
+pub fn find(id: &str) -> Option<&'static dyn Fix> {
+    REGISTRY.iter().copied().find(|fix| fix.id() == id)
+}
+
+/// Whether `fix` should run with `options`: it is listed in `only`, or
+/// enabled by default when `only` is not set, and it is not in `skip`.
+pub fn is_enabled(fix: &dyn Fix, options: &FixOptions) -> bool {
+    let selected = match &options.only {
+        Some(only) => only.iter().any(|id| id == fix.id()),
+        None => fix.enabled_by_default(),
+    };
+    selected && !options.skip.iter().any(|id| id == fix.id())
+}
+
 /// Check if a filename has an HTML/XHTML extension.
 pub(crate) fn is_html_file(filename: &str) -> bool {
     let ext = filename.rsplit('.').next().unwrap_or("").to_lowercase();
@@ -2,11 +2,12 @@ use std::collections::HashMap;
 
 use regex::Regex;
 
-use super::is_html_file;
+use super::{is_html_file, EpubContents, Fix};
+use crate::types::{Change, DiagnosticCode, FixDescription, FixOptions, FixReport};
 
 /// Remove `<img>` tags that have no `src` attribute.
-/// Returns a list of filenames where stray images were removed.
-pub fn fix_stray_images(files: &mut HashMap<String, String>) -> Vec<String> {
+/// Returns every tag that was removed.
+pub fn fix_stray_images(files: &mut HashMap<String, String>) -> Vec<Change> {
     let img_regex = Regex::new(r#"<img\b([^>]*)/?>"#).expect("valid regex");
     let src_regex = Regex::new(r#"\bsrc\s*="#).expect("valid regex");
 
@@ -19,23 +20,55 @@ pub fn fix_stray_images(files: &mut HashMap<String, String>) -> Vec<String> {
         }
 
         let content = files[&filename].clone();
-        let mut removed_count = 0;
+        let mut removed = Vec::new();
 
         let new_content = img_regex.replace_all(&content, |caps: &regex::Captures| {
             let attrs = &caps[1];
             if src_regex.is_match(attrs) {
                 caps[0].to_string()
             } else {
-                removed_count += 1;
+                let tag = caps.get(0).expect("match has a whole group");
+                removed.push(
+                    Change::new(filename.as_str(), tag.as_str(), "")
+                        .at_offset(&content, tag.start()),
+                );
                 String::new()
             }
         });
 
-        if removed_count > 0 {
+        if !removed.is_empty() {
             files.insert(filename.clone(), new_content.to_string());
-            fixed.push(filename);
+            fixed.append(&mut removed);
         }
     }
 
     fixed
 }
+
+pub struct StrayImgFix;
+
+impl Fix for StrayImgFix {
+    fn id(&self) -> &'static str {
+        "stray_img"
+    }
+
+    fn description(&self) -> &'static str {
+        "Remove <img> tags without a src attribute"
+    }
+
+    fn code(&self) -> DiagnosticCode {
+        DiagnosticCode::StrayImage
+    }
+
+    fn apply(&self, contents: &mut EpubContents, _options: &FixOptions, report: &mut FixReport) {
+        let fixed = fix_stray_images(&mut contents.text_files);
+        if !fixed.is_empty() {
+            let mut description = FixDescription::from_changes(self.id(), String::new(), fixed);
+            description.details = format!(
+                "Removed stray image tag(s) in {} file(s)",
+                description.files_affected
+            );
+            report.fixes_applied.push(description);
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use std::collections::HashMap;
+
+use crate::types::BookMetadata;
+
+use super::package::{find_opf_path, Package};
+
+/// Read the title, first author, language and date of an EPUB from its
+/// package document. Missing or unreadable metadata is left as `None`.
+pub fn read_metadata(text_files: &HashMap<String, String>) -> BookMetadata {
+    text_files
+        .get("META-INF/container.xml")
+        .and_then(|container| find_opf_path(container))
+        .and_then(|opf_path| text_files.get(&opf_path))
+        .map(|opf| parse_opf_metadata(opf))
+        .unwrap_or_default()
+}
+
+/// Collect the first non-empty `dc:title`, `dc:creator`, `dc:language` and
+/// `dc:date` of an OPF.
+pub fn parse_opf_metadata(opf: &str) -> BookMetadata {
+    let Ok(package) = Package::parse(opf) else {
+        return BookMetadata::default();
+    };
+    let metadata = &package.metadata;
+    BookMetadata {
+        title: metadata.dc_text("title"),
+        author: metadata.dc_text("creator"),
+        language: metadata.dc_text("language"),
+        date: metadata.dc_text("date"),
+    }
+}
@@ -1,19 +1,25 @@
+pub mod charset;
+pub mod diff;
 pub mod fixes;
+pub mod metadata;
+pub mod package;
 pub mod reader;
+pub mod stamp;
 pub mod writer;
 
 use std::io::{Cursor, Read};
 
-use crate::error::Result;
+use crate::error::{KindleFixError, Result};
 use crate::formats::FileFixer;
-use crate::types::{FileFormat, FixDescription, FixOptions, FixOutput, FixReport};
+use crate::types::{Change, FileFormat, FixDescription, FixOptions, FixOutput, FixReport};
 
-use self::fixes::body_id::fix_body_id_links;
-use self::fixes::encoding::fix_encoding;
-use self::fixes::language::{fix_language, LanguageFixResult};
-use self::fixes::stray_img::fix_stray_images;
+use self::diff::diff_files;
+use self::fixes::{is_enabled, registry, EpubContents};
+use self::metadata::read_metadata;
+use self::package::find_opf_path;
 use self::reader::EpubReader;
-use self::writer::EpubWriter;
+use self::stamp::{read_stamp, write_stamp, Stamp};
+use self::writer::{EpubWriter, Reproducible};
 
 pub struct EpubFixer;
 
@@ -33,83 +39,145 @@ impl FileFixer for EpubFixer {
 
     fn fix(&self, data: &[u8], options: &FixOptions) -> Result<FixOutput> {
         let reader = EpubReader::from_bytes(data)?;
-        let (mut text_files, binary_files) = reader.into_parts();
+        let manifest = reader.manifest().clone();
+        let transcoded = reader.transcoded().to_vec();
         let mut report = FixReport::new(String::new(), FileFormat::Epub);
+        report.warnings.extend_from_slice(reader.warnings());
+        let (text_files, binary_files) = reader.into_parts();
 
-        // Fix 1: Body ID links
-        let body_id_fixes = fix_body_id_links(&mut text_files);
-        if !body_id_fixes.is_empty() {
+        // Non-UTF-8 text files were already transcoded while reading
+        if !transcoded.is_empty() {
+            let mut charsets: Vec<&str> = transcoded.iter().map(|(_, c)| c.as_str()).collect();
+            charsets.sort_unstable();
+            charsets.dedup();
             report.fixes_applied.push(FixDescription {
-                name: "body_id".to_string(),
-                details: format!("Removed {} body ID link reference(s)", body_id_fixes.len()),
-                files_affected: body_id_fixes.len(),
-            });
-        }
-
-        // Fix 2: Language
-        let lang_result = fix_language(&mut text_files, options.language.clone());
-        match &lang_result {
-            LanguageFixResult::Added(lang) => {
-                report.fixes_applied.push(FixDescription {
-                    name: "language".to_string(),
-                    details: format!("Added missing language tag: {}", lang),
-                    files_affected: 1,
-                });
-            }
-            LanguageFixResult::Changed { from, to } => {
-                report.fixes_applied.push(FixDescription {
-                    name: "language".to_string(),
-                    details: format!("Changed language from {} to {}", from, to),
-                    files_affected: 1,
-                });
-            }
-            LanguageFixResult::Unsupported(lang) => {
-                report.warnings.push(format!(
-                    "Language '{}' is not supported by Kindle. Use --language to override.",
-                    lang
-                ));
-            }
-            LanguageFixResult::Error(msg) => {
-                report.warnings.push(format!("Language check failed: {}", msg));
-            }
-            LanguageFixResult::Valid(_) => {}
-        }
-
-        // Fix 3: Stray images
-        let stray_img_fixes = fix_stray_images(&mut text_files);
-        if !stray_img_fixes.is_empty() {
-            report.fixes_applied.push(FixDescription {
-                name: "stray_img".to_string(),
+                name: "transcode".to_string(),
                 details: format!(
-                    "Removed stray image tag(s) in {} file(s)",
-                    stray_img_fixes.len()
+                    "Transcoded {} file(s) from {} to UTF-8",
+                    transcoded.len(),
+                    charsets.join(", ")
                 ),
-                files_affected: stray_img_fixes.len(),
+                files_affected: transcoded.len(),
+                changes: transcoded
+                    .iter()
+                    .map(|(name, charset)| Change::new(name.as_str(), charset.as_str(), "UTF-8"))
+                    .collect(),
             });
         }
 
-        // Fix 4: Encoding
-        let encoding_fixes = fix_encoding(&mut text_files);
-        if !encoding_fixes.is_empty() {
-            report.fixes_applied.push(FixDescription {
-                name: "encoding".to_string(),
-                details: format!(
-                    "Added UTF-8 encoding declaration to {} file(s)",
-                    encoding_fixes.len()
-                ),
-                files_affected: encoding_fixes.len(),
-            });
-        }
+        let original = options.diff.then(|| text_files.clone());
+        let mut contents = EpubContents {
+            text_files,
+            binary_files,
+        };
+        apply_fixes(&mut contents, options, &mut report);
+        let EpubContents {
+            text_files,
+            binary_files,
+        } = contents;
+        report.metadata = read_metadata(&text_files);
+        let unchanged = !report.has_fixes();
 
         let output_data = if options.dry_run {
             Vec::new()
+        } else if unchanged {
+            data.to_vec()
+        } else if let Some(order) = options.reproducible {
+            let settings = Reproducible::from_env(order);
+            EpubWriter::write_reproducible(&text_files, &binary_files, &manifest, &settings)?
         } else {
-            EpubWriter::write(&text_files, &binary_files)?
+            EpubWriter::write_with_manifest(&text_files, &binary_files, &manifest)?
         };
 
+        let diffs = original
+            .map(|original| diff_files(&original, &text_files))
+            .unwrap_or_default();
+
         Ok(FixOutput {
             data: output_data,
             report,
+            unchanged,
+            diffs,
         })
     }
 }
+
+/// Run the selected fixes, skipping those the book's stamp says an earlier
+/// run applied, and stamp the book if asked to and anything changed.
+fn apply_fixes(contents: &mut EpubContents, options: &FixOptions, report: &mut FixReport) {
+    let opf_path = contents
+        .text_files
+        .get("META-INF/container.xml")
+        .and_then(|container| find_opf_path(container));
+    let stamp = opf_path
+        .as_ref()
+        .and_then(|path| contents.text_files.get(path))
+        .and_then(|opf| read_stamp(opf));
+    let done: &[String] = match &stamp {
+        Some(stamp) if !options.force => &stamp.fixes,
+        _ => &[],
+    };
+
+    let mut ran = Vec::new();
+    for fix in registry() {
+        if !is_enabled(*fix, options) {
+            continue;
+        }
+        if done.iter().any(|id| id == fix.id()) {
+            report.already_applied.push(fix.id().to_string());
+        } else {
+            fix.apply(contents, options, report);
+            ran.push(fix.id().to_string());
+        }
+    }
+
+    if options.stamp && report.has_fixes() {
+        let mut fixes = stamp.as_ref().map(|s| s.fixes.clone()).unwrap_or_default();
+        fixes.extend(ran);
+        fixes.sort();
+        fixes.dedup();
+        let new_stamp = Stamp {
+            version: crate::version().to_string(),
+            fixes,
+        };
+        if let Some(path) = opf_path {
+            let stamped = contents
+                .text_files
+                .get(&path)
+                .and_then(|opf| write_stamp(opf, &new_stamp));
+            if let Some(stamped) = stamped {
+                contents.text_files.insert(path, stamped);
+            }
+        }
+    }
+
+    report.stamp = stamp;
+}
+
+/// Check that `data` re-opens as an EPUB: the mimetype is right, every
+/// entry decompresses, and the container points at a package document
+/// that exists.
+pub fn validate(data: &[u8]) -> Result<()> {
+    if !EpubFixer::detect(data) {
+        return Err(KindleFixError::InvalidEpub(
+            "missing or wrong mimetype entry".into(),
+        ));
+    }
+
+    let reader = EpubReader::from_bytes(data)?;
+    let container = reader
+        .text_files()
+        .get("META-INF/container.xml")
+        .ok_or_else(|| KindleFixError::InvalidEpub("missing META-INF/container.xml".into()))?;
+    let opf_path = find_opf_path(container).ok_or_else(|| {
+        KindleFixError::InvalidEpub("container.xml names no package document".into())
+    })?;
+    if !reader.text_files().contains_key(&opf_path) {
+        return Err(KindleFixError::InvalidEpub(format!(
+            "missing package document {}",
+            opf_path
+        )));
+    }
+
+    Ok(())
+}
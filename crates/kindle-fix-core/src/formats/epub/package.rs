@@ -0,0 +1,994 @@
+//! The package document (OPF) of an EPUB and the `META-INF/container.xml`
+//! that points at it, as a typed model that can be edited and written back.
+//!
+//! The model has fields for what fixes work with: the metadata, manifest,
+//! spine and guide. Everything else is kept as it was parsed: attributes the
+//! model has no field for stay on their element, and unknown elements and
+//! comments are kept as [`Node`]s. Writing a package back therefore only
+//! changes insignificant whitespace, indenting one element per line with the
+//! indent the document used.
+
+use std::sync::LazyLock;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use regex::Regex;
+
+use crate::error::{KindleFixError, Result};
+
+/// An entity or character reference (`&amp;`, `&#233;`, `&nbsp;`).
+static ENTITY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"&#?\w+;").expect("valid regex"));
+
+/// Media type of the package document in `container.xml`.
+pub const OPF_MEDIA_TYPE: &str = "application/oebps-package+xml";
+
+/// Namespace of the Dublin Core metadata elements.
+pub const DC_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+
+/// A node of an XML element the model has no type for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Element(Element),
+    /// Text, entities expanded.
+    Text(String),
+    /// Text as written, because it uses entities XML does not define
+    /// (`&nbsp;`). It is written back verbatim.
+    Escaped(String),
+    /// The inside of a CDATA section.
+    CData(String),
+    /// The inside of a comment.
+    Comment(String),
+    /// The inside of a processing instruction.
+    ProcessingInstruction(String),
+}
+
+/// An XML element with its attributes and children.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Element {
+    /// Qualified name, as written (`dc:title`).
+    pub name: String,
+    /// Attributes in document order, values unescaped. Entities XML does not
+    /// define (`&euml;`) are kept as written.
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+impl Element {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// The name without its namespace prefix.
+    pub fn local_name(&self) -> &str {
+        local_name(&self.name)
+    }
+
+    /// The namespace prefix of the name, if it has one.
+    pub fn prefix(&self) -> Option<&str> {
+        self.name.split_once(':').map(|(prefix, _)| prefix)
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        attribute(&self.attributes, name)
+    }
+
+    /// Set an attribute, keeping its place if the element already has it.
+    pub fn set_attribute(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        set_attribute(&mut self.attributes, name.into(), value.into());
+    }
+
+    pub fn remove_attribute(&mut self, name: &str) -> Option<String> {
+        remove_attribute(&mut self.attributes, name)
+    }
+
+    /// The text and CDATA inside the element, child elements included.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for child in &self.children {
+            match child {
+                Node::Text(t) | Node::CData(t) => text.push_str(t),
+                Node::Escaped(t) => text.push_str(&unescape_known(t)),
+                Node::Element(e) => text.push_str(&e.text()),
+                _ => {}
+            }
+        }
+        text
+    }
+
+    /// Replace the children of the element with `text`.
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        self.children = vec![Node::Text(text.into())];
+    }
+
+    /// The child elements.
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|child| match child {
+            Node::Element(e) => Some(e),
+            _ => None,
+        })
+    }
+
+    /// The namespace `prefix` is bound to on this element, if it is.
+    fn namespace(&self, prefix: Option<&str>) -> Option<&str> {
+        match prefix {
+            Some(prefix) => self.attribute(&format!("xmlns:{}", prefix)),
+            None => self.attribute("xmlns"),
+        }
+    }
+}
+
+/// The rootfiles listed in `META-INF/container.xml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Container {
+    pub rootfiles: Vec<Rootfile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rootfile {
+    pub full_path: String,
+    pub media_type: String,
+}
+
+impl Container {
+    pub fn parse(xml: &str) -> Result<Self> {
+        let root = parse_document(xml)?.root;
+        let rootfiles = root
+            .elements()
+            .filter(|e| e.local_name() == "rootfiles")
+            .flat_map(|e| e.elements())
+            .filter(|e| e.local_name() == "rootfile")
+            .filter_map(|e| {
+                Some(Rootfile {
+                    full_path: e.attribute("full-path")?.trim().to_string(),
+                    media_type: e
+                        .attribute("media-type")
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string(),
+                })
+            })
+            .collect();
+        Ok(Self { rootfiles })
+    }
+
+    /// Paths of the package documents, in the order they are listed.
+    pub fn package_paths(&self) -> impl Iterator<Item = &str> {
+        self.rootfiles
+            .iter()
+            .filter(|rootfile| rootfile.media_type == OPF_MEDIA_TYPE)
+            .map(|rootfile| rootfile.full_path.as_str())
+    }
+
+    /// Path of the first package document, which reading systems use.
+    pub fn opf_path(&self) -> Option<&str> {
+        self.package_paths().next()
+    }
+}
+
+/// Path of the first package document listed in a `container.xml`.
+pub fn find_opf_path(container_xml: &str) -> Option<String> {
+    Container::parse(container_xml)
+        .ok()?
+        .opf_path()
+        .map(str::to_string)
+}
+
+/// A package document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    /// Namespace prefix of the package elements with its colon (`opf:`), or
+    /// empty when they use the default namespace.
+    pub prefix: String,
+    /// Attributes of `<package>`: `version`, `unique-identifier`, namespace
+    /// declarations and so on.
+    pub attributes: Vec<(String, String)>,
+    pub metadata: Metadata,
+    pub manifest: Manifest,
+    pub spine: Spine,
+    pub guide: Option<Guide>,
+    /// Other children of `<package>` (`bindings`, `collection`, comments).
+    /// Parsed ones are written back in their place, added ones at the end.
+    pub other: Vec<Node>,
+    /// The children of `<package>` in source order.
+    order: Vec<Part>,
+    /// The XML declaration, comments and doctype before `<package>`.
+    prolog: String,
+    /// Whatever follows `</package>`.
+    epilog: String,
+    /// One level of indentation in the source.
+    indent: String,
+}
+
+impl Package {
+    pub fn parse(opf: &str) -> Result<Self> {
+        let document = parse_document(opf)?;
+        let root = document.root;
+        if root.local_name() != "package" {
+            return Err(KindleFixError::InvalidEpub(format!(
+                "package document starts with <{}> instead of <package>",
+                root.name
+            )));
+        }
+
+        let prefix = root
+            .prefix()
+            .map(|prefix| format!("{}:", prefix))
+            .unwrap_or_default();
+        let mut package = Package {
+            prefix,
+            attributes: root.attributes.clone(),
+            metadata: Metadata::default(),
+            manifest: Manifest::default(),
+            spine: Spine::default(),
+            guide: None,
+            other: Vec::new(),
+            order: Vec::new(),
+            prolog: document.prolog,
+            epilog: document.epilog,
+            indent: document.indent,
+        };
+
+        for child in root.children {
+            let element = match child {
+                Node::Element(e) => e,
+                Node::Text(text) if text.trim().is_empty() => continue,
+                other => {
+                    package.order.push(Part::Other(package.other.len()));
+                    package.other.push(other);
+                    continue;
+                }
+            };
+            let part = match element.local_name() {
+                "metadata" => {
+                    package.metadata = Metadata::from_element(element, &root.attributes);
+                    Part::Metadata
+                }
+                "manifest" => {
+                    package.manifest = Manifest::from_element(element);
+                    Part::Manifest
+                }
+                "spine" => {
+                    package.spine = Spine::from_element(element);
+                    Part::Spine
+                }
+                "guide" => {
+                    package.guide = Some(Guide::from_element(element));
+                    Part::Guide
+                }
+                _ => {
+                    package.other.push(Node::Element(element));
+                    Part::Other(package.other.len() - 1)
+                }
+            };
+            if !package.order.contains(&part) {
+                package.order.push(part);
+            }
+        }
+        Ok(package)
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        attribute(&self.attributes, "version")
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        attribute(&self.attributes, name)
+    }
+
+    /// Set an attribute of `<package>`, keeping its place if it is there.
+    pub fn set_attribute(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        set_attribute(&mut self.attributes, name.into(), value.into());
+    }
+
+    pub fn remove_attribute(&mut self, name: &str) -> Option<String> {
+        remove_attribute(&mut self.attributes, name)
+    }
+
+    /// Write the package document back out.
+    pub fn to_xml(&self) -> String {
+        let name = |local: &str| format!("{}{}", self.prefix, local);
+        let mut root = Element {
+            name: name("package"),
+            attributes: self.attributes.clone(),
+            children: Vec::new(),
+        };
+        for part in self.layout() {
+            let child = match part {
+                Part::Metadata => Node::Element(self.metadata.to_element(name("metadata"))),
+                Part::Manifest => Node::Element(self.manifest.to_element(&self.prefix)),
+                Part::Spine => Node::Element(self.spine.to_element(&self.prefix)),
+                Part::Guide => match &self.guide {
+                    Some(guide) => Node::Element(guide.to_element(&self.prefix)),
+                    None => continue,
+                },
+                Part::Other(index) => match self.other.get(index) {
+                    Some(node) => node.clone(),
+                    None => continue,
+                },
+            };
+            root.children.push(child);
+        }
+
+        let mut out = self.prolog.clone();
+        write_element(&mut out, &root, &self.indent, 0);
+        out.push_str(&self.epilog);
+        out
+    }
+
+    /// The order to write the children of `<package>` in: the source order,
+    /// with missing known elements after the ones before them in the usual
+    /// order and nodes added to `other` at the end.
+    fn layout(&self) -> Vec<Part> {
+        let mut order = self.order.clone();
+        let mut previous = None;
+        for part in [Part::Metadata, Part::Manifest, Part::Spine, Part::Guide] {
+            match order.iter().position(|p| *p == part) {
+                Some(at) => previous = Some(at),
+                None => {
+                    let at = previous.map_or(0, |i| i + 1);
+                    order.insert(at, part);
+                    previous = Some(at);
+                }
+            }
+        }
+        let parsed = order
+            .iter()
+            .filter(|part| matches!(part, Part::Other(_)))
+            .count();
+        order.extend((parsed..self.other.len()).map(Part::Other));
+        order
+    }
+}
+
+/// A child of `<package>`, recorded to write the children back in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Metadata,
+    Manifest,
+    Spine,
+    Guide,
+    /// An index into `Package::other`.
+    Other(usize),
+}
+
+/// The `<metadata>` of a package: Dublin Core elements, `<meta>`s and
+/// `<link>`s, kept in order as elements.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+    /// Namespace declarations of `<package>`, which the metadata inherits.
+    inherited: Vec<(String, String)>,
+}
+
+impl Metadata {
+    fn from_element(element: Element, package: &[(String, String)]) -> Self {
+        Self {
+            attributes: element.attributes,
+            children: element
+                .children
+                .into_iter()
+                .filter(|child| !matches!(child, Node::Text(t) if t.trim().is_empty()))
+                .collect(),
+            inherited: package
+                .iter()
+                .filter(|(key, _)| key == "xmlns" || key.starts_with("xmlns:"))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn to_element(&self, name: String) -> Element {
+        Element {
+            name,
+            attributes: self.attributes.clone(),
+            children: self.children.clone(),
+        }
+    }
+
+    /// The elements of the metadata.
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|child| match child {
+            Node::Element(e) => Some(e),
+            _ => None,
+        })
+    }
+
+    /// The Dublin Core elements named `local` (`title`, `language`, ...).
+    pub fn dc<'a>(&'a self, local: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.elements()
+            .filter(move |e| e.local_name() == local && self.is_dc(e))
+    }
+
+    /// Mutable access to the Dublin Core elements named `local`.
+    pub fn dc_mut(&mut self, local: &str) -> Vec<&mut Element> {
+        let dc: Vec<bool> = self
+            .children
+            .iter()
+            .map(|child| matches!(child, Node::Element(e) if e.local_name() == local && self.is_dc(e)))
+            .collect();
+        self.children
+            .iter_mut()
+            .zip(dc)
+            .filter_map(|(child, is_dc)| match child {
+                Node::Element(e) if is_dc => Some(e),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The trimmed text of the first non-empty Dublin Core element named
+    /// `local`.
+    pub fn dc_text(&self, local: &str) -> Option<String> {
+        self.dc(local)
+            .map(|e| e.text().trim().to_string())
+            .find(|text| !text.is_empty())
+    }
+
+    /// Add a Dublin Core element after the last one with the same name, or
+    /// at the end of the metadata. The `dc` prefix is declared if needed.
+    pub fn add_dc(&mut self, local: &str, text: &str) -> &mut Element {
+        let prefix = match self.dc_prefix() {
+            Some(prefix) => prefix,
+            None => {
+                self.attributes
+                    .push(("xmlns:dc".to_string(), DC_NAMESPACE.to_string()));
+                "dc".to_string()
+            }
+        };
+        let mut element = Element::new(format!("{}:{}", prefix, local));
+        element.set_text(text);
+
+        let at = self
+            .children
+            .iter()
+            .rposition(|child| matches!(child, Node::Element(e) if e.local_name() == local && self.is_dc(e)))
+            .map_or(self.children.len(), |i| i + 1);
+        self.children.insert(at, Node::Element(element));
+        match &mut self.children[at] {
+            Node::Element(e) => e,
+            _ => unreachable!("an element was just inserted"),
+        }
+    }
+
+    /// Remove the Dublin Core elements named `local`, returning how many
+    /// there were.
+    pub fn remove_dc(&mut self, local: &str) -> usize {
+        self.retain_dc(local, |_| false)
+    }
+
+    /// Keep only the Dublin Core elements named `local` for which `keep`
+    /// returns true, visiting them in order. Returns how many were removed.
+    pub fn retain_dc(&mut self, local: &str, mut keep: impl FnMut(&Element) -> bool) -> usize {
+        let before = self.children.len();
+        let kept: Vec<bool> = self
+            .children
+            .iter()
+            .map(|child| match child {
+                Node::Element(e) if e.local_name() == local && self.is_dc(e) => keep(e),
+                _ => true,
+            })
+            .collect();
+        let mut kept = kept.into_iter();
+        self.children.retain(|_| kept.next().unwrap_or(true));
+        before - self.children.len()
+    }
+
+    /// The `content` of the EPUB 2 `<meta name="...">` named `name`.
+    pub fn meta(&self, name: &str) -> Option<&str> {
+        self.elements()
+            .find(|e| e.local_name() == "meta" && e.attribute("name") == Some(name))
+            .and_then(|e| e.attribute("content"))
+    }
+
+    /// Whether `element` is in the Dublin Core namespace. An undeclared `dc`
+    /// prefix is taken to be Dublin Core, as reading systems do.
+    fn is_dc(&self, element: &Element) -> bool {
+        let Some(prefix) = element.prefix() else {
+            return element.namespace(None) == Some(DC_NAMESPACE);
+        };
+        match element
+            .namespace(Some(prefix))
+            .or_else(|| self.namespace(prefix))
+        {
+            Some(namespace) => namespace == DC_NAMESPACE,
+            None => prefix == "dc",
+        }
+    }
+
+    fn namespace(&self, prefix: &str) -> Option<&str> {
+        let key = format!("xmlns:{}", prefix);
+        attribute(&self.attributes, &key).or_else(|| attribute(&self.inherited, &key))
+    }
+
+    /// The prefix Dublin Core elements are written with.
+    fn dc_prefix(&self) -> Option<String> {
+        self.attributes
+            .iter()
+            .chain(&self.inherited)
+            .find(|(key, value)| key.starts_with("xmlns:") && value == DC_NAMESPACE)
+            .map(|(key, _)| key["xmlns:".len()..].to_string())
+            .or_else(|| self.dc("title").next()?.prefix().map(str::to_string))
+    }
+}
+
+/// An `<item>` of the manifest.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Item {
+    pub id: String,
+    /// Path relative to the package document, as written.
+    pub href: String,
+    pub media_type: String,
+    pub properties: Option<String>,
+    pub fallback: Option<String>,
+    pub media_overlay: Option<String>,
+    /// Other attributes.
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Item {
+    pub fn new(
+        id: impl Into<String>,
+        href: impl Into<String>,
+        media_type: impl Into<String>,
+    ) -> Self {
+        Self {
+            id: id.into(),
+            href: href.into(),
+            media_type: media_type.into(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub attributes: Vec<(String, String)>,
+    pub items: Vec<Item>,
+    /// Children that are not items, written after them.
+    pub other: Vec<Node>,
+}
+
+impl Manifest {
+    fn from_element(element: Element) -> Self {
+        let mut manifest = Manifest {
+            attributes: element.attributes,
+            ..Default::default()
+        };
+        for child in element.children {
+            match child {
+                Node::Element(mut e) if e.local_name() == "item" => {
+                    manifest.items.push(Item {
+                        id: e.remove_attribute("id").unwrap_or_default(),
+                        href: e.remove_attribute("href").unwrap_or_default(),
+                        media_type: e.remove_attribute("media-type").unwrap_or_default(),
+                        properties: e.remove_attribute("properties"),
+                        fallback: e.remove_attribute("fallback"),
+                        media_overlay: e.remove_attribute("media-overlay"),
+                        attributes: e.attributes,
+                    });
+                }
+                Node::Text(text) if text.trim().is_empty() => {}
+                other => manifest.other.push(other),
+            }
+        }
+        manifest
+    }
+
+    fn to_element(&self, prefix: &str) -> Element {
+        let items = self.items.iter().map(|item| {
+            let mut attributes = vec![
+                ("id".to_string(), item.id.clone()),
+                ("href".to_string(), item.href.clone()),
+                ("media-type".to_string(), item.media_type.clone()),
+            ];
+            push_optional(&mut attributes, "properties", &item.properties);
+            push_optional(&mut attributes, "fallback", &item.fallback);
+            push_optional(&mut attributes, "media-overlay", &item.media_overlay);
+            attributes.extend(item.attributes.iter().cloned());
+            Node::Element(Element {
+                name: format!("{}item", prefix),
+                attributes,
+                children: Vec::new(),
+            })
+        });
+        Element {
+            name: format!("{}manifest", prefix),
+            attributes: self.attributes.clone(),
+            children: items.chain(self.other.iter().cloned()).collect(),
+        }
+    }
+
+    pub fn item(&self, id: &str) -> Option<&Item> {
+        self.items.iter().find(|item| item.id == id)
+    }
+
+    pub fn item_mut(&mut self, id: &str) -> Option<&mut Item> {
+        self.items.iter_mut().find(|item| item.id == id)
+    }
+
+    /// Remove the first item with `id`.
+    pub fn remove(&mut self, id: &str) -> Option<Item> {
+        let index = self.items.iter().position(|item| item.id == id)?;
+        Some(self.items.remove(index))
+    }
+}
+
+/// An `<itemref>` of the spine.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ItemRef {
+    pub idref: String,
+    /// `linear` as written (`yes` or `no`), if given.
+    pub linear: Option<String>,
+    pub properties: Option<String>,
+    /// Other attributes.
+    pub attributes: Vec<(String, String)>,
+}
+
+impl ItemRef {
+    pub fn new(idref: impl Into<String>) -> Self {
+        Self {
+            idref: idref.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the item is part of the reading order (`linear` is not `no`).
+    pub fn is_linear(&self) -> bool {
+        self.linear.as_deref().map(str::trim) != Some("no")
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Spine {
+    /// Id of the NCX item.
+    pub toc: Option<String>,
+    /// Other attributes, such as `page-progression-direction`.
+    pub attributes: Vec<(String, String)>,
+    pub itemrefs: Vec<ItemRef>,
+    /// Children that are not itemrefs, written after them.
+    pub other: Vec<Node>,
+}
+
+impl Spine {
+    fn from_element(mut element: Element) -> Self {
+        let mut spine = Spine {
+            toc: element.remove_attribute("toc"),
+            attributes: element.attributes,
+            ..Default::default()
+        };
+        for child in element.children {
+            match child {
+                Node::Element(mut e) if e.local_name() == "itemref" => {
+                    spine.itemrefs.push(ItemRef {
+                        idref: e.remove_attribute("idref").unwrap_or_default(),
+                        linear: e.remove_attribute("linear"),
+                        properties: e.remove_attribute("properties"),
+                        attributes: e.attributes,
+                    });
+                }
+                Node::Text(text) if text.trim().is_empty() => {}
+                other => spine.other.push(other),
+            }
+        }
+        spine
+    }
+
+    fn to_element(&self, prefix: &str) -> Element {
+        let mut attributes = Vec::new();
+        push_optional(&mut attributes, "toc", &self.toc);
+        attributes.extend(self.attributes.iter().cloned());
+
+        let itemrefs = self.itemrefs.iter().map(|itemref| {
+            let mut attributes = vec![("idref".to_string(), itemref.idref.clone())];
+            push_optional(&mut attributes, "linear", &itemref.linear);
+            push_optional(&mut attributes, "properties", &itemref.properties);
+            attributes.extend(itemref.attributes.iter().cloned());
+            Node::Element(Element {
+                name: format!("{}itemref", prefix),
+                attributes,
+                children: Vec::new(),
+            })
+        });
+        Element {
+            name: format!("{}spine", prefix),
+            attributes,
+            children: itemrefs.chain(self.other.iter().cloned()).collect(),
+        }
+    }
+}
+
+/// A `<reference>` of the EPUB 2 guide.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reference {
+    /// The `type` attribute (`cover`, `toc`, `text`, ...).
+    pub kind: String,
+    pub title: Option<String>,
+    pub href: String,
+    /// Other attributes.
+    pub attributes: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Guide {
+    pub attributes: Vec<(String, String)>,
+    pub references: Vec<Reference>,
+    /// Children that are not references, written after them.
+    pub other: Vec<Node>,
+}
+
+impl Guide {
+    fn from_element(element: Element) -> Self {
+        let mut guide = Guide {
+            attributes: element.attributes,
+            ..Default::default()
+        };
+        for child in element.children {
+            match child {
+                Node::Element(mut e) if e.local_name() == "reference" => {
+                    guide.references.push(Reference {
+                        kind: e.remove_attribute("type").unwrap_or_default(),
+                        title: e.remove_attribute("title"),
+                        href: e.remove_attribute("href").unwrap_or_default(),
+                        attributes: e.attributes,
+                    });
+                }
+                Node::Text(text) if text.trim().is_empty() => {}
+                other => guide.other.push(other),
+            }
+        }
+        guide
+    }
+
+    fn to_element(&self, prefix: &str) -> Element {
+        let references = self.references.iter().map(|reference| {
+            let mut attributes = vec![("type".to_string(), reference.kind.clone())];
+            push_optional(&mut attributes, "title", &reference.title);
+            attributes.push(("href".to_string(), reference.href.clone()));
+            attributes.extend(reference.attributes.iter().cloned());
+            Node::Element(Element {
+                name: format!("{}reference", prefix),
+                attributes,
+                children: Vec::new(),
+            })
+        });
+        Element {
+            name: format!("{}guide", prefix),
+            attributes: self.attributes.clone(),
+            children: references.chain(self.other.iter().cloned()).collect(),
+        }
+    }
+}
+
+/// A parsed XML document: its root element and the text around it.
+struct Document {
+    prolog: String,
+    root: Element,
+    epilog: String,
+    indent: String,
+}
+
+fn parse_document(xml: &str) -> Result<Document> {
+    let mut reader = Reader::from_str(xml);
+    let config = reader.config_mut();
+    config.check_end_names = false;
+    config.allow_unmatched_ends = true;
+
+    let mut prolog_end = None;
+    let mut indent = None;
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root = None;
+    loop {
+        let start = reader.buffer_position() as usize;
+        let event = reader.read_event()?;
+        let node = match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                prolog_end.get_or_insert(start);
+                let element = start_element(e);
+                if let Event::Empty(_) = event {
+                    Node::Element(element)
+                } else {
+                    stack.push(element);
+                    continue;
+                }
+            }
+            Event::End(_) => match stack.pop() {
+                Some(element) => Node::Element(element),
+                None => continue,
+            },
+            Event::Text(ref e) => match e.unescape() {
+                Ok(text) => {
+                    let text = text.into_owned();
+                    // The whitespace before the first child of the root is
+                    // one level of indentation
+                    if stack.len() == 1 && indent.is_none() && text.trim().is_empty() {
+                        indent = text.rsplit('\n').next().map(str::to_string);
+                    }
+                    Node::Text(text)
+                }
+                Err(_) => Node::Escaped(String::from_utf8_lossy(e).into_owned()),
+            },
+            Event::CData(ref e) => Node::CData(String::from_utf8_lossy(e).into_owned()),
+            Event::Comment(ref e) => Node::Comment(String::from_utf8_lossy(e).into_owned()),
+            Event::PI(ref e) => {
+                Node::ProcessingInstruction(String::from_utf8_lossy(e).into_owned())
+            }
+            Event::Eof => break,
+            Event::Decl(_) | Event::DocType(_) => continue,
+        };
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => {
+                if let Node::Element(element) = node {
+                    root = Some((element, reader.buffer_position() as usize));
+                    break;
+                }
+            }
+        }
+    }
+
+    // An unclosed root still counts, closed at the end of the document
+    while let Some(element) = stack.pop() {
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(Node::Element(element)),
+            None => root = Some((element, xml.len())),
+        }
+    }
+
+    let (root, end) =
+        root.ok_or_else(|| KindleFixError::InvalidEpub("XML document has no root element".into()))?;
+    let prolog_end = prolog_end.unwrap_or(0);
+    Ok(Document {
+        prolog: xml[..prolog_end].to_string(),
+        root,
+        epilog: xml[end..].to_string(),
+        indent: indent
+            .filter(|i| !i.is_empty())
+            .unwrap_or_else(|| "  ".to_string()),
+    })
+}
+
+fn start_element(start: &BytesStart) -> Element {
+    let attributes = start
+        .attributes()
+        .with_checks(false)
+        .flatten()
+        .map(|attr| {
+            let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+            let value = attr
+                .unescape_value()
+                .map(|v| v.into_owned())
+                .unwrap_or_else(|_| unescape_known(&String::from_utf8_lossy(&attr.value)));
+            (key, value)
+        })
+        .collect();
+    Element {
+        name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+        attributes,
+        children: Vec::new(),
+    }
+}
+
+/// Write `element` at nesting `depth`. Text and mixed content are written
+/// as they are.
+fn write_element(out: &mut String, element: &Element, indent: &str, depth: usize) {
+    out.push('<');
+    out.push_str(&element.name);
+    for (key, value) in &element.attributes {
+        out.push_str(&format!(" {}=\"{}\"", key, escape_attribute(value)));
+    }
+
+    let children: Vec<&Node> = element
+        .children
+        .iter()
+        .filter(|child| !matches!(child, Node::Text(t) if t.is_empty()))
+        .collect();
+    if children.is_empty() {
+        out.push_str("/>");
+        return;
+    }
+    out.push('>');
+
+    // Elements holding only elements and comments get one child per line
+    let structured = children
+        .iter()
+        .any(|child| !matches!(child, Node::Text(_) | Node::CData(_) | Node::Escaped(_)))
+        && !children.iter().any(|child| {
+            matches!(child, Node::Text(t) | Node::CData(t) | Node::Escaped(t) if !t.trim().is_empty())
+        });
+    for child in &children {
+        if structured {
+            if matches!(child, Node::Text(_)) {
+                continue;
+            }
+            out.push('\n');
+            out.push_str(&indent.repeat(depth + 1));
+        }
+        write_node(out, child, indent, depth + 1, structured);
+    }
+    if structured {
+        out.push('\n');
+        out.push_str(&indent.repeat(depth));
+    }
+
+    out.push_str("</");
+    out.push_str(&element.name);
+    out.push('>');
+}
+
+fn write_node(out: &mut String, node: &Node, indent: &str, depth: usize, pretty: bool) {
+    match node {
+        Node::Element(e) if pretty => write_element(out, e, indent, depth),
+        // Inside text, a child element keeps everything on one line
+        Node::Element(e) => write_element(out, e, "", 0),
+        Node::Text(text) => out.push_str(&quick_xml::escape::partial_escape(text)),
+        Node::Escaped(text) => out.push_str(text),
+        Node::CData(text) => out.push_str(&format!("<![CDATA[{}]]>", text)),
+        Node::Comment(text) => out.push_str(&format!("<!--{}-->", text)),
+        Node::ProcessingInstruction(text) => out.push_str(&format!("<?{}?>", text)),
+    }
+}
+
+/// Expand the entities of `text` that XML defines, keeping the others as
+/// they are written.
+fn unescape_known(text: &str) -> String {
+    ENTITY
+        .replace_all(text, |caps: &regex::Captures| {
+            quick_xml::escape::unescape(&caps[0])
+                .map(|t| t.into_owned())
+                .unwrap_or_else(|_| caps[0].to_string())
+        })
+        .into_owned()
+}
+
+/// Escape an attribute value, leaving the entity references it still holds
+/// as they are.
+fn escape_attribute(value: &str) -> String {
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('"', "&quot;")
+    };
+
+    let mut out = String::with_capacity(value.len());
+    let mut last = 0;
+    for entity in ENTITY.find_iter(value) {
+        out.push_str(&escape(&value[last..entity.start()]));
+        out.push_str(entity.as_str());
+        last = entity.end();
+    }
+    out.push_str(&escape(&value[last..]));
+    out
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn attribute<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+fn set_attribute(attributes: &mut Vec<(String, String)>, name: String, value: String) {
+    match attributes.iter_mut().find(|(key, _)| *key == name) {
+        Some((_, old)) => *old = value,
+        None => attributes.push((name, value)),
+    }
+}
+
+fn remove_attribute(attributes: &mut Vec<(String, String)>, name: &str) -> Option<String> {
+    let index = attributes.iter().position(|(key, _)| key == name)?;
+    Some(attributes.remove(index).1)
+}
+
+fn push_optional(attributes: &mut Vec<(String, String)>, name: &str, value: &Option<String>) {
+    if let Some(value) = value {
+        attributes.push((name.to_string(), value.clone()));
+    }
+}
@@ -1,7 +1,13 @@
 use std::collections::HashMap;
 use std::io::{Cursor, Read};
 
+use encoding_rs::UTF_8;
+use zip::{CompressionMethod, DateTime};
+
 use crate::error::Result;
+use crate::types::{Diagnostic, DiagnosticCode};
+
+use super::charset::{detect_encoding, try_decode_text};
 
 const TEXT_EXTENSIONS: &[&str] = &[
     "html", "xhtml", "htm", "xml", "svg", "css", "opf", "ncx",
@@ -9,9 +15,36 @@ const TEXT_EXTENSIONS: &[&str] = &[
 
 const TEXT_FILENAMES: &[&str] = &["mimetype"];
 
+/// How an entry was stored in the original archive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryMetadata {
+    pub name: String,
+    pub is_dir: bool,
+    pub compression: CompressionMethod,
+    pub last_modified: Option<DateTime>,
+    pub unix_mode: Option<u32>,
+}
+
+/// The entries of an EPUB archive in their original order, used by
+/// `EpubWriter` to reproduce the archive layout.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EpubManifest {
+    pub entries: Vec<EntryMetadata>,
+    pub comment: Vec<u8>,
+}
+
+impl EpubManifest {
+    pub fn get(&self, name: &str) -> Option<&EntryMetadata> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+}
+
 pub struct EpubReader {
     text_files: HashMap<String, String>,
     binary_files: HashMap<String, Vec<u8>>,
+    manifest: EpubManifest,
+    transcoded: Vec<(String, String)>,
+    warnings: Vec<Diagnostic>,
 }
 
 impl EpubReader {
@@ -21,19 +54,64 @@ impl EpubReader {
 
         let mut text_files = HashMap::new();
         let mut binary_files = HashMap::new();
+        let mut transcoded = Vec::new();
+        let mut warnings = Vec::new();
+        let mut manifest = EpubManifest {
+            entries: Vec::with_capacity(archive.len()),
+            comment: archive.comment().to_vec(),
+        };
 
         for i in 0..archive.len() {
             let mut entry = archive.by_index(i)?;
             let name = entry.name().to_string();
 
+            manifest.entries.push(EntryMetadata {
+                name: name.clone(),
+                is_dir: entry.is_dir(),
+                compression: entry.compression(),
+                last_modified: entry.last_modified(),
+                unix_mode: entry.unix_mode(),
+            });
+
             if entry.is_dir() {
                 continue;
             }
 
             if is_text_file(&name) {
-                let mut content = String::new();
-                entry.read_to_string(&mut content)?;
-                text_files.insert(name, content);
+                let mut bytes = Vec::new();
+                entry.read_to_end(&mut bytes)?;
+                match try_decode_text(&bytes) {
+                    Some(decoded) => {
+                        if decoded.guessed {
+                            let message = format!(
+                                "Guessed that {} is stored as {}; check it for garbled text.",
+                                name,
+                                decoded.encoding.name()
+                            );
+                            warnings.push(
+                                Diagnostic::warning(DiagnosticCode::GuessedCharset, message)
+                                    .with_file(name.clone()),
+                            );
+                        }
+                        if decoded.encoding != UTF_8 {
+                            transcoded.push((name.clone(), decoded.encoding.name().to_string()));
+                        }
+                        text_files.insert(name, decoded.text);
+                    }
+                    None => {
+                        // Keep the original bytes rather than garbling the file
+                        let message = format!(
+                            "Could not decode {} as {}; it was left unchanged.",
+                            name,
+                            detect_encoding(&bytes).name()
+                        );
+                        warnings.push(
+                            Diagnostic::warning(DiagnosticCode::UndecodableText, message)
+                                .with_file(name.clone()),
+                        );
+                        binary_files.insert(name, bytes);
+                    }
+                }
             } else {
                 let mut content = Vec::new();
                 entry.read_to_end(&mut content)?;
@@ -44,6 +122,9 @@ impl EpubReader {
         Ok(Self {
             text_files,
             binary_files,
+            manifest,
+            transcoded,
+            warnings,
         })
     }
 
@@ -59,6 +140,23 @@ impl EpubReader {
         &self.binary_files
     }
 
+    pub fn manifest(&self) -> &EpubManifest {
+        &self.manifest
+    }
+
+    /// Text files that were not UTF-8, with the charset they were decoded from.
+    /// Their content has been transcoded to UTF-8.
+    pub fn transcoded(&self) -> &[(String, String)] {
+        &self.transcoded
+    }
+
+    /// Problems met while reading, such as text files whose charset had to
+    /// be guessed, or that could not be decoded and were kept as binary
+    /// files instead.
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
     pub fn into_parts(self) -> (HashMap<String, String>, HashMap<String, Vec<u8>>) {
         (self.text_files, self.binary_files)
     }
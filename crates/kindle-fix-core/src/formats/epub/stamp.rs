@@ -0,0 +1,65 @@
+//! The optional marker a run leaves in the OPF of the books it fixed:
+//!
+//! ```xml
+//! <meta name="kindle-file-fix:version" content="0.1.0"/>
+//! <meta name="kindle-file-fix:fixes" content="body_id,encoding,language,stray_img"/>
+//! ```
+//!
+//! Later runs read it back and only apply the fixes it does not list.
+
+use serde::Serialize;
+
+use crate::formats::epub::package::{Element, Node, Package};
+
+/// `name` of the meta recording the version that fixed the book.
+pub const VERSION_META: &str = "kindle-file-fix:version";
+
+/// `name` of the meta listing the ids of the fixes that ran on the book.
+pub const FIXES_META: &str = "kindle-file-fix:fixes";
+
+/// What an earlier run recorded in a book.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Stamp {
+    pub version: String,
+    pub fixes: Vec<String>,
+}
+
+/// Read the stamp of an OPF, if it has one.
+pub fn read_stamp(opf: &str) -> Option<Stamp> {
+    let package = Package::parse(opf).ok()?;
+    let metadata = &package.metadata;
+
+    Some(Stamp {
+        version: metadata.meta(VERSION_META)?.to_string(),
+        fixes: metadata
+            .meta(FIXES_META)?
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+/// Replace the stamp of an OPF with `stamp`, or add one at the end of its
+/// metadata. Returns `None` when the OPF is not a package document.
+pub fn write_stamp(opf: &str, stamp: &Stamp) -> Option<String> {
+    let mut package = Package::parse(opf).ok()?;
+
+    let metadata = &mut package.metadata;
+    metadata.children.retain(|child| {
+        !matches!(child, Node::Element(e) if e.local_name() == "meta"
+            && matches!(e.attribute("name"), Some(VERSION_META | FIXES_META)))
+    });
+    for (name, content) in [
+        (VERSION_META, stamp.version.clone()),
+        (FIXES_META, stamp.fixes.join(",")),
+    ] {
+        let mut meta = Element::new(format!("{}meta", package.prefix));
+        meta.set_attribute("name", name);
+        meta.set_attribute("content", content);
+        metadata.children.push(Node::Element(meta));
+    }
+
+    Some(package.to_xml())
+}
@@ -2,10 +2,89 @@ use std::collections::HashMap;
 use std::io::{Cursor, Write};
 
 use zip::write::SimpleFileOptions;
-use zip::ZipWriter;
+use zip::{CompressionMethod, DateTime, ZipWriter};
 
 use crate::error::Result;
 
+use super::reader::{EntryMetadata, EpubManifest};
+
+/// Deflate level used for every entry in reproducible mode.
+const REPRODUCIBLE_LEVEL: i64 = 6;
+
+/// Order of the entries in a reproducible archive. `mimetype` always comes first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EntryOrder {
+    /// Keep the order of the original archive; new files follow in name order.
+    #[default]
+    Original,
+    /// Sort every entry by name.
+    Sorted,
+}
+
+/// Settings for byte-for-byte reproducible output: entry metadata from the
+/// original archive is replaced by fixed values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reproducible {
+    pub order: EntryOrder,
+    /// Modification time written on every entry.
+    pub timestamp: DateTime,
+}
+
+impl Reproducible {
+    /// Settings for `order`, timestamped with `SOURCE_DATE_EPOCH` when it is
+    /// set and with the earliest ZIP date (1980-01-01) otherwise.
+    pub fn from_env(order: EntryOrder) -> Self {
+        let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .map(zip_timestamp)
+            .unwrap_or_default();
+        Self { order, timestamp }
+    }
+
+    fn options(&self, is_dir: bool) -> SimpleFileOptions {
+        SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(Some(REPRODUCIBLE_LEVEL))
+            .last_modified_time(self.timestamp)
+            .unix_permissions(if is_dir { 0o755 } else { 0o644 })
+    }
+}
+
+/// Convert a Unix timestamp to a ZIP timestamp, clamped to the 1980–2107
+/// range ZIP can store.
+pub fn zip_timestamp(epoch: i64) -> DateTime {
+    let days = epoch.div_euclid(86_400);
+    let secs = epoch.rem_euclid(86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    if year < 1980 {
+        return DateTime::default();
+    }
+    if year > 2107 {
+        return DateTime::from_date_and_time(2107, 12, 31, 23, 59, 58).unwrap_or_default();
+    }
+    DateTime::from_date_and_time(
+        year as u16,
+        month as u8,
+        day as u8,
+        (secs / 3600) as u8,
+        (secs / 60 % 60) as u8,
+        (secs % 60) as u8,
+    )
+    .unwrap_or_default()
+}
+
 pub struct EpubWriter;
 
 impl EpubWriter {
@@ -13,36 +92,117 @@ impl EpubWriter {
         text_files: &HashMap<String, String>,
         binary_files: &HashMap<String, Vec<u8>>,
     ) -> Result<Vec<u8>> {
-        let buf = Cursor::new(Vec::new());
-        let mut zip = ZipWriter::new(buf);
-
-        let stored = SimpleFileOptions::default()
-            .compression_method(zip::CompressionMethod::Stored);
-        let deflated = SimpleFileOptions::default()
-            .compression_method(zip::CompressionMethod::Deflated);
-
-        // Write mimetype FIRST, uncompressed (EPUB spec requirement)
-        if let Some(mimetype) = text_files.get("mimetype") {
-            zip.start_file("mimetype", stored)?;
-            zip.write_all(mimetype.as_bytes())?;
-        }
+        Self::write_with_manifest(text_files, binary_files, &EpubManifest::default())
+    }
 
-        // Write remaining text files
-        for (name, content) in text_files {
-            if name == "mimetype" {
-                continue;
-            }
-            zip.start_file(name.as_str(), deflated)?;
-            zip.write_all(content.as_bytes())?;
-        }
+    /// Write an EPUB that follows the layout of the original archive: entries
+    /// listed in `manifest` keep their order, timestamps, permissions and
+    /// compression. Files that were not in the original archive are appended
+    /// in name order.
+    pub fn write_with_manifest(
+        text_files: &HashMap<String, String>,
+        binary_files: &HashMap<String, Vec<u8>>,
+        manifest: &EpubManifest,
+    ) -> Result<Vec<u8>> {
+        write_archive(text_files, binary_files, manifest, None)
+    }
 
-        // Write binary files
-        for (name, content) in binary_files {
-            zip.start_file(name.as_str(), deflated)?;
-            zip.write_all(content)?;
+    /// Write an EPUB whose bytes only depend on the file contents, the
+    /// entry names and `settings`.
+    pub fn write_reproducible(
+        text_files: &HashMap<String, String>,
+        binary_files: &HashMap<String, Vec<u8>>,
+        manifest: &EpubManifest,
+        settings: &Reproducible,
+    ) -> Result<Vec<u8>> {
+        write_archive(text_files, binary_files, manifest, Some(settings))
+    }
+}
+
+fn write_archive(
+    text_files: &HashMap<String, String>,
+    binary_files: &HashMap<String, Vec<u8>>,
+    manifest: &EpubManifest,
+    reproducible: Option<&Reproducible>,
+) -> Result<Vec<u8>> {
+    let buf = Cursor::new(Vec::new());
+    let mut zip = ZipWriter::new(buf);
+
+    let content = |name: &str| -> Option<&[u8]> {
+        text_files
+            .get(name)
+            .map(|text| text.as_bytes())
+            .or_else(|| binary_files.get(name).map(Vec::as_slice))
+    };
+    let options = |name: &str, is_dir: bool| match reproducible {
+        Some(settings) => settings.options(is_dir),
+        None => manifest.get(name).map(entry_options).unwrap_or_else(|| {
+            SimpleFileOptions::default().compression_method(CompressionMethod::Deflated)
+        }),
+    };
+
+    // Write mimetype FIRST, uncompressed (EPUB spec requirement)
+    if let Some(mimetype) = text_files.get("mimetype") {
+        zip.start_file(
+            "mimetype",
+            options("mimetype", false)
+                .compression_method(CompressionMethod::Stored)
+                .compression_level(None),
+        )?;
+        zip.write_all(mimetype.as_bytes())?;
+    }
+
+    // Original entries first, in their original order, then new files
+    let mut new_files: Vec<&str> = text_files
+        .keys()
+        .chain(binary_files.keys())
+        .map(String::as_str)
+        .filter(|name| manifest.get(name).is_none())
+        .collect();
+    new_files.sort_unstable();
+
+    let mut entries: Vec<(&str, bool)> = manifest
+        .entries
+        .iter()
+        .filter(|entry| entry.is_dir || content(&entry.name).is_some())
+        .map(|entry| (entry.name.as_str(), entry.is_dir))
+        .chain(new_files.into_iter().map(|name| (name, false)))
+        .filter(|(name, _)| *name != "mimetype")
+        .collect();
+    if reproducible.is_some_and(|settings| settings.order == EntryOrder::Sorted) {
+        entries.sort_unstable();
+    }
+
+    for (name, is_dir) in entries {
+        if is_dir {
+            zip.add_directory(name, options(name, true))?;
+        } else {
+            zip.start_file(name, options(name, false))?;
+            zip.write_all(content(name).unwrap_or_default())?;
         }
+    }
 
-        let cursor = zip.finish()?;
-        Ok(cursor.into_inner())
+    if !manifest.comment.is_empty() {
+        zip.set_raw_comment(manifest.comment.clone().into_boxed_slice());
+    }
+
+    let cursor = zip.finish()?;
+    Ok(cursor.into_inner())
+}
+
+/// Options reproducing how `entry` was stored. Compression methods other than
+/// stored are written as deflate, the only other method EPUB allows.
+fn entry_options(entry: &EntryMetadata) -> SimpleFileOptions {
+    let compression = match entry.compression {
+        CompressionMethod::Stored => CompressionMethod::Stored,
+        _ => CompressionMethod::Deflated,
+    };
+    let mut options = SimpleFileOptions::default().compression_method(compression);
+    if let Some(time) = entry.last_modified {
+        options = options.last_modified_time(time);
+    }
+    if let Some(mode) = entry.unix_mode {
+        options = options.unix_permissions(mode);
     }
+    options
 }
@@ -0,0 +1,147 @@
+use crate::error::Result;
+use crate::types::FileFormat;
+
+use super::exth::{Exth, EXTH_AUTHOR, EXTH_KF8_BOUNDARY, EXTH_LANGUAGE, EXTH_UPDATED_TITLE};
+use super::header::{decode_text, MobiHeader, PalmDocHeader, NULL_INDEX};
+use super::palmdb::{invalid, record_slice, PalmDb};
+
+/// File version written into the MOBI header of KF8 (AZW3) sections.
+pub const KF8_FILE_VERSION: u32 = 8;
+
+/// Which Kindle formats a PalmDB book contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MobiKind {
+    /// Legacy MOBI 6 content only.
+    Mobi6,
+    /// KF8 content only, as in standalone AZW3 files.
+    Kf8,
+    /// A MOBI 6 section followed by a KF8 section starting at `kf8_record`.
+    Combo { kf8_record: usize },
+}
+
+impl MobiKind {
+    /// Classify a PalmDB book from its first MOBI header, its file version and
+    /// the KF8 boundary EXTH record. Returns `None` if the data is not a MOBI book.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.len() <= 68 || &data[60..68] != b"BOOKMOBI" {
+            return None;
+        }
+        let record0 = record_slice(data, 0)?;
+        let mobi = MobiHeader::parse(record0).ok()?;
+        if mobi.file_version >= KF8_FILE_VERSION {
+            return Some(MobiKind::Kf8);
+        }
+
+        let boundary = if mobi.has_exth() {
+            Exth::parse(&record0[mobi.end_offset()..])
+                .ok()
+                .and_then(|exth| exth.get_u32(EXTH_KF8_BOUNDARY))
+        } else {
+            None
+        };
+
+        match boundary {
+            Some(index) if index != NULL_INDEX && is_kf8_header(data, index as usize) => {
+                Some(MobiKind::Combo {
+                    kf8_record: index as usize,
+                })
+            }
+            _ => Some(MobiKind::Mobi6),
+        }
+    }
+
+    /// The format reported for books of this kind. Combo files keep the legacy
+    /// MOBI container, so they are reported as MOBI.
+    pub fn format(&self) -> FileFormat {
+        match self {
+            MobiKind::Kf8 => FileFormat::Azw3,
+            MobiKind::Mobi6 | MobiKind::Combo { .. } => FileFormat::Mobi,
+        }
+    }
+
+    pub fn has_kf8(&self) -> bool {
+        !matches!(self, MobiKind::Mobi6)
+    }
+}
+
+fn is_kf8_header(data: &[u8], index: usize) -> bool {
+    let boundary_ok = index
+        .checked_sub(1)
+        .and_then(|i| record_slice(data, i))
+        .is_some_and(|r| r.starts_with(b"BOUNDARY"));
+    let header_ok = record_slice(data, index)
+        .and_then(|r| MobiHeader::parse(r).ok())
+        .is_some_and(|h| h.file_version >= KF8_FILE_VERSION);
+    boundary_ok && header_ok
+}
+
+/// A parsed MOBI book: the PalmDB container plus the headers found in record 0.
+#[derive(Debug, Clone)]
+pub struct MobiBook {
+    pub kind: MobiKind,
+    pub palmdb: PalmDb,
+    pub palmdoc: PalmDocHeader,
+    pub mobi: MobiHeader,
+    pub exth: Option<Exth>,
+    pub full_name: String,
+}
+
+impl MobiBook {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let palmdb = PalmDb::parse(data)?;
+        if &palmdb.header.type_code != b"BOOK" || &palmdb.header.creator != b"MOBI" {
+            return Err(invalid("PalmDB type is not BOOKMOBI"));
+        }
+
+        let record0 = palmdb
+            .record(0)
+            .ok_or_else(|| invalid("PalmDB has no records"))?;
+        let palmdoc = PalmDocHeader::parse(record0)?;
+        let mobi = MobiHeader::parse(record0)?;
+
+        let exth = if mobi.has_exth() {
+            Some(Exth::parse(&record0[mobi.end_offset()..])?)
+        } else {
+            None
+        };
+
+        let name_start = mobi.full_name_offset as usize;
+        let name_end = name_start + mobi.full_name_length as usize;
+        let full_name = record0
+            .get(name_start..name_end)
+            .map(|bytes| decode_text(bytes, mobi.text_encoding))
+            .unwrap_or_default();
+
+        Ok(Self {
+            kind: MobiKind::detect(data).unwrap_or(MobiKind::Mobi6),
+            palmdb,
+            palmdoc,
+            mobi,
+            exth,
+            full_name,
+        })
+    }
+
+    /// Read a string-valued EXTH record, decoded with the book's text encoding.
+    pub fn exth_string(&self, kind: u32) -> Option<String> {
+        let record = self.exth.as_ref()?.get(kind)?;
+        Some(decode_text(&record.data, self.mobi.text_encoding))
+    }
+
+    pub fn title(&self) -> String {
+        self.exth_string(EXTH_UPDATED_TITLE)
+            .unwrap_or_else(|| self.full_name.clone())
+    }
+
+    pub fn author(&self) -> Option<String> {
+        self.exth_string(EXTH_AUTHOR)
+    }
+
+    pub fn language(&self) -> Option<String> {
+        self.exth_string(EXTH_LANGUAGE)
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.palmdoc.encryption != 0
+    }
+}
@@ -0,0 +1,172 @@
+use crate::error::Result;
+
+use super::palmdb::{invalid, read_u16, read_u32};
+
+/// Decompress a PalmDOC (LZ77 variant) text record.
+pub fn palmdoc_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() * 2);
+    let mut i = 0;
+
+    while i < data.len() {
+        let byte = data[i];
+        i += 1;
+        match byte {
+            0x01..=0x08 => {
+                let end = i + byte as usize;
+                let literal = data
+                    .get(i..end)
+                    .ok_or_else(|| invalid("PalmDOC literal run extends past end of record"))?;
+                out.extend_from_slice(literal);
+                i = end;
+            }
+            0x00 | 0x09..=0x7F => out.push(byte),
+            0x80..=0xBF => {
+                let next = *data
+                    .get(i)
+                    .ok_or_else(|| invalid("PalmDOC back-reference is truncated"))?;
+                i += 1;
+                let pair = (u16::from(byte) << 8 | u16::from(next)) & 0x3FFF;
+                let distance = (pair >> 3) as usize;
+                let length = (pair & 0x07) as usize + 3;
+                if distance == 0 || distance > out.len() {
+                    return Err(invalid(
+                        "PalmDOC back-reference points before start of text",
+                    ));
+                }
+                let start = out.len() - distance;
+                for k in 0..length {
+                    out.push(out[start + k]);
+                }
+            }
+            0xC0..=0xFF => {
+                out.push(b' ');
+                out.push(byte ^ 0x80);
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Decoder for MOBI HUFF/CDIC compressed text records.
+pub struct HuffCdicReader {
+    dict1: Vec<(u32, bool, u64)>,
+    min_codes: Vec<u64>,
+    max_codes: Vec<u64>,
+    dictionary: Vec<Option<(Vec<u8>, bool)>>,
+}
+
+impl HuffCdicReader {
+    /// Build a decoder from the HUFF record and the CDIC records that follow it.
+    pub fn new(huff: &[u8], cdics: &[&[u8]]) -> Result<Self> {
+        if huff.get(0..8) != Some(b"HUFF\x00\x00\x00\x18".as_slice()) {
+            return Err(invalid("HUFF record has an invalid header"));
+        }
+        let table1 = read_u32(huff, 8)? as usize;
+        let table2 = read_u32(huff, 12)? as usize;
+
+        let mut dict1 = Vec::with_capacity(256);
+        for i in 0..256 {
+            let value = read_u32(huff, table1 + i * 4)?;
+            let code_len = value & 0x1F;
+            let terminal = value & 0x80 != 0;
+            if code_len == 0 {
+                return Err(invalid("HUFF table contains a zero-length code"));
+            }
+            let max_code = ((u64::from(value >> 8) + 1) << (32 - code_len)) - 1;
+            dict1.push((code_len, terminal, max_code));
+        }
+
+        let mut min_codes = vec![0u64];
+        let mut max_codes = vec![u64::from(u32::MAX)];
+        for code_len in 1..=32u32 {
+            let offset = table2 + (code_len as usize - 1) * 8;
+            let min = u64::from(read_u32(huff, offset)?);
+            let max = u64::from(read_u32(huff, offset + 4)?);
+            min_codes.push(min << (32 - code_len));
+            max_codes.push(((max + 1) << (32 - code_len)) - 1);
+        }
+
+        let mut dictionary = Vec::new();
+        for cdic in cdics {
+            if cdic.get(0..8) != Some(b"CDIC\x00\x00\x00\x10".as_slice()) {
+                return Err(invalid("CDIC record has an invalid header"));
+            }
+            let phrases = read_u32(cdic, 8)? as usize;
+            let bits = read_u32(cdic, 12)?;
+            let count = (1usize << bits.min(31)).min(phrases.saturating_sub(dictionary.len()));
+            for i in 0..count {
+                let offset = read_u16(cdic, 16 + i * 2)? as usize;
+                let length = read_u16(cdic, 16 + offset)?;
+                let start = 18 + offset;
+                let end = start + (length & 0x7FFF) as usize;
+                let phrase = cdic
+                    .get(start..end)
+                    .ok_or_else(|| invalid("CDIC phrase extends past end of record"))?;
+                dictionary.push(Some((phrase.to_vec(), length & 0x8000 != 0)));
+            }
+        }
+
+        Ok(Self {
+            dict1,
+            min_codes,
+            max_codes,
+            dictionary,
+        })
+    }
+
+    /// Decompress a single text record.
+    pub fn unpack(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut padded = data.to_vec();
+        padded.extend_from_slice(&[0; 12]);
+        let word = |pos: usize| -> u64 {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&padded[pos..pos + 8]);
+            u64::from_be_bytes(bytes)
+        };
+
+        let mut bits_left = data.len() as i64 * 8;
+        let mut pos = 0;
+        let mut x = word(pos);
+        let mut n: i64 = 32;
+        let mut out = Vec::new();
+
+        loop {
+            if n <= 0 {
+                pos += 4;
+                x = word(pos);
+                n += 32;
+            }
+            let code = (x >> n as u32) & 0xFFFF_FFFF;
+            let (mut code_len, terminal, mut max_code) = self.dict1[(code >> 24) as usize];
+            if !terminal {
+                while code_len < 32 && code < self.min_codes[code_len as usize] {
+                    code_len += 1;
+                }
+                max_code = self.max_codes[code_len as usize];
+            }
+            n -= i64::from(code_len);
+            bits_left -= i64::from(code_len);
+            if bits_left < 0 {
+                break;
+            }
+
+            let index = (max_code.wrapping_sub(code) >> (32 - code_len)) as usize;
+            let entry = self
+                .dictionary
+                .get_mut(index)
+                .ok_or_else(|| invalid("HUFF code refers to a missing CDIC phrase"))?
+                .take()
+                .ok_or_else(|| invalid("CDIC phrase refers to itself"))?;
+            let phrase = if entry.1 {
+                entry.0
+            } else {
+                self.unpack(&entry.0)?
+            };
+            out.extend_from_slice(&phrase);
+            self.dictionary[index] = Some((phrase, true));
+        }
+
+        Ok(out)
+    }
+}
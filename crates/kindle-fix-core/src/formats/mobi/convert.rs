@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+
+use quick_xml::escape::escape;
+use regex::Regex;
+
+use crate::error::Result;
+use crate::formats::epub::writer::EpubWriter;
+
+use super::book::{MobiBook, MobiKind};
+use super::exth::{EXTH_ASIN, EXTH_COVER_OFFSET, EXTH_DESCRIPTION, EXTH_PUBLISHER};
+use super::kf8::read_kf8;
+use super::mobi6::read_mobi6;
+use super::section::MobiSection;
+
+/// An entry of the rebuilt table of contents. `href` is relative to the
+/// directory holding the XHTML parts.
+#[derive(Debug, Clone)]
+pub(crate) struct TocEntry {
+    pub label: String,
+    pub href: String,
+}
+
+/// The content recovered from a MOBI or KF8 section, ready to be packaged.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConvertedBook {
+    pub parts: Vec<(String, String)>,
+    pub styles: Vec<(String, String)>,
+    pub svgs: Vec<(String, String)>,
+    pub toc: Vec<TocEntry>,
+}
+
+pub(crate) struct Image {
+    pub name: String,
+    pub media_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// Image records of a book, addressed by the 1-based index used by MOBI
+/// `recindex` attributes and KF8 `kindle:embed` links.
+#[derive(Default)]
+pub(crate) struct ImageTable {
+    images: Vec<Option<Image>>,
+}
+
+impl ImageTable {
+    fn from_book(book: &MobiBook) -> Self {
+        let first = book.mobi.first_image_index;
+        if first == super::header::NULL_INDEX {
+            return Self::default();
+        }
+        let end = match book.kind {
+            MobiKind::Combo { kf8_record } => kf8_record.saturating_sub(1),
+            _ => book.palmdb.records.len(),
+        };
+
+        let images = (first as usize..end)
+            .map(|i| {
+                let data = book.palmdb.record(i)?;
+                let (ext, media_type) = image_type(data)?;
+                Some(Image {
+                    name: format!("image{:05}.{}", i + 1 - first as usize, ext),
+                    media_type,
+                    data: data.to_vec(),
+                })
+            })
+            .collect();
+        Self { images }
+    }
+
+    pub fn name(&self, index: usize) -> Option<&str> {
+        let image = self.images.get(index.checked_sub(1)?)?.as_ref()?;
+        Some(&image.name)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (usize, &Image)> {
+        self.images
+            .iter()
+            .enumerate()
+            .filter_map(|(i, img)| img.as_ref().map(|img| (i + 1, img)))
+    }
+}
+
+fn image_type(data: &[u8]) -> Option<(&'static str, &'static str)> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(("jpg", "image/jpeg"))
+    } else if data.starts_with(b"\x89PNG") {
+        Some(("png", "image/png"))
+    } else if data.starts_with(b"GIF8") {
+        Some(("gif", "image/gif"))
+    } else if data.starts_with(b"BM") {
+        Some(("bmp", "image/bmp"))
+    } else {
+        None
+    }
+}
+
+/// Convert a MOBI, combo or AZW3 book into an EPUB 2 package. For combo files
+/// the KF8 section is used, since it carries the richer markup.
+pub fn convert_to_epub(book: &MobiBook) -> Result<Vec<u8>> {
+    let images = ImageTable::from_book(book);
+    let content = match book.kind {
+        MobiKind::Mobi6 => read_mobi6(&MobiSection::new(&book.palmdb, 0)?, &images)?,
+        MobiKind::Kf8 => read_kf8(&MobiSection::new(&book.palmdb, 0)?, &images)?,
+        MobiKind::Combo { kf8_record } => {
+            read_kf8(&MobiSection::new(&book.palmdb, kf8_record)?, &images)?
+        }
+    };
+    let is_kf8 = book.kind.has_kf8();
+    let title = book.title();
+
+    let mut text_files = HashMap::new();
+    let mut binary_files = HashMap::new();
+    text_files.insert("mimetype".to_string(), "application/epub+zip".to_string());
+    text_files.insert(
+        "META-INF/container.xml".to_string(),
+        CONTAINER_XML.to_string(),
+    );
+
+    let mut manifest = vec![manifest_item("ncx", "toc.ncx", "application/x-dtbncx+xml")];
+    let mut spine = Vec::new();
+
+    for (name, markup) in &content.parts {
+        let id = name.trim_end_matches(".xhtml");
+        let document = if is_kf8 {
+            markup.clone()
+        } else {
+            wrap_document(&title, markup)
+        };
+        text_files.insert(format!("OEBPS/text/{}", name), document);
+        manifest.push(manifest_item(
+            id,
+            &format!("text/{}", name),
+            "application/xhtml+xml",
+        ));
+        spine.push(format!(r#"    <itemref idref="{}"/>"#, id));
+    }
+
+    for (name, css) in &content.styles {
+        text_files.insert(format!("OEBPS/styles/{}", name), css.clone());
+        manifest.push(manifest_item(
+            &format!("style-{}", name.trim_end_matches(".css")),
+            &format!("styles/{}", name),
+            "text/css",
+        ));
+    }
+
+    for (name, svg) in &content.svgs {
+        text_files.insert(format!("OEBPS/images/{}", name), svg.clone());
+        manifest.push(manifest_item(
+            name.trim_end_matches(".svg"),
+            &format!("images/{}", name),
+            "image/svg+xml",
+        ));
+    }
+
+    let cover = book
+        .exth
+        .as_ref()
+        .and_then(|e| e.get_u32(EXTH_COVER_OFFSET))
+        .map(|offset| offset as usize + 1);
+    let mut cover_meta = String::new();
+    for (index, image) in images.iter() {
+        binary_files.insert(format!("OEBPS/images/{}", image.name), image.data.clone());
+        let id = if Some(index) == cover {
+            cover_meta = r#"    <meta name="cover" content="cover-image"/>"#.to_string() + "\n";
+            "cover-image".to_string()
+        } else {
+            image.name.replace('.', "-")
+        };
+        manifest.push(manifest_item(
+            &id,
+            &format!("images/{}", image.name),
+            image.media_type,
+        ));
+    }
+
+    let identifier = book
+        .exth_string(EXTH_ASIN)
+        .unwrap_or_else(|| format!("mobi-{}", book.mobi.unique_id));
+
+    let mut metadata = vec![format!("    <dc:title>{}</dc:title>", escape(&title))];
+    if let Some(author) = book.author() {
+        metadata.push(format!(
+            r#"    <dc:creator opf:role="aut">{}</dc:creator>"#,
+            escape(&author)
+        ));
+    }
+    if let Some(language) = book.language() {
+        metadata.push(format!(
+            "    <dc:language>{}</dc:language>",
+            escape(&language)
+        ));
+    }
+    metadata.push(format!(
+        r#"    <dc:identifier id="BookId">{}</dc:identifier>"#,
+        escape(&identifier)
+    ));
+    if let Some(publisher) = book.exth_string(EXTH_PUBLISHER) {
+        metadata.push(format!(
+            "    <dc:publisher>{}</dc:publisher>",
+            escape(&publisher)
+        ));
+    }
+    if let Some(description) = book.exth_string(EXTH_DESCRIPTION) {
+        metadata.push(format!(
+            "    <dc:description>{}</dc:description>",
+            escape(&description)
+        ));
+    }
+
+    let opf = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="BookId">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+{}
+{}  </metadata>
+  <manifest>
+{}
+  </manifest>
+  <spine toc="ncx">
+{}
+  </spine>
+</package>
+"#,
+        metadata.join("\n"),
+        cover_meta,
+        manifest.join("\n"),
+        spine.join("\n"),
+    );
+    text_files.insert("OEBPS/content.opf".to_string(), opf);
+
+    let toc = if content.toc.is_empty() {
+        fallback_toc(&content.parts)
+    } else {
+        content.toc.clone()
+    };
+    text_files.insert(
+        "OEBPS/toc.ncx".to_string(),
+        build_ncx(&title, &identifier, &toc),
+    );
+
+    EpubWriter::write(&text_files, &binary_files)
+}
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+fn manifest_item(id: &str, href: &str, media_type: &str) -> String {
+    format!(
+        r#"    <item id="{}" href="{}" media-type="{}"/>"#,
+        escape(id),
+        escape(href),
+        media_type
+    )
+}
+
+fn wrap_document(title: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head>
+<title>{}</title>
+</head>
+<body>
+{}
+</body>
+</html>
+"#,
+        escape(title),
+        body.trim()
+    )
+}
+
+/// One entry per part, labelled with its first heading when it has one.
+fn fallback_toc(parts: &[(String, String)]) -> Vec<TocEntry> {
+    let heading = Regex::new(r"(?is)<h[1-3]\b[^>]*>(.*?)</h[1-3]>").expect("valid regex");
+    let tags = Regex::new(r"<[^>]*>").expect("valid regex");
+
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, (name, markup))| {
+            let label = heading
+                .captures(markup)
+                .map(|caps| tags.replace_all(&caps[1], "").trim().to_string())
+                .filter(|label| !label.is_empty())
+                .unwrap_or_else(|| format!("Part {}", i + 1));
+            TocEntry {
+                label: quick_xml::escape::unescape(&label)
+                    .map(|l| l.to_string())
+                    .unwrap_or(label),
+                href: name.clone(),
+            }
+        })
+        .collect()
+}
+
+fn build_ncx(title: &str, identifier: &str, toc: &[TocEntry]) -> String {
+    let nav_points: Vec<String> = toc
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            format!(
+                r#"    <navPoint id="navpoint{0}" playOrder="{0}">
+      <navLabel><text>{1}</text></navLabel>
+      <content src="text/{2}"/>
+    </navPoint>"#,
+                i + 1,
+                escape(&entry.label),
+                escape(&entry.href)
+            )
+        })
+        .collect();
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+  <head>
+    <meta name="dtb:uid" content="{}"/>
+  </head>
+  <docTitle><text>{}</text></docTitle>
+  <navMap>
+{}
+  </navMap>
+</ncx>
+"#,
+        escape(identifier),
+        escape(title),
+        nav_points.join("\n")
+    )
+}
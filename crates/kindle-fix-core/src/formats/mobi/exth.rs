@@ -0,0 +1,124 @@
+use crate::error::Result;
+
+use super::palmdb::{invalid, read_u32};
+
+pub const EXTH_AUTHOR: u32 = 100;
+pub const EXTH_PUBLISHER: u32 = 101;
+pub const EXTH_DESCRIPTION: u32 = 103;
+pub const EXTH_ISBN: u32 = 104;
+pub const EXTH_SUBJECT: u32 = 105;
+pub const EXTH_PUBLISHED_DATE: u32 = 106;
+pub const EXTH_ASIN: u32 = 113;
+pub const EXTH_KF8_BOUNDARY: u32 = 121;
+pub const EXTH_COVER_OFFSET: u32 = 201;
+pub const EXTH_THUMB_OFFSET: u32 = 202;
+pub const EXTH_CDE_TYPE: u32 = 501;
+pub const EXTH_UPDATED_TITLE: u32 = 503;
+pub const EXTH_LANGUAGE: u32 = 524;
+
+/// A single EXTH metadata record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExthRecord {
+    pub kind: u32,
+    pub data: Vec<u8>,
+}
+
+impl ExthRecord {
+    /// Interpret the record payload as a big-endian integer, if it has 4 bytes.
+    pub fn as_u32(&self) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.as_slice().try_into().ok()?;
+        Some(u32::from_be_bytes(bytes))
+    }
+}
+
+/// The EXTH block of extended metadata that follows the MOBI header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Exth {
+    pub records: Vec<ExthRecord>,
+}
+
+impl Exth {
+    /// Parse an EXTH block starting at the beginning of `data`.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.get(0..4) != Some(b"EXTH".as_slice()) {
+            return Err(invalid("EXTH block is missing its identifier"));
+        }
+
+        let count = read_u32(data, 8)? as usize;
+        // The count comes from the file; every record takes at least 8 bytes
+        let mut records = Vec::with_capacity(count.min(data.len() / 8));
+        let mut offset = 12;
+
+        for _ in 0..count {
+            let kind = read_u32(data, offset)?;
+            let length = read_u32(data, offset + 4)? as usize;
+            if length < 8 || offset + length > data.len() {
+                return Err(invalid(&format!(
+                    "EXTH record {} has an invalid length",
+                    kind
+                )));
+            }
+            records.push(ExthRecord {
+                kind,
+                data: data[offset + 8..offset + length].to_vec(),
+            });
+            offset += length;
+        }
+
+        Ok(Self { records })
+    }
+
+    pub fn get(&self, kind: u32) -> Option<&ExthRecord> {
+        self.records.iter().find(|r| r.kind == kind)
+    }
+
+    pub fn get_all(&self, kind: u32) -> impl Iterator<Item = &ExthRecord> {
+        self.records.iter().filter(move |r| r.kind == kind)
+    }
+
+    pub fn get_u32(&self, kind: u32) -> Option<u32> {
+        self.get(kind).and_then(ExthRecord::as_u32)
+    }
+
+    /// Replace every record of `kind` with a single record holding `data`,
+    /// kept at the position of the first one. Appends it if there was none.
+    pub fn set(&mut self, kind: u32, data: Vec<u8>) {
+        match self.records.iter().position(|r| r.kind == kind) {
+            Some(first) => {
+                self.records[first].data = data;
+                let mut seen = false;
+                self.records.retain(|r| {
+                    if r.kind != kind {
+                        return true;
+                    }
+                    let keep = !seen;
+                    seen = true;
+                    keep
+                });
+            }
+            None => self.records.push(ExthRecord { kind, data }),
+        }
+    }
+
+    pub fn remove(&mut self, kind: u32) {
+        self.records.retain(|r| r.kind != kind);
+    }
+
+    /// Serialize the block, padded to a multiple of four bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let body_len: usize = self.records.iter().map(|r| r.data.len() + 8).sum();
+        let padding = (4 - body_len % 4) % 4;
+
+        let mut out = Vec::with_capacity(12 + body_len + padding);
+        out.extend_from_slice(b"EXTH");
+        out.extend_from_slice(&((12 + body_len) as u32).to_be_bytes());
+        out.extend_from_slice(&(self.records.len() as u32).to_be_bytes());
+        for record in &self.records {
+            out.extend_from_slice(&record.kind.to_be_bytes());
+            out.extend_from_slice(&((record.data.len() + 8) as u32).to_be_bytes());
+            out.extend_from_slice(&record.data);
+        }
+        out.extend(std::iter::repeat_n(0, padding));
+        out
+    }
+}
@@ -0,0 +1,177 @@
+use encoding_rs::WINDOWS_1252;
+
+use crate::error::Result;
+
+use super::palmdb::{invalid, read_u16, read_u32};
+
+/// Value used by MOBI headers for "no such record".
+pub const NULL_INDEX: u32 = 0xFFFF_FFFF;
+
+/// Offset of the MOBI header inside record 0, right after the PalmDOC header.
+pub const MOBI_HEADER_OFFSET: usize = 16;
+
+/// EXTH flag bit signalling that an EXTH block follows the MOBI header.
+pub const EXTH_FLAG: u32 = 0x40;
+
+pub const ENCODING_CP1252: u32 = 1252;
+pub const ENCODING_UTF8: u32 = 65001;
+
+/// Text compression schemes used by PalmDOC and MOBI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    PalmDoc,
+    Huffman,
+    Unknown(u16),
+}
+
+impl From<u16> for Compression {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => Compression::None,
+            2 => Compression::PalmDoc,
+            17480 => Compression::Huffman,
+            other => Compression::Unknown(other),
+        }
+    }
+}
+
+/// The 16-byte PalmDOC header at the start of record 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PalmDocHeader {
+    pub compression: Compression,
+    pub text_length: u32,
+    pub text_record_count: u16,
+    pub text_record_size: u16,
+    pub encryption: u16,
+}
+
+impl PalmDocHeader {
+    pub fn parse(record0: &[u8]) -> Result<Self> {
+        Ok(Self {
+            compression: Compression::from(read_u16(record0, 0)?),
+            text_length: read_u32(record0, 4)?,
+            text_record_count: read_u16(record0, 8)?,
+            text_record_size: read_u16(record0, 10)?,
+            encryption: read_u16(record0, 12)?,
+        })
+    }
+}
+
+/// The MOBI header that follows the PalmDOC header in record 0.
+///
+/// Offsets are relative to the start of record 0. Fields that lie past the
+/// declared header length fall back to their "absent" values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MobiHeader {
+    pub header_length: u32,
+    pub mobi_type: u32,
+    pub text_encoding: u32,
+    pub unique_id: u32,
+    pub file_version: u32,
+    pub first_non_book_index: u32,
+    pub full_name_offset: u32,
+    pub full_name_length: u32,
+    pub locale: u32,
+    pub min_version: u32,
+    pub first_image_index: u32,
+    pub huffman_record_offset: u32,
+    pub huffman_record_count: u32,
+    pub exth_flags: u32,
+    pub extra_data_flags: u16,
+    pub ncx_index: u32,
+    /// KF8 only: record holding the FDST flow table.
+    pub fdst_index: u32,
+    /// KF8 only: first record of the fragment (chunk) index.
+    pub fragment_index: u32,
+    /// KF8 only: first record of the skeleton index.
+    pub skeleton_index: u32,
+    /// KF8 only: first record of the guide index.
+    pub guide_index: u32,
+}
+
+impl MobiHeader {
+    pub fn parse(record0: &[u8]) -> Result<Self> {
+        let base = MOBI_HEADER_OFFSET;
+        if record0.get(base..base + 4) != Some(b"MOBI".as_slice()) {
+            return Err(invalid("record 0 does not contain a MOBI header"));
+        }
+
+        let header_length = read_u32(record0, base + 4)?;
+        let header_end = base + header_length as usize;
+        if record0.len() < header_end {
+            return Err(invalid("MOBI header extends past end of record 0"));
+        }
+
+        let field = |offset: usize, absent: u32| -> Result<u32> {
+            if offset + 4 <= header_end {
+                read_u32(record0, offset)
+            } else {
+                Ok(absent)
+            }
+        };
+
+        Ok(Self {
+            header_length,
+            mobi_type: field(0x18, 0)?,
+            text_encoding: field(0x1C, ENCODING_CP1252)?,
+            unique_id: field(0x20, 0)?,
+            file_version: field(0x24, 0)?,
+            first_non_book_index: field(0x50, NULL_INDEX)?,
+            full_name_offset: field(0x54, 0)?,
+            full_name_length: field(0x58, 0)?,
+            locale: field(0x5C, 0)?,
+            min_version: field(0x68, 0)?,
+            first_image_index: field(0x6C, NULL_INDEX)?,
+            huffman_record_offset: field(0x70, 0)?,
+            huffman_record_count: field(0x74, 0)?,
+            exth_flags: field(0x80, 0)?,
+            extra_data_flags: (field(0xF0, 0)? & 0xFFFF) as u16,
+            ncx_index: field(0xF4, NULL_INDEX)?,
+            fdst_index: field(0xC0, NULL_INDEX)?,
+            fragment_index: field(0xF8, NULL_INDEX)?,
+            skeleton_index: field(0xFC, NULL_INDEX)?,
+            guide_index: field(0x104, NULL_INDEX)?,
+        })
+    }
+
+    pub fn has_exth(&self) -> bool {
+        self.exth_flags & EXTH_FLAG != 0
+    }
+
+    /// Offset of the first byte after the MOBI header within record 0.
+    pub fn end_offset(&self) -> usize {
+        MOBI_HEADER_OFFSET + self.header_length as usize
+    }
+}
+
+/// Decode a MOBI string according to the header's text encoding.
+pub(crate) fn decode_text(bytes: &[u8], encoding: u32) -> String {
+    if encoding == ENCODING_UTF8 {
+        String::from_utf8_lossy(bytes).to_string()
+    } else {
+        WINDOWS_1252
+            .decode_without_bom_handling(bytes)
+            .0
+            .into_owned()
+    }
+}
+
+/// Encode a string for a MOBI header. CP1252 books get `?` for characters
+/// that cannot be represented.
+pub(crate) fn encode_text(text: &str, encoding: u32) -> Vec<u8> {
+    if encoding == ENCODING_UTF8 {
+        return text.as_bytes().to_vec();
+    }
+    let mut out = Vec::with_capacity(text.len());
+    let mut buf = [0u8; 4];
+    for c in text.chars() {
+        let (bytes, _, unmappable) = WINDOWS_1252.encode(c.encode_utf8(&mut buf));
+        if unmappable {
+            out.push(b'?');
+        } else {
+            out.extend_from_slice(&bytes);
+        }
+    }
+    out
+}
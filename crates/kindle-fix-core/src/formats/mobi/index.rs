@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use crate::error::Result;
+
+use super::header::decode_text;
+use super::palmdb::{invalid, read_u16, read_u32};
+use super::section::MobiSection;
+
+/// One entry of a MOBI index (INDX) table: its identifier and tag values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub ident: String,
+    pub tags: HashMap<u8, Vec<u32>>,
+}
+
+impl IndexEntry {
+    pub fn tag(&self, tag: u8, position: usize) -> Option<u32> {
+        self.tags.get(&tag).and_then(|v| v.get(position)).copied()
+    }
+}
+
+/// A decoded MOBI index: its entries in order plus the CNCX string table.
+#[derive(Debug, Clone, Default)]
+pub struct MobiIndex {
+    pub entries: Vec<IndexEntry>,
+    pub strings: HashMap<u32, String>,
+}
+
+impl MobiIndex {
+    /// Read the index whose header record is `index` within `section`.
+    pub fn read(section: &MobiSection<'_>, index: u32) -> Result<Self> {
+        let encoding = section.mobi.text_encoding;
+        let header_data = section
+            .record(index)
+            .ok_or_else(|| invalid("missing INDX header record"))?;
+        let header = IndxHeader::parse(header_data)?;
+        let tagx = header_data
+            .get(header.length as usize..)
+            .ok_or_else(|| invalid("INDX header length extends past end of record"))?;
+        let tagx = TagX::parse(tagx)?;
+        // Record numbers are sums of values read from the file
+        let record_number = |offset: Option<u32>| {
+            offset
+                .and_then(|offset| index.checked_add(offset))
+                .ok_or_else(|| invalid("INDX record number is out of range"))
+        };
+
+        let mut strings = HashMap::new();
+        for i in 0..header.cncx_count {
+            let record = section
+                .record(record_number(header.count.checked_add(1 + i))?)
+                .ok_or_else(|| invalid("missing CNCX record"))?;
+            read_cncx(record, i * 0x10000, encoding, &mut strings);
+        }
+
+        let mut entries = Vec::new();
+        for i in 0..header.count {
+            let data = section
+                .record(record_number(Some(1 + i))?)
+                .ok_or_else(|| invalid("missing INDX entry record"))?;
+            let record_header = IndxHeader::parse(data)?;
+            let idxt = record_header.start as usize;
+            if data.get(idxt..idxt + 4) != Some(b"IDXT".as_slice()) {
+                return Err(invalid("INDX record is missing its IDXT table"));
+            }
+
+            // The count comes from the file, so it only sizes what the table can hold
+            let table_len = (data.len() - idxt - 4) / 2;
+            let mut positions =
+                Vec::with_capacity((record_header.count as usize).min(table_len) + 1);
+            for j in 0..record_header.count as usize {
+                positions.push(read_u16(data, idxt + 4 + j * 2)? as usize);
+            }
+            positions.push(idxt);
+
+            for window in positions.windows(2) {
+                let entry = data
+                    .get(window[0]..window[1])
+                    .ok_or_else(|| invalid("INDX entry extends past end of record"))?;
+                let ident_len = *entry.first().unwrap_or(&0) as usize;
+                let ident = entry
+                    .get(1..1 + ident_len)
+                    .ok_or_else(|| invalid("INDX entry identifier is truncated"))?;
+                entries.push(IndexEntry {
+                    ident: decode_text(ident, encoding),
+                    tags: tagx.read_tags(&entry[1 + ident_len..]),
+                });
+            }
+        }
+
+        Ok(Self { entries, strings })
+    }
+
+    pub fn string(&self, offset: u32) -> Option<&str> {
+        self.strings.get(&offset).map(String::as_str)
+    }
+}
+
+struct IndxHeader {
+    length: u32,
+    start: u32,
+    count: u32,
+    cncx_count: u32,
+}
+
+impl IndxHeader {
+    fn parse(data: &[u8]) -> Result<Self> {
+        if data.get(0..4) != Some(b"INDX".as_slice()) {
+            return Err(invalid("index record is missing its INDX identifier"));
+        }
+        Ok(Self {
+            length: read_u32(data, 4)?,
+            start: read_u32(data, 20)?,
+            count: read_u32(data, 24)?,
+            cncx_count: read_u32(data, 52)?,
+        })
+    }
+}
+
+struct TagDefinition {
+    tag: u8,
+    values_per_entry: u8,
+    bitmask: u8,
+    end_of_control_byte: bool,
+}
+
+/// The TAGX table describing how index entries encode their tags.
+struct TagX {
+    control_byte_count: usize,
+    tags: Vec<TagDefinition>,
+}
+
+impl TagX {
+    fn parse(data: &[u8]) -> Result<Self> {
+        if data.get(0..4) != Some(b"TAGX".as_slice()) {
+            return Err(invalid("INDX header is missing its TAGX table"));
+        }
+        let length = read_u32(data, 4)? as usize;
+        let control_byte_count = read_u32(data, 8)? as usize;
+        let table = data
+            .get(12..length)
+            .ok_or_else(|| invalid("TAGX table extends past end of record"))?;
+        let tags = table
+            .chunks_exact(4)
+            .map(|c| TagDefinition {
+                tag: c[0],
+                values_per_entry: c[1],
+                bitmask: c[2],
+                end_of_control_byte: c[3] == 1,
+            })
+            .collect();
+        Ok(Self {
+            control_byte_count,
+            tags,
+        })
+    }
+
+    fn read_tags(&self, data: &[u8]) -> HashMap<u8, Vec<u32>> {
+        let control_bytes = &data[..self.control_byte_count.min(data.len())];
+        let mut rest = &data[control_bytes.len()..];
+        let mut control_index = 0;
+
+        // First pass: work out how many values (or bytes) each present tag has.
+        let mut present = Vec::new();
+        for def in &self.tags {
+            if def.end_of_control_byte {
+                control_index += 1;
+                continue;
+            }
+            let Some(&control) = control_bytes.get(control_index) else {
+                break;
+            };
+            let mut value = control & def.bitmask;
+            if value == 0 {
+                continue;
+            }
+            if value == def.bitmask {
+                if def.bitmask.count_ones() > 1 {
+                    let (byte_count, consumed) = forward_varint(rest);
+                    rest = &rest[consumed..];
+                    present.push((def, None, Some(byte_count as usize)));
+                } else {
+                    present.push((def, Some(1), None));
+                }
+            } else {
+                let mut mask = def.bitmask;
+                while mask & 1 == 0 {
+                    mask >>= 1;
+                    value >>= 1;
+                }
+                present.push((def, Some(value as usize), None));
+            }
+        }
+
+        // Second pass: decode the values themselves.
+        let mut tags = HashMap::new();
+        for (def, value_count, byte_count) in present {
+            let mut values = Vec::new();
+            if let Some(count) = value_count {
+                for _ in 0..count * def.values_per_entry as usize {
+                    let (value, consumed) = forward_varint(rest);
+                    rest = &rest[consumed..];
+                    values.push(value);
+                }
+            } else if let Some(total) = byte_count {
+                let mut consumed_total = 0;
+                while consumed_total < total && !rest.is_empty() {
+                    let (value, consumed) = forward_varint(rest);
+                    rest = &rest[consumed..];
+                    consumed_total += consumed;
+                    values.push(value);
+                }
+            }
+            tags.insert(def.tag, values);
+        }
+        tags
+    }
+}
+
+/// Read a forward variable-width integer; the last byte has its high bit set.
+fn forward_varint(data: &[u8]) -> (u32, usize) {
+    let mut value = 0u32;
+    let mut consumed = 0;
+    for &byte in data.iter().take(4) {
+        consumed += 1;
+        value = (value << 7) | u32::from(byte & 0x7F);
+        if byte & 0x80 != 0 {
+            break;
+        }
+    }
+    (value, consumed)
+}
+
+fn read_cncx(record: &[u8], base: u32, encoding: u32, strings: &mut HashMap<u32, String>) {
+    let mut pos = 0;
+    while pos < record.len() {
+        let (length, consumed) = forward_varint(&record[pos..]);
+        if consumed == 0 {
+            break;
+        }
+        let start = pos + consumed;
+        let end = (start + length as usize).min(record.len());
+        if length > 0 {
+            strings.insert(
+                base + pos as u32,
+                decode_text(&record[start..end], encoding),
+            );
+        }
+        pos = end;
+    }
+}
@@ -0,0 +1,255 @@
+use regex::bytes::Regex as BytesRegex;
+use regex::{Captures, Regex};
+
+use crate::error::Result;
+
+use super::convert::{ConvertedBook, ImageTable, TocEntry};
+use super::header::{decode_text, NULL_INDEX};
+use super::index::MobiIndex;
+use super::palmdb::read_u32;
+use super::section::MobiSection;
+
+struct Skeleton {
+    fragment_count: usize,
+    start: usize,
+    length: usize,
+}
+
+struct Fragment {
+    insert_position: usize,
+    length: usize,
+}
+
+struct Part {
+    filename: String,
+    start: usize,
+    end: usize,
+    data: Vec<u8>,
+}
+
+/// Rebuild the XHTML files, stylesheets and table of contents of a KF8 section.
+pub(crate) fn read_kf8(section: &MobiSection<'_>, images: &ImageTable) -> Result<ConvertedBook> {
+    let text = section.text()?;
+    let encoding = section.mobi.text_encoding;
+    let flows = split_flows(section, &text);
+    let main_flow = flows.first().cloned().unwrap_or_default();
+
+    let skeletons = read_skeletons(section)?;
+    let fragments = read_fragments(section)?;
+    let parts = assemble_parts(&main_flow, &skeletons, &fragments);
+
+    let id_regex = BytesRegex::new(r#"\bid\s*=\s*["']([^"']+)["']"#).expect("valid regex");
+    let resolve = |fid: usize, offset: usize| -> Option<String> {
+        let position = fragments.get(fid)?.insert_position + offset;
+        resolve_position(&parts, &id_regex, position)
+    };
+
+    let pos_regex =
+        Regex::new(r"kindle:pos:fid:([0-9A-Va-v]{4}):off:([0-9A-Va-v]{10})").expect("valid regex");
+    let aid_regex = Regex::new(r#"\s+aid\s*=\s*["'][^"']*["']"#).expect("valid regex");
+
+    let mut book = ConvertedBook::default();
+    for (i, flow) in flows.iter().enumerate().skip(1) {
+        let content = rewrite_resources(&decode_text(flow, encoding), images, "../images/");
+        if content.trim_start().starts_with('<') {
+            book.svgs.push((format!("flow{:04}.svg", i), content));
+        } else {
+            book.styles.push((format!("flow{:04}.css", i), content));
+        }
+    }
+
+    for part in &parts {
+        let html = decode_text(&part.data, encoding);
+        let html = pos_regex.replace_all(&html, |caps: &Captures| {
+            resolve(base32(&caps[1]), base32(&caps[2])).unwrap_or_default()
+        });
+        let html = aid_regex.replace_all(&html, "");
+        let html = rewrite_resources(&html, images, "../images/");
+        book.parts.push((part.filename.clone(), html));
+    }
+
+    if let Some(ncx) = section
+        .absolute(section.mobi.ncx_index)
+        .and_then(|_| MobiIndex::read(section, section.mobi.ncx_index).ok())
+    {
+        for entry in &ncx.entries {
+            let label = entry.tag(3, 0).and_then(|o| ncx.string(o));
+            let href = match (entry.tag(6, 0), entry.tag(6, 1)) {
+                (Some(fid), Some(off)) => resolve(fid as usize, off as usize),
+                _ => entry
+                    .tag(1, 0)
+                    .and_then(|pos| resolve_position(&parts, &id_regex, pos as usize)),
+            };
+            if let (Some(label), Some(href)) = (label, href) {
+                book.toc.push(TocEntry {
+                    label: label.to_string(),
+                    href,
+                });
+            }
+        }
+    }
+
+    Ok(book)
+}
+
+/// Split the decompressed text into flows using the FDST table. Flow 0 holds
+/// the XHTML; the others hold stylesheets and SVG images.
+fn split_flows(section: &MobiSection<'_>, text: &[u8]) -> Vec<Vec<u8>> {
+    let table = section
+        .record(section.mobi.fdst_index)
+        .filter(|r| r.starts_with(b"FDST"));
+
+    let mut flows = Vec::new();
+    if let Some(fdst) = table {
+        let count = read_u32(fdst, 8).unwrap_or(0) as usize;
+        for i in 0..count {
+            let start = read_u32(fdst, 12 + i * 8).unwrap_or(0) as usize;
+            let end = read_u32(fdst, 16 + i * 8).unwrap_or(0) as usize;
+            if let Some(flow) = text.get(start..end.min(text.len())) {
+                flows.push(flow.to_vec());
+            }
+        }
+    }
+    if flows.is_empty() {
+        flows.push(text.to_vec());
+    }
+    flows
+}
+
+fn read_skeletons(section: &MobiSection<'_>) -> Result<Vec<Skeleton>> {
+    if section.mobi.skeleton_index == NULL_INDEX {
+        return Ok(Vec::new());
+    }
+    let index = MobiIndex::read(section, section.mobi.skeleton_index)?;
+    Ok(index
+        .entries
+        .iter()
+        .map(|e| Skeleton {
+            fragment_count: e.tag(1, 0).unwrap_or(0) as usize,
+            start: e.tag(6, 0).unwrap_or(0) as usize,
+            length: e.tag(6, 1).unwrap_or(0) as usize,
+        })
+        .collect())
+}
+
+fn read_fragments(section: &MobiSection<'_>) -> Result<Vec<Fragment>> {
+    if section.mobi.fragment_index == NULL_INDEX {
+        return Ok(Vec::new());
+    }
+    let index = MobiIndex::read(section, section.mobi.fragment_index)?;
+    Ok(index
+        .entries
+        .iter()
+        .map(|e| Fragment {
+            insert_position: e.ident.trim().parse().unwrap_or(0),
+            length: e.tag(6, 1).unwrap_or(0) as usize,
+        })
+        .collect())
+}
+
+/// Insert every fragment into its skeleton to recreate the original files.
+fn assemble_parts(text: &[u8], skeletons: &[Skeleton], fragments: &[Fragment]) -> Vec<Part> {
+    if skeletons.is_empty() {
+        return vec![Part {
+            filename: "part0000.xhtml".to_string(),
+            start: 0,
+            end: text.len(),
+            data: text.to_vec(),
+        }];
+    }
+
+    let mut parts = Vec::with_capacity(skeletons.len());
+    let mut fragment_iter = fragments.iter();
+
+    for (i, skeleton) in skeletons.iter().enumerate() {
+        let skeleton_end = (skeleton.start + skeleton.length).min(text.len());
+        let mut data = text[skeleton.start.min(skeleton_end)..skeleton_end].to_vec();
+        let mut cursor = skeleton_end;
+
+        for fragment in fragment_iter.by_ref().take(skeleton.fragment_count) {
+            let end = (cursor + fragment.length).min(text.len());
+            let content = &text[cursor.min(end)..end];
+            let at = fragment
+                .insert_position
+                .saturating_sub(skeleton.start)
+                .min(data.len());
+            data.splice(at..at, content.iter().copied());
+            cursor = end;
+        }
+
+        parts.push(Part {
+            filename: format!("part{:04}.xhtml", i),
+            start: skeleton.start,
+            end: cursor,
+            data,
+        });
+    }
+    parts
+}
+
+/// Turn a position in the rebuilt text into `file#id`, using the closest `id`
+/// attribute at or before the position.
+fn resolve_position(parts: &[Part], id_regex: &BytesRegex, position: usize) -> Option<String> {
+    let part = parts
+        .iter()
+        .find(|p| p.start <= position && position < p.end)
+        .or_else(|| parts.last())?;
+    let block = &part.data;
+    let mut npos = position.saturating_sub(part.start).min(block.len());
+
+    let next_gt = block[npos..]
+        .iter()
+        .position(|&b| b == b'>')
+        .map(|p| p + npos);
+    let next_lt = block[npos..]
+        .iter()
+        .position(|&b| b == b'<')
+        .map(|p| p + npos);
+    if let Some(gt) = next_gt {
+        if next_lt == Some(npos) || next_lt.is_none_or(|lt| gt < lt) {
+            npos = gt + 1;
+        }
+    }
+
+    match id_regex.captures_iter(&block[..npos]).last() {
+        Some(caps) => Some(format!(
+            "{}#{}",
+            part.filename,
+            String::from_utf8_lossy(&caps[1])
+        )),
+        None => Some(part.filename.clone()),
+    }
+}
+
+/// Replace `kindle:embed` and `kindle:flow` references with relative paths.
+fn rewrite_resources(content: &str, images: &ImageTable, image_dir: &str) -> String {
+    let embed_regex =
+        Regex::new(r#"kindle:embed:([0-9A-Va-v]{4})(\?mime=[^"')\s]*)?"#).expect("valid regex");
+    let flow_regex =
+        Regex::new(r#"kindle:flow:([0-9A-Va-v]{4})\?mime=([^"')\s]*)"#).expect("valid regex");
+
+    let content = embed_regex.replace_all(content, |caps: &Captures| {
+        match images.name(base32(&caps[1])) {
+            Some(name) => format!("{}{}", image_dir, name),
+            None => String::new(),
+        }
+    });
+    flow_regex
+        .replace_all(&content, |caps: &Captures| {
+            let flow = base32(&caps[1]);
+            if &caps[2] == "text/css" {
+                format!("../styles/flow{:04}.css", flow)
+            } else {
+                format!("{}flow{:04}.svg", image_dir, flow)
+            }
+        })
+        .to_string()
+}
+
+/// Decode the base-32 numbers (digits `0-9A-V`) used in KF8 references.
+fn base32(digits: &str) -> usize {
+    digits
+        .chars()
+        .filter_map(|c| c.to_digit(32))
+        .fold(0, |acc, d| acc * 32 + d as usize)
+}
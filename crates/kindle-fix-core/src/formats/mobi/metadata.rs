@@ -0,0 +1,142 @@
+use crate::error::Result;
+
+use super::book::MobiKind;
+use super::exth::{
+    Exth, EXTH_ASIN, EXTH_AUTHOR, EXTH_CDE_TYPE, EXTH_COVER_OFFSET, EXTH_LANGUAGE,
+    EXTH_UPDATED_TITLE,
+};
+use super::header::{encode_text, MobiHeader, PalmDocHeader, EXTH_FLAG};
+use super::palmdb::{invalid, read_u32, PalmDb};
+
+/// Offsets of the record 0 fields rewritten along with the EXTH block.
+const FULL_NAME_OFFSET: usize = 0x54;
+const FULL_NAME_LENGTH: usize = 0x58;
+const EXTH_FLAGS: usize = 0x80;
+
+/// Metadata changes for a MOBI or AZW3 book. Fields left as `None` are kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataEdit {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// Language code stored in EXTH record 524.
+    pub language: Option<String>,
+    pub asin: Option<String>,
+    /// Kindle document type, such as `EBOK` or `PDOC`.
+    pub cde_type: Option<String>,
+    /// Cover image, as an offset from the first image record.
+    pub cover_offset: Option<u32>,
+}
+
+impl MetadataEdit {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Apply `edit` to a MOBI, combo or AZW3 book without touching its content.
+///
+/// Every MOBI header of the book is rewritten (both of them in combo files),
+/// and the record table is rebuilt since record 0 changes size.
+pub fn edit_metadata(data: &[u8], edit: &MetadataEdit) -> Result<Vec<u8>> {
+    let mut palmdb = PalmDb::parse(data)?;
+    let kind = MobiKind::detect(data).ok_or_else(|| invalid("not a MOBI book"))?;
+
+    let mut header_records = vec![0];
+    if let MobiKind::Combo { kf8_record } = kind {
+        header_records.push(kf8_record);
+    }
+
+    for index in header_records {
+        let record = palmdb
+            .records
+            .get_mut(index)
+            .ok_or_else(|| invalid(&format!("missing MOBI header record {}", index)))?;
+        record.data = rewrite_header_record(&record.data, edit)?;
+    }
+
+    Ok(palmdb.to_bytes())
+}
+
+/// Rebuild a record holding a MOBI header: the headers are kept, followed by
+/// the edited EXTH block and the full name.
+///
+/// Anything else in the record would be dropped, so encrypted books (whose
+/// DRM data lives there) and records with other data are refused.
+fn rewrite_header_record(record: &[u8], edit: &MetadataEdit) -> Result<Vec<u8>> {
+    let mobi = MobiHeader::parse(record)?;
+    if mobi.end_offset() < EXTH_FLAGS + 4 {
+        return Err(invalid("MOBI header is too short to hold EXTH metadata"));
+    }
+    if PalmDocHeader::parse(record)?.encryption != 0 {
+        return Err(invalid("cannot edit the metadata of an encrypted book"));
+    }
+
+    let mut exth = if mobi.has_exth() {
+        Exth::parse(&record[mobi.end_offset()..])?
+    } else {
+        Exth::default()
+    };
+    let encode = |text: &str| encode_text(text, mobi.text_encoding);
+
+    let name_start = mobi.full_name_offset as usize;
+    let name_end = name_start + mobi.full_name_length as usize;
+    let exth_end = if mobi.has_exth() {
+        mobi.end_offset() + read_u32(record, mobi.end_offset() + 4)? as usize
+    } else {
+        mobi.end_offset()
+    };
+    let unknown = record
+        .iter()
+        .enumerate()
+        .any(|(i, &byte)| byte != 0 && i >= exth_end && !(name_start..name_end).contains(&i));
+    if unknown {
+        return Err(invalid(
+            "MOBI header record holds data a metadata edit would drop",
+        ));
+    }
+    let mut full_name = record
+        .get(name_start..name_end)
+        .unwrap_or_default()
+        .to_vec();
+
+    if let Some(title) = &edit.title {
+        full_name = encode(title);
+        // Kindles prefer the updated title over the full name when present.
+        if exth.get(EXTH_UPDATED_TITLE).is_some() {
+            exth.set(EXTH_UPDATED_TITLE, full_name.clone());
+        }
+    }
+    if let Some(author) = &edit.author {
+        exth.set(EXTH_AUTHOR, encode(author));
+    }
+    if let Some(language) = &edit.language {
+        exth.set(EXTH_LANGUAGE, encode(language));
+    }
+    if let Some(asin) = &edit.asin {
+        exth.set(EXTH_ASIN, encode(asin));
+    }
+    if let Some(cde_type) = &edit.cde_type {
+        exth.set(EXTH_CDE_TYPE, encode(cde_type));
+    }
+    if let Some(offset) = edit.cover_offset {
+        exth.set(EXTH_COVER_OFFSET, offset.to_be_bytes().to_vec());
+    }
+
+    let mut out = record[..mobi.end_offset()].to_vec();
+    out.extend_from_slice(&exth.to_bytes());
+    let name_offset = out.len();
+    out.extend_from_slice(&full_name);
+    out.extend_from_slice(&[0, 0]);
+    while !out.len().is_multiple_of(4) {
+        out.push(0);
+    }
+
+    put_u32(&mut out, FULL_NAME_OFFSET, name_offset as u32);
+    put_u32(&mut out, FULL_NAME_LENGTH, full_name.len() as u32);
+    put_u32(&mut out, EXTH_FLAGS, mobi.exth_flags | EXTH_FLAG);
+    Ok(out)
+}
+
+fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
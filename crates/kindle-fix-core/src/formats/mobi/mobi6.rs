@@ -0,0 +1,145 @@
+use std::collections::{BTreeSet, HashMap};
+
+use regex::bytes::Regex as BytesRegex;
+use regex::{Captures, Regex};
+
+use crate::error::Result;
+
+use super::convert::{ConvertedBook, ImageTable, TocEntry};
+use super::header::{decode_text, NULL_INDEX};
+use super::index::MobiIndex;
+use super::section::MobiSection;
+use super::xhtml::normalize_markup;
+
+/// Rebuild XHTML chapters and a table of contents from a MOBI 6 section.
+///
+/// MOBI 6 text is one HTML stream: chapters are separated by
+/// `<mbp:pagebreak/>`, links use `filepos` byte offsets and images refer to
+/// resource records with `recindex`.
+pub(crate) fn read_mobi6(section: &MobiSection<'_>, images: &ImageTable) -> Result<ConvertedBook> {
+    let mut raw = section.text()?;
+    let encoding = section.mobi.text_encoding;
+
+    let filepos_regex =
+        BytesRegex::new(r#"(?i)\bfilepos\s*=\s*["']?0*(\d+)"#).expect("valid regex");
+    let mut targets: BTreeSet<usize> = filepos_regex
+        .captures_iter(&raw)
+        .filter_map(|caps| std::str::from_utf8(&caps[1]).ok()?.parse().ok())
+        .collect();
+
+    let ncx = if section.mobi.ncx_index != NULL_INDEX {
+        MobiIndex::read(section, section.mobi.ncx_index).ok()
+    } else {
+        None
+    };
+    let ncx_entries: Vec<(String, usize)> = ncx
+        .iter()
+        .flat_map(|index| {
+            index.entries.iter().filter_map(|entry| {
+                let label = index.string(entry.tag(3, 0)?)?.to_string();
+                Some((label, entry.tag(1, 0)? as usize))
+            })
+        })
+        .collect();
+    targets.extend(ncx_entries.iter().map(|(_, pos)| *pos));
+
+    insert_anchors(&mut raw, &targets);
+    let html = decode_text(&raw, encoding);
+    let body = extract_body(&html);
+
+    let pagebreak = Regex::new(r"(?i)<mbp:pagebreak[^>]*>").expect("valid regex");
+    let anchor_regex = Regex::new(r#"id="filepos(\d+)""#).expect("valid regex");
+
+    let mut parts = Vec::new();
+    let mut anchor_files: HashMap<String, String> = HashMap::new();
+    for chunk in pagebreak.split(body) {
+        let markup = normalize_markup(chunk);
+        if markup.trim().is_empty() {
+            continue;
+        }
+        let filename = format!("part{:04}.xhtml", parts.len());
+        for caps in anchor_regex.captures_iter(&markup) {
+            anchor_files.insert(caps[1].to_string(), filename.clone());
+        }
+        parts.push((filename, markup));
+    }
+
+    let link_regex = Regex::new(r#"filepos="0*(\d+)""#).expect("valid regex");
+    let image_regex = Regex::new(r#"\brecindex="0*(\d+)""#).expect("valid regex");
+    let alt_recindex = Regex::new(r#"\s(?:hi|lo)recindex="[^"]*""#).expect("valid regex");
+
+    let mut book = ConvertedBook::default();
+    for (filename, markup) in parts {
+        let markup = link_regex.replace_all(&markup, |caps: &Captures| {
+            match anchor_files.get(&caps[1]) {
+                Some(file) => format!("href=\"{}#filepos{}\"", file, &caps[1]),
+                None => String::new(),
+            }
+        });
+        let markup = alt_recindex.replace_all(&markup, "");
+        let markup = image_regex.replace_all(&markup, |caps: &Captures| {
+            let index: usize = caps[1].parse().unwrap_or(0);
+            match images.name(index) {
+                Some(name) => format!("src=\"../images/{}\"", name),
+                None => String::new(),
+            }
+        });
+        book.parts.push((filename, markup.to_string()));
+    }
+
+    for (label, pos) in ncx_entries {
+        if let Some(file) = anchor_files.get(&pos.to_string()) {
+            book.toc.push(TocEntry {
+                label,
+                href: format!("{}#filepos{}", file, pos),
+            });
+        }
+    }
+
+    Ok(book)
+}
+
+/// Insert `<a id="fileposN"></a>` at every link target so `filepos` links can
+/// become fragment links. Targets inside a tag are moved to the tag start;
+/// targets at a page break are moved after it so they land in the next chapter.
+fn insert_anchors(raw: &mut Vec<u8>, targets: &BTreeSet<usize>) {
+    for &target in targets.iter().rev() {
+        if target > raw.len() {
+            continue;
+        }
+        let mut at = target;
+        let before = &raw[..at];
+        let last_lt = before.iter().rposition(|&b| b == b'<');
+        let last_gt = before.iter().rposition(|&b| b == b'>');
+        if let Some(lt) = last_lt {
+            if last_gt.is_none_or(|gt| lt > gt) {
+                at = lt;
+            }
+        }
+        if raw[at..].len() >= 14 && raw[at..at + 14].eq_ignore_ascii_case(b"<mbp:pagebreak") {
+            if let Some(end) = raw[at..].iter().position(|&b| b == b'>') {
+                at += end + 1;
+            }
+        }
+        let anchor = format!("<a id=\"filepos{}\"></a>", target);
+        raw.splice(at..at, anchor.into_bytes());
+    }
+}
+
+/// Return the content of `<body>`, or everything after `</head>` if there is
+/// no body element.
+fn extract_body(html: &str) -> &str {
+    let lower = html.to_ascii_lowercase();
+    let start = match lower.find("<body") {
+        Some(pos) => lower[pos..]
+            .find('>')
+            .map(|end| pos + end + 1)
+            .unwrap_or(pos),
+        None => lower.find("</head>").map(|pos| pos + 7).unwrap_or(0),
+    };
+    let end = lower
+        .rfind("</body>")
+        .filter(|&e| e >= start)
+        .unwrap_or(html.len());
+    &html[start..end]
+}
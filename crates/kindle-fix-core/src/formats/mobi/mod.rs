@@ -1,18 +1,129 @@
+pub mod book;
+pub mod compression;
+pub mod convert;
+pub mod exth;
+pub mod header;
+pub mod index;
+mod kf8;
+pub mod metadata;
+mod mobi6;
+pub mod palmdb;
+pub mod section;
+mod xhtml;
+
 use crate::error::Result;
+use crate::formats::epub::fixes::is_enabled;
+use crate::formats::epub::fixes::language::{
+    is_supported, report_language_fix, LanguageFix, LanguageFixResult,
+};
+use crate::formats::epub::EpubFixer;
 use crate::formats::FileFixer;
-use crate::types::{FixOptions, FixOutput};
-use crate::KindleFixError;
+use crate::types::{
+    BookMetadata, Diagnostic, DiagnosticCode, FixDescription, FixOptions, FixOutput, FixReport,
+};
+
+use self::book::{MobiBook, MobiKind};
+use self::convert::convert_to_epub;
+use self::exth::EXTH_PUBLISHED_DATE;
+use self::metadata::{edit_metadata, MetadataEdit};
 
 pub struct MobiFixer;
 
 impl FileFixer for MobiFixer {
+    /// Matches legacy MOBI 6 books and MOBI/KF8 combo files. Standalone KF8
+    /// books are left to `Azw3Fixer`.
     fn detect(data: &[u8]) -> bool {
-        data.len() > 68 && &data[60..68] == b"BOOKMOBI"
+        let is_palmdb_book = data.len() > 68 && &data[60..68] == b"BOOKMOBI";
+        is_palmdb_book && MobiKind::detect(data) != Some(MobiKind::Kf8)
     }
 
-    fn fix(&self, _data: &[u8], _options: &FixOptions) -> Result<FixOutput> {
-        Err(KindleFixError::UnsupportedFormat(
-            "MOBI support coming soon".into(),
-        ))
+    fn fix(&self, data: &[u8], options: &FixOptions) -> Result<FixOutput> {
+        fix_palmdb_book(data, options)
+    }
+}
+
+/// Shared fix pipeline for every PalmDB-based format (MOBI, combo and AZW3).
+pub(crate) fn fix_palmdb_book(data: &[u8], options: &FixOptions) -> Result<FixOutput> {
+    let book = MobiBook::parse(data)?;
+
+    if options.convert_to_epub {
+        let epub = convert_to_epub(&book)?;
+        let mut output = EpubFixer.fix(&epub, options)?;
+        output.report.format = book.kind.format();
+        output.report.fixes_applied.insert(
+            0,
+            FixDescription {
+                name: "convert".to_string(),
+                details: format!("Converted {} to EPUB", book.kind.format()),
+                files_affected: 1,
+                changes: Vec::new(),
+            },
+        );
+        output.unchanged = !output.report.has_fixes();
+        return Ok(output);
+    }
+
+    let mut report = FixReport::new(String::new(), book.kind.format());
+
+    if book.is_encrypted() {
+        report.warnings.push(Diagnostic::warning(
+            DiagnosticCode::DrmProtected,
+            "Book is DRM-protected; it cannot be modified.",
+        ));
+    }
+
+    let mut edit = MetadataEdit::default();
+    if is_enabled(&LanguageFix, options) {
+        let language = check_language(&book, options.language.clone());
+        let needs_edit = matches!(
+            language,
+            LanguageFixResult::Added(_) | LanguageFixResult::Changed { .. }
+        );
+        // Rewriting record 0 would drop the DRM data it holds
+        if !(needs_edit && book.is_encrypted()) {
+            report_language_fix(&mut report, &language, None);
+            if let LanguageFixResult::Added(lang) | LanguageFixResult::Changed { to: lang, .. } =
+                language
+            {
+                edit.language = Some(lang);
+            }
+        }
+    }
+
+    report.metadata = BookMetadata {
+        title: Some(book.title()),
+        author: book.author(),
+        language: edit.language.clone().or_else(|| book.language()),
+        date: book.exth_string(EXTH_PUBLISHED_DATE),
+    };
+
+    let output_data = if options.dry_run {
+        Vec::new()
+    } else if edit.is_empty() {
+        data.to_vec()
+    } else {
+        edit_metadata(data, &edit)?
+    };
+
+    // Only EPUB text can be diffed; MOBI metadata edits are listed as fixes
+    Ok(FixOutput {
+        data: output_data,
+        unchanged: !report.has_fixes(),
+        report,
+        diffs: Vec::new(),
+    })
+}
+
+/// Check the EXTH language of a book with the same rules `fix_language`
+/// applies to an OPF: a missing language is added, and an unsupported one is
+/// only replaced when an override is given.
+fn check_language(book: &MobiBook, language_override: Option<String>) -> LanguageFixResult {
+    match book.language() {
+        None => LanguageFixResult::Added(language_override.unwrap_or_else(|| "en".to_string())),
+        Some(lang) if is_supported(&lang) => LanguageFixResult::Valid(lang),
+        Some(lang) => match language_override {
+            Some(to) => LanguageFixResult::Changed { from: lang, to },
+            None => LanguageFixResult::Unsupported(lang),
+        },
     }
 }
@@ -0,0 +1,189 @@
+use crate::error::{KindleFixError, Result};
+
+/// Size of the fixed PalmDB header that precedes the record table.
+pub const PALMDB_HEADER_LEN: usize = 78;
+
+/// Size of a single entry in the PalmDB record table.
+const RECORD_INFO_LEN: usize = 8;
+
+/// The fixed 78-byte header at the start of every PalmDB file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PalmDbHeader {
+    /// The database name as stored: NUL-padded, in no particular encoding.
+    pub name: [u8; 32],
+    pub attributes: u16,
+    pub version: u16,
+    pub creation_date: u32,
+    pub modification_date: u32,
+    pub backup_date: u32,
+    pub modification_number: u32,
+    pub app_info_id: u32,
+    pub sort_info_id: u32,
+    pub type_code: [u8; 4],
+    pub creator: [u8; 4],
+    pub unique_id_seed: u32,
+    pub next_record_list_id: u32,
+}
+
+impl PalmDbHeader {
+    /// The database name up to its first NUL, for display.
+    pub fn name(&self) -> String {
+        let end = self.name.iter().position(|&b| b == 0).unwrap_or(32);
+        String::from_utf8_lossy(&self.name[..end]).into_owned()
+    }
+}
+
+/// A single record of a PalmDB database, with its table attributes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PalmRecord {
+    pub attributes: u8,
+    pub unique_id: u32,
+    pub data: Vec<u8>,
+}
+
+/// A PalmDB database: the container format used by MOBI and AZW3 books.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PalmDb {
+    pub header: PalmDbHeader,
+    pub records: Vec<PalmRecord>,
+}
+
+impl PalmDb {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < PALMDB_HEADER_LEN {
+            return Err(invalid("file is too short for a PalmDB header"));
+        }
+
+        let header = PalmDbHeader {
+            name: data[0..32].try_into().expect("header length was checked"),
+            attributes: read_u16(data, 32)?,
+            version: read_u16(data, 34)?,
+            creation_date: read_u32(data, 36)?,
+            modification_date: read_u32(data, 40)?,
+            backup_date: read_u32(data, 44)?,
+            modification_number: read_u32(data, 48)?,
+            app_info_id: read_u32(data, 52)?,
+            sort_info_id: read_u32(data, 56)?,
+            type_code: read_tag(data, 60)?,
+            creator: read_tag(data, 64)?,
+            unique_id_seed: read_u32(data, 68)?,
+            next_record_list_id: read_u32(data, 72)?,
+        };
+
+        let record_count = read_u16(data, 76)? as usize;
+        let table_end = PALMDB_HEADER_LEN + record_count * RECORD_INFO_LEN;
+        if data.len() < table_end {
+            return Err(invalid("record table extends past end of file"));
+        }
+
+        let mut infos = Vec::with_capacity(record_count);
+        for i in 0..record_count {
+            let base = PALMDB_HEADER_LEN + i * RECORD_INFO_LEN;
+            let offset = read_u32(data, base)? as usize;
+            let attributes = data[base + 4];
+            let unique_id = (read_u32(data, base + 4)?) & 0x00FF_FFFF;
+            infos.push((offset, attributes, unique_id));
+        }
+
+        let mut records = Vec::with_capacity(record_count);
+        for (i, &(offset, attributes, unique_id)) in infos.iter().enumerate() {
+            let end = infos.get(i + 1).map(|next| next.0).unwrap_or(data.len());
+            if offset < table_end || offset > end || end > data.len() {
+                return Err(invalid(&format!("record {} has an invalid offset", i)));
+            }
+            records.push(PalmRecord {
+                attributes,
+                unique_id,
+                data: data[offset..end].to_vec(),
+            });
+        }
+
+        Ok(Self { header, records })
+    }
+
+    pub fn record(&self, index: usize) -> Option<&[u8]> {
+        self.records.get(index).map(|r| r.data.as_slice())
+    }
+
+    /// Serialize the database, recomputing the record offsets from the
+    /// current record sizes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = &self.header;
+        let table_end = PALMDB_HEADER_LEN + self.records.len() * RECORD_INFO_LEN;
+        let data_len: usize = self.records.iter().map(|r| r.data.len()).sum();
+        let mut out = Vec::with_capacity(table_end + 2 + data_len);
+
+        out.extend_from_slice(&header.name);
+        out.extend_from_slice(&header.attributes.to_be_bytes());
+        out.extend_from_slice(&header.version.to_be_bytes());
+        for value in [
+            header.creation_date,
+            header.modification_date,
+            header.backup_date,
+            header.modification_number,
+            header.app_info_id,
+            header.sort_info_id,
+        ] {
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+        out.extend_from_slice(&header.type_code);
+        out.extend_from_slice(&header.creator);
+        out.extend_from_slice(&header.unique_id_seed.to_be_bytes());
+        out.extend_from_slice(&header.next_record_list_id.to_be_bytes());
+        out.extend_from_slice(&(self.records.len() as u16).to_be_bytes());
+
+        // Records start after the table and the conventional 2-byte gap.
+        let mut offset = table_end + 2;
+        for record in &self.records {
+            out.extend_from_slice(&(offset as u32).to_be_bytes());
+            let info = ((record.attributes as u32) << 24) | (record.unique_id & 0x00FF_FFFF);
+            out.extend_from_slice(&info.to_be_bytes());
+            offset += record.data.len();
+        }
+        out.extend_from_slice(&[0, 0]);
+
+        for record in &self.records {
+            out.extend_from_slice(&record.data);
+        }
+        out
+    }
+}
+
+pub(crate) fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid(&format!("unexpected end of data at offset {}", offset)))
+}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid(&format!("unexpected end of data at offset {}", offset)))
+}
+
+fn read_tag(data: &[u8], offset: usize) -> Result<[u8; 4]> {
+    data.get(offset..offset + 4)
+        .map(|b| [b[0], b[1], b[2], b[3]])
+        .ok_or_else(|| invalid(&format!("unexpected end of data at offset {}", offset)))
+}
+
+pub(crate) fn invalid(msg: &str) -> KindleFixError {
+    KindleFixError::InvalidMobi(msg.to_string())
+}
+
+/// Borrow the bytes of record `index` straight from the file without copying
+/// the whole database.
+pub(crate) fn record_slice(data: &[u8], index: usize) -> Option<&[u8]> {
+    let count = read_u16(data, 76).ok()? as usize;
+    if index >= count {
+        return None;
+    }
+    let entry = PALMDB_HEADER_LEN + index * RECORD_INFO_LEN;
+    let start = read_u32(data, entry).ok()? as usize;
+    let end = if index + 1 < count {
+        read_u32(data, entry + RECORD_INFO_LEN).ok()? as usize
+    } else {
+        data.len()
+    };
+    data.get(start..end)
+}
@@ -0,0 +1,143 @@
+use crate::error::{KindleFixError, Result};
+
+use super::compression::{palmdoc_decompress, HuffCdicReader};
+use super::exth::Exth;
+use super::header::{Compression, MobiHeader, PalmDocHeader, NULL_INDEX};
+use super::palmdb::{invalid, PalmDb};
+
+/// One MOBI header and the records it describes: the whole file for MOBI 6
+/// and AZW3 books, or either half of a combo file.
+///
+/// Record indices stored in a KF8 header are relative to the header record,
+/// so `record` and `absolute` take care of adding `base`.
+pub struct MobiSection<'a> {
+    pub palmdb: &'a PalmDb,
+    pub base: usize,
+    pub palmdoc: PalmDocHeader,
+    pub mobi: MobiHeader,
+    pub exth: Option<Exth>,
+}
+
+impl<'a> MobiSection<'a> {
+    pub fn new(palmdb: &'a PalmDb, base: usize) -> Result<Self> {
+        let record0 = palmdb
+            .record(base)
+            .ok_or_else(|| invalid(&format!("missing MOBI header record {}", base)))?;
+        let palmdoc = PalmDocHeader::parse(record0)?;
+        let mobi = MobiHeader::parse(record0)?;
+        let exth = if mobi.has_exth() {
+            Some(Exth::parse(&record0[mobi.end_offset()..])?)
+        } else {
+            None
+        };
+        Ok(Self {
+            palmdb,
+            base,
+            palmdoc,
+            mobi,
+            exth,
+        })
+    }
+
+    /// Translate a header record index into an absolute PalmDB record index.
+    pub fn absolute(&self, index: u32) -> Option<usize> {
+        if index == NULL_INDEX {
+            None
+        } else {
+            Some(self.base + index as usize)
+        }
+    }
+
+    pub fn record(&self, index: u32) -> Option<&'a [u8]> {
+        self.absolute(index).and_then(|i| self.palmdb.record(i))
+    }
+
+    /// Decompress and concatenate all text records of this section.
+    pub fn text(&self) -> Result<Vec<u8>> {
+        if self.palmdoc.encryption != 0 {
+            return Err(KindleFixError::UnsupportedFormat(
+                "DRM-protected books cannot be converted".into(),
+            ));
+        }
+
+        let mut huffman = match self.palmdoc.compression {
+            Compression::Huffman => Some(self.huffman_reader()?),
+            Compression::Unknown(kind) => {
+                return Err(KindleFixError::UnsupportedFormat(format!(
+                    "unknown MOBI compression type {}",
+                    kind
+                )))
+            }
+            _ => None,
+        };
+
+        let mut text = Vec::new();
+        for i in 1..=u32::from(self.palmdoc.text_record_count) {
+            let record = self
+                .record(i)
+                .ok_or_else(|| invalid(&format!("missing text record {}", i)))?;
+            let trailing = trailing_data_size(record, self.mobi.extra_data_flags);
+            let body = &record[..record.len().saturating_sub(trailing)];
+
+            match (&mut huffman, self.palmdoc.compression) {
+                (Some(reader), _) => text.extend(reader.unpack(body)?),
+                (None, Compression::PalmDoc) => text.extend(palmdoc_decompress(body)?),
+                _ => text.extend_from_slice(body),
+            }
+        }
+
+        text.truncate(self.palmdoc.text_length as usize);
+        Ok(text)
+    }
+
+    fn huffman_reader(&self) -> Result<HuffCdicReader> {
+        let first = self.mobi.huffman_record_offset;
+        let huff = self
+            .record(first)
+            .ok_or_else(|| invalid("missing HUFF record"))?;
+        let cdics = (1..self.mobi.huffman_record_count)
+            .map(|i| {
+                self.record(first + i)
+                    .ok_or_else(|| invalid("missing CDIC record"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        HuffCdicReader::new(huff, &cdics)
+    }
+}
+
+/// Number of trailing bytes appended to a text record, as announced by the
+/// MOBI header's extra data flags.
+fn trailing_data_size(record: &[u8], flags: u16) -> usize {
+    let mut size = 0;
+    let mut bits = flags >> 1;
+    while bits != 0 {
+        if bits & 1 != 0 {
+            size += backward_varint(&record[..record.len().saturating_sub(size)]);
+        }
+        bits >>= 1;
+    }
+    if flags & 1 != 0 {
+        if let Some(&last) = record
+            .len()
+            .checked_sub(size + 1)
+            .and_then(|i| record.get(i))
+        {
+            size += (last & 0x3) as usize + 1;
+        }
+    }
+    size.min(record.len())
+}
+
+/// Read a variable-width integer stored backwards at the end of `data`.
+fn backward_varint(data: &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    for &byte in data.iter().rev() {
+        value |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 != 0 || shift >= 28 {
+            break;
+        }
+    }
+    value
+}
@@ -0,0 +1,264 @@
+//! Turn the loose HTML found in MOBI 6 text into well-formed XHTML.
+
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "wbr",
+];
+
+/// Elements that implicitly close an open `<p>` in HTML.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "blockquote",
+    "div",
+    "dl",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "ol",
+    "p",
+    "pre",
+    "table",
+    "ul",
+];
+
+/// Document-level tags that are dropped; their content is kept.
+const DROPPED_ELEMENTS: &[&str] = &["html", "head", "body", "guide", "reference"];
+
+const HTML_ENTITIES: &[(&str, u32)] = &[
+    ("nbsp", 160),
+    ("laquo", 171),
+    ("copy", 169),
+    ("shy", 173),
+    ("reg", 174),
+    ("deg", 176),
+    ("middot", 183),
+    ("raquo", 187),
+    ("ndash", 8211),
+    ("mdash", 8212),
+    ("lsquo", 8216),
+    ("rsquo", 8217),
+    ("ldquo", 8220),
+    ("rdquo", 8221),
+    ("bull", 8226),
+    ("hellip", 8230),
+    ("trade", 8482),
+];
+
+/// Normalize a fragment of MOBI markup: lowercase tag names, quote attribute
+/// values, self-close void elements, drop `mbp:` tags and comments, escape
+/// stray `&` and `<`, and balance unclosed elements.
+pub(crate) fn normalize_markup(html: &str) -> String {
+    let mut out = String::with_capacity(html.len() + html.len() / 8);
+    let mut stack: Vec<String> = Vec::new();
+    let mut rest = html;
+
+    while let Some(lt) = rest.find('<') {
+        push_text(&mut out, &rest[..lt]);
+        rest = &rest[lt..];
+
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map(|end| &rest[end + 3..]).unwrap_or("");
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map(|end| &rest[end + 1..]).unwrap_or("");
+            continue;
+        }
+
+        let is_end = rest.starts_with("</");
+        let name_start = if is_end { 2 } else { 1 };
+        let starts_with_letter = rest[name_start..]
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic());
+        if !starts_with_letter {
+            out.push_str("&lt;");
+            rest = &rest[1..];
+            continue;
+        }
+
+        let Some(tag_len) = tag_length(rest) else {
+            push_text(&mut out, rest);
+            rest = "";
+            break;
+        };
+        let tag = &rest[name_start..tag_len - 1];
+        rest = &rest[tag_len..];
+
+        let name_len = tag
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .unwrap_or(tag.len());
+        let name = tag[..name_len].to_ascii_lowercase();
+        if name.contains(':') || DROPPED_ELEMENTS.contains(&name.as_str()) {
+            continue;
+        }
+
+        if is_end {
+            if let Some(pos) = stack.iter().rposition(|open| *open == name) {
+                for open in stack.drain(pos..).rev() {
+                    out.push_str(&format!("</{}>", open));
+                }
+            }
+            continue;
+        }
+
+        if BLOCK_ELEMENTS.contains(&name.as_str()) && stack.last().map(String::as_str) == Some("p")
+        {
+            stack.pop();
+            out.push_str("</p>");
+        }
+        if name == "li" {
+            let list = stack.iter().rposition(|o| o == "ul" || o == "ol");
+            if let Some(pos) = stack.iter().rposition(|o| o == "li") {
+                if list.is_none_or(|l| pos > l) {
+                    for open in stack.drain(pos..).rev() {
+                        out.push_str(&format!("</{}>", open));
+                    }
+                }
+            }
+        }
+
+        let self_closing = tag.trim_end().ends_with('/');
+        let attrs = tag[name_len..].trim_end().trim_end_matches('/');
+        out.push('<');
+        out.push_str(&name);
+        push_attributes(&mut out, attrs);
+
+        if self_closing || VOID_ELEMENTS.contains(&name.as_str()) {
+            out.push_str("/>");
+        } else {
+            out.push('>');
+            stack.push(name);
+        }
+    }
+
+    push_text(&mut out, rest);
+    for open in stack.into_iter().rev() {
+        out.push_str(&format!("</{}>", open));
+    }
+    out
+}
+
+/// Length of the tag at the start of `s`, up to and including its `>`,
+/// skipping over quoted attribute values.
+fn tag_length(s: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in s.char_indices().skip(1) {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '>') => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn push_attributes(out: &mut String, attrs: &str) {
+    let mut seen: Vec<String> = Vec::new();
+    let mut rest = attrs.trim_start();
+
+    while !rest.is_empty() {
+        let name_len = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let name = rest[..name_len].to_ascii_lowercase();
+        rest = rest[name_len..].trim_start();
+
+        let value = if let Some(after_eq) = rest.strip_prefix('=') {
+            let after_eq = after_eq.trim_start();
+            match after_eq.chars().next() {
+                Some(q @ ('"' | '\'')) => {
+                    let end = after_eq[1..]
+                        .find(q)
+                        .map(|e| e + 1)
+                        .unwrap_or(after_eq.len());
+                    let value = &after_eq[1..end];
+                    rest = after_eq.get(end + 1..).unwrap_or("");
+                    value.to_string()
+                }
+                _ => {
+                    let end = after_eq.find(char::is_whitespace).unwrap_or(after_eq.len());
+                    rest = &after_eq[end..];
+                    after_eq[..end].to_string()
+                }
+            }
+        } else {
+            name.clone()
+        };
+        rest = rest.trim_start();
+
+        let valid_name = name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if name.is_empty() || !valid_name || seen.contains(&name) {
+            continue;
+        }
+        out.push(' ');
+        out.push_str(&name);
+        out.push_str("=\"");
+        push_escaped(out, &value, true);
+        out.push('"');
+        seen.push(name);
+    }
+}
+
+fn push_text(out: &mut String, text: &str) {
+    push_escaped(out, text, false);
+}
+
+/// Escape text for XML while keeping valid entity references. HTML-only named
+/// entities are turned into numeric references.
+fn push_escaped(out: &mut String, text: &str, in_attribute: bool) {
+    let mut rest = text;
+    while let Some(pos) = rest.find(['&', '<', '>', '"']) {
+        out.push_str(&rest[..pos]);
+        let c = rest.as_bytes()[pos];
+        rest = &rest[pos + 1..];
+        match c {
+            b'<' => out.push_str("&lt;"),
+            b'>' => out.push_str("&gt;"),
+            b'"' if in_attribute => out.push_str("&quot;"),
+            b'"' => out.push('"'),
+            _ => {
+                let entity_len =
+                    rest.find(';')
+                        .filter(|&end| end > 0 && end <= 10)
+                        .filter(|&end| {
+                            rest[..end]
+                                .chars()
+                                .all(|c| c.is_ascii_alphanumeric() || c == '#')
+                        });
+                match entity_len.map(|end| &rest[..end]) {
+                    Some(name) if is_xml_entity(name) => {
+                        out.push('&');
+                        out.push_str(name);
+                        out.push(';');
+                        rest = &rest[name.len() + 1..];
+                    }
+                    Some(name) => match HTML_ENTITIES.iter().find(|(n, _)| *n == name) {
+                        Some((_, code)) => {
+                            out.push_str(&format!("&#{};", code));
+                            rest = &rest[name.len() + 1..];
+                        }
+                        None => out.push_str("&amp;"),
+                    },
+                    None => out.push_str("&amp;"),
+                }
+            }
+        }
+    }
+    out.push_str(rest);
+}
+
+fn is_xml_entity(name: &str) -> bool {
+    matches!(name, "amp" | "lt" | "gt" | "quot" | "apos")
+        || name.strip_prefix('#').is_some_and(|n| {
+            !n.is_empty() && (n.starts_with('x') || n.chars().all(|c| c.is_ascii_digit()))
+        })
+}
@@ -2,11 +2,18 @@
 
 pub mod error;
 pub mod formats;
+pub mod naming;
 pub mod types;
 
 pub use error::{KindleFixError, Result};
-pub use types::{FileFormat, FixDescription, FixOptions, FixOutput, FixReport};
+pub use naming::NameTemplate;
+pub use types::{
+    BookMetadata, Change, CheckReport, Diagnostic, DiagnosticCode, FileDiff, FileFormat, FixDescription,
+    FixOptions, FixOutput, FixReport, Severity,
+};
 
+use formats::azw3::Azw3Fixer;
+use formats::epub::fixes;
 use formats::epub::EpubFixer;
 use formats::mobi::MobiFixer;
 use formats::FileFixer;
@@ -22,6 +29,11 @@ pub fn process_file(data: &[u8], filename: &str, options: &FixOptions) -> Result
         let mut output = fixer.fix(data, options)?;
         output.report.filename = filename.to_string();
         Ok(output)
+    } else if Azw3Fixer::detect(data) {
+        let fixer = Azw3Fixer;
+        let mut output = fixer.fix(data, options)?;
+        output.report.filename = filename.to_string();
+        Ok(output)
     } else if MobiFixer::detect(data) {
         let fixer = MobiFixer;
         let mut output = fixer.fix(data, options)?;
@@ -35,6 +47,54 @@ pub fn process_file(data: &[u8], filename: &str, options: &FixOptions) -> Result
     }
 }
 
+/// Check that fixed output re-opens as a valid book of `format` before it
+/// replaces anything on disk.
+pub fn validate_file(data: &[u8], format: FileFormat) -> Result<()> {
+    match format {
+        FileFormat::Epub => formats::epub::validate(data),
+        FileFormat::Azw3 if Azw3Fixer::detect(data) => Ok(()),
+        FileFormat::Mobi if MobiFixer::detect(data) => Ok(()),
+        FileFormat::Azw3 | FileFormat::Mobi => Err(KindleFixError::InvalidMobi(format!(
+            "output is not a valid {} file",
+            format
+        ))),
+        FileFormat::Unknown => Err(KindleFixError::UnsupportedFormat(
+            "cannot validate a file of unknown format".into(),
+        )),
+    }
+}
+
+/// Find the Kindle compatibility problems in a file without fixing it.
+/// Every selected fix runs in dry-run mode; what it would change becomes a
+/// diagnostic, followed by the warnings the fixes raised.
+pub fn check_file(data: &[u8], filename: &str, options: &FixOptions) -> Result<CheckReport> {
+    let options = FixOptions {
+        dry_run: true,
+        convert_to_epub: false,
+        diff: false,
+        // A stamp says what ran, not that the book is still fine
+        force: true,
+        ..options.clone()
+    };
+    let report = process_file(data, filename, &options)?.report;
+
+    let fixable = report.fixes_applied.into_iter().map(|fix| {
+        match fixes::find(&fix.name) {
+            Some(f) => Diagnostic::new(f.code(), f.severity(), fix.details)
+                .with_suggestion(f.description())
+                .with_fix(fix.name),
+            // Transcoding is the only change made outside the fix registry
+            None => Diagnostic::warning(DiagnosticCode::NonUtf8Text, fix.details),
+        }
+    });
+
+    Ok(CheckReport {
+        filename: report.filename,
+        format: report.format,
+        problems: fixable.chain(report.warnings).collect(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -68,6 +128,32 @@ mod tests {
         assert_eq!(format!("{}", FileFormat::Unknown), "Unknown");
     }
 
+    #[test]
+    fn diagnostic_location() {
+        let text = "<a>\n  <b/>\n</a>";
+        let diagnostic = Diagnostic::warning(DiagnosticCode::StrayImage, "stray")
+            .with_file("c.xhtml")
+            .at_offset(text, text.find("<b").unwrap());
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(2), Some(3)));
+        assert_eq!(diagnostic.location().as_deref(), Some("c.xhtml:2:3"));
+        assert_eq!(
+            Diagnostic::warning(DiagnosticCode::StrayImage, "stray").location(),
+            None
+        );
+    }
+
+    #[test]
+    fn diagnostic_code_is_kebab_case() {
+        assert_eq!(
+            DiagnosticCode::UnsupportedLanguage.to_string(),
+            "unsupported-language"
+        );
+        assert_eq!(
+            serde_json::to_string(&DiagnosticCode::NonUtf8Text).unwrap(),
+            "\"non-utf8-text\""
+        );
+    }
+
     #[test]
     fn error_display() {
         let err = KindleFixError::InvalidEpub("missing mimetype".into());
@@ -0,0 +1,237 @@
+//! Naming the files fixed books are written to.
+//!
+//! A template is a relative file name with placeholders:
+//!
+//! | Placeholder | Value                                          |
+//! |-------------|------------------------------------------------|
+//! | `{stem}`    | input file name without its extension          |
+//! | `{ext}`     | extension of the output format                 |
+//! | `{title}`   | book title (falls back to `{stem}`)            |
+//! | `{author}`  | first author (falls back to `Unknown`)         |
+//! | `{lang}`    | language code (falls back to `und`)            |
+//! | `{date}`    | publication date, `YYYY-MM-DD` part only       |
+//!
+//! `{{` and `}}` stand for literal braces. A `/` in the template sorts books
+//! into subdirectories; metadata values never introduce one.
+
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use crate::error::{KindleFixError, Result};
+use crate::types::{FileFormat, FixReport};
+
+/// Prefix the default template puts in front of fixed files.
+pub const FIXED_PREFIX: &str = "(fixed) ";
+
+/// Template used when none is given.
+pub const DEFAULT_TEMPLATE: &str = "(fixed) {stem}.{ext}";
+
+/// Template that keeps the original file name.
+pub const KEEP_NAME_TEMPLATE: &str = "{stem}.{ext}";
+
+/// Longest metadata value put into a file name, in characters.
+const MAX_VALUE_LEN: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Stem,
+    Ext,
+    Title,
+    Author,
+    Lang,
+    Date,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "stem" => Some(Field::Stem),
+            "ext" => Some(Field::Ext),
+            "title" => Some(Field::Title),
+            "author" => Some(Field::Author),
+            "lang" => Some(Field::Lang),
+            "date" => Some(Field::Date),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+/// A parsed output file name template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+impl NameTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        if template.trim().is_empty() {
+            return Err(KindleFixError::InvalidTemplate("template is empty".into()));
+        }
+
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or_else(|| {
+                        KindleFixError::InvalidTemplate(format!("unclosed '{{' in '{}'", template))
+                    })?;
+                    let name = &rest[..end];
+                    let field = Field::from_name(name).ok_or_else(|| {
+                        KindleFixError::InvalidTemplate(format!(
+                            "unknown placeholder {{{}}}; use {{stem}}, {{ext}}, {{title}}, \
+                             {{author}}, {{lang}} or {{date}}",
+                            name
+                        ))
+                    })?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(field));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => {
+                    return Err(KindleFixError::InvalidTemplate(format!(
+                        "unmatched '}}' in '{}'",
+                        template
+                    )));
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        if escapes_directory(&parts) {
+            return Err(KindleFixError::InvalidTemplate(format!(
+                "'{}' must be a relative file name",
+                template
+            )));
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// The template that keeps the original file name.
+    pub fn keep_name() -> Self {
+        Self::parse(KEEP_NAME_TEMPLATE).expect("built-in template is valid")
+    }
+
+    /// Render the relative output path for the input `filename` fixed into
+    /// `report`.
+    pub fn render(&self, filename: &str, report: &FixReport) -> String {
+        let path = Path::new(filename);
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let ext = match report.output_format {
+            FileFormat::Unknown => path
+                .extension()
+                .map(|ext| ext.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            format => format.extension().to_string(),
+        };
+        let metadata = &report.metadata;
+        let value = |value: Option<&str>, fallback: &str| {
+            value
+                .and_then(sanitize)
+                .unwrap_or_else(|| fallback.to_string())
+        };
+
+        let mut name = String::new();
+        for part in &self.parts {
+            let text = match part {
+                Part::Literal(text) => text.clone(),
+                Part::Field(Field::Stem) => stem.clone(),
+                Part::Field(Field::Ext) => ext.clone(),
+                Part::Field(Field::Title) => value(metadata.title.as_deref(), &stem),
+                Part::Field(Field::Author) => value(metadata.author.as_deref(), "Unknown"),
+                Part::Field(Field::Lang) => value(metadata.language.as_deref(), "und"),
+                Part::Field(Field::Date) => {
+                    let date = metadata.date.as_deref();
+                    value(date.and_then(|date| date.split('T').next()), "")
+                }
+            };
+            name.push_str(&text);
+        }
+        name
+    }
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_TEMPLATE).expect("built-in template is valid")
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = KindleFixError;
+
+    fn from_str(template: &str) -> Result<Self> {
+        Self::parse(template)
+    }
+}
+
+/// Whether a template could name a file outside the directory it is rendered
+/// into: it is absolute, has a drive or climbs with `..`. Both `/` and `\`
+/// count as separators, whatever the platform. Fields never contain either,
+/// so they stand in as a plain name.
+fn escapes_directory(parts: &[Part]) -> bool {
+    let skeleton: String = parts
+        .iter()
+        .map(|part| match part {
+            Part::Literal(text) => text.as_str(),
+            Part::Field(_) => "_",
+        })
+        .collect::<String>()
+        .replace('\\', "/");
+
+    let bytes = skeleton.as_bytes();
+    let drive = bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':';
+    drive
+        || Path::new(&skeleton).components().any(|component| {
+            matches!(
+                component,
+                Component::ParentDir | Component::RootDir | Component::Prefix(_)
+            )
+        })
+}
+
+/// Make a metadata value safe to use as (part of) a file name: path
+/// separators and characters Windows rejects become `_`, whitespace runs
+/// collapse, and the result is trimmed and shortened.
+fn sanitize(value: &str) -> Option<String> {
+    let cleaned: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let shortened: String = cleaned.chars().take(MAX_VALUE_LEN).collect();
+    // Windows drops trailing dots, and a bare ".." would climb a directory
+    let trimmed = shortened.trim_end_matches(['.', ' ']).trim_start();
+    (!trimmed.is_empty()).then(|| trimmed.to_string())
+}
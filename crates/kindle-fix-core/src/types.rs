@@ -1,18 +1,46 @@
 use std::fmt;
 
+use serde::{Serialize, Serializer};
+
+use crate::formats::epub::stamp::Stamp;
+use crate::formats::epub::writer::EntryOrder;
+
 #[derive(Debug, Clone, Default)]
 pub struct FixOptions {
     pub language: Option<String>,
     pub keep_name: bool,
     pub dry_run: bool,
+    /// Convert MOBI and AZW3 books to EPUB instead of keeping their format.
+    pub convert_to_epub: bool,
+    /// Write EPUBs reproducibly, with entries in the given order, fixed
+    /// timestamps and fixed compression settings.
+    pub reproducible: Option<EntryOrder>,
+    /// Ids of the only fixes to run; `None` runs every fix enabled by default.
+    pub only: Option<Vec<String>>,
+    /// Ids of fixes not to run.
+    pub skip: Vec<String>,
+    /// Collect a unified diff of every text file the fixes change.
+    pub diff: bool,
+    /// Record the version and the fixes run in the OPF of changed EPUBs.
+    pub stamp: bool,
+    /// Run every selected fix, even those a book's stamp says already ran.
+    pub force: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct FixReport {
     pub filename: String,
     pub format: FileFormat,
+    /// Format of the fixed output; differs from `format` after a conversion.
+    pub output_format: FileFormat,
     pub fixes_applied: Vec<FixDescription>,
-    pub warnings: Vec<String>,
+    pub warnings: Vec<Diagnostic>,
+    /// Metadata of the fixed book.
+    pub metadata: BookMetadata,
+    /// Stamp an earlier run left in the book.
+    pub stamp: Option<Stamp>,
+    /// Fixes not run because the stamp says they were already applied.
+    pub already_applied: Vec<String>,
 }
 
 impl FixReport {
@@ -20,8 +48,12 @@ impl FixReport {
         Self {
             filename,
             format,
+            output_format: format,
             fixes_applied: Vec::new(),
             warnings: Vec::new(),
+            metadata: BookMetadata::default(),
+            stamp: None,
+            already_applied: Vec::new(),
         }
     }
 
@@ -30,20 +62,290 @@ impl FixReport {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Descriptive metadata of a book, as used to name its output.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BookMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub language: Option<String>,
+    pub date: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct FixDescription {
     pub name: String,
     pub details: String,
     pub files_affected: usize,
+    /// The individual edits, when the fix can tell them apart.
+    pub changes: Vec<Change>,
+}
+
+impl FixDescription {
+    /// Describe the edits in `changes`, counting the distinct files they touch.
+    pub fn from_changes(name: &str, details: String, changes: Vec<Change>) -> Self {
+        let mut files: Vec<&str> = changes.iter().map(|change| change.file.as_str()).collect();
+        files.sort_unstable();
+        files.dedup();
+        Self {
+            name: name.to_string(),
+            details,
+            files_affected: files.len(),
+            changes,
+        }
+    }
+}
+
+/// One edit made by a fix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Change {
+    /// Path of the changed file inside the book.
+    pub file: String,
+    /// The text that was replaced; empty for insertions.
+    pub original: String,
+    /// The text it was replaced with; empty for removals.
+    pub replacement: String,
+    /// 1-based line of the edit in the original file.
+    pub line: Option<usize>,
+    /// 1-based column of the edit in the original file, in characters.
+    pub column: Option<usize>,
+}
+
+impl Change {
+    pub fn new(
+        file: impl Into<String>,
+        original: impl Into<String>,
+        replacement: impl Into<String>,
+    ) -> Self {
+        Self {
+            file: file.into(),
+            original: original.into(),
+            replacement: replacement.into(),
+            line: None,
+            column: None,
+        }
+    }
+
+    /// Set the line and column of byte `offset` in `text`, the original file.
+    pub fn at_offset(mut self, text: &str, offset: usize) -> Self {
+        let (line, column) = line_column(text, offset);
+        self.line = Some(line);
+        self.column = Some(column);
+        self
+    }
+}
+
+/// 1-based line and character column of byte `offset` in `text`.
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// How serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The file works on Kindle, but may not display as intended.
+    Warning,
+    /// Kindle is likely to reject the file.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Stable identifier of a kind of problem. Serialized as `as_str`, in
+/// kebab-case, e.g. `unsupported-language`, so scripts can match on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiagnosticCode {
+    /// The book's language is one Kindle does not support.
+    UnsupportedLanguage,
+    /// The language metadata could not be read or written.
+    LanguageCheckFailed,
+    /// Language metadata is missing.
+    MissingLanguage,
+    /// A text file could not be decoded and was left unchanged.
+    UndecodableText,
+    /// The charset of a text file was guessed, so it may have been misread.
+    GuessedCharset,
+    /// A text file is not stored as UTF-8.
+    NonUtf8Text,
+    /// An encoding declaration is missing or names another charset.
+    EncodingDeclaration,
+    /// A link points at the ID of a `<body>` element.
+    BodyIdLink,
+    /// A link points at a file or fragment ID that does not exist.
+    BrokenLink,
+    /// The OPF manifest does not match the files of the book.
+    ManifestMismatch,
+    /// An `<img>` tag has no `src` attribute.
+    StrayImage,
+    /// The book is DRM-protected.
+    DrmProtected,
+}
+
+impl DiagnosticCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiagnosticCode::UnsupportedLanguage => "unsupported-language",
+            DiagnosticCode::LanguageCheckFailed => "language-check-failed",
+            DiagnosticCode::MissingLanguage => "missing-language",
+            DiagnosticCode::UndecodableText => "undecodable-text",
+            DiagnosticCode::GuessedCharset => "guessed-charset",
+            DiagnosticCode::NonUtf8Text => "non-utf8-text",
+            DiagnosticCode::EncodingDeclaration => "encoding-declaration",
+            DiagnosticCode::BodyIdLink => "body-id-link",
+            DiagnosticCode::BrokenLink => "broken-link",
+            DiagnosticCode::ManifestMismatch => "manifest-mismatch",
+            DiagnosticCode::StrayImage => "stray-image",
+            DiagnosticCode::DrmProtected => "drm-protected",
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for DiagnosticCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// A problem found in a book, with where it is and how to resolve it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub code: DiagnosticCode,
+    pub severity: Severity,
+    pub message: String,
+    /// Path of the affected file inside the book.
+    pub file: Option<String>,
+    /// 1-based line in `file`.
+    pub line: Option<usize>,
+    /// 1-based column in `file`, in characters.
+    pub column: Option<usize>,
+    /// How the problem can be resolved.
+    pub suggestion: Option<String>,
+    /// Id of the fix that repairs the problem, if any.
+    pub fix: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(code: DiagnosticCode, severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            severity,
+            message: message.into(),
+            file: None,
+            line: None,
+            column: None,
+            suggestion: None,
+            fix: None,
+        }
+    }
+
+    pub fn warning(code: DiagnosticCode, message: impl Into<String>) -> Self {
+        Self::new(code, Severity::Warning, message)
+    }
+
+    pub fn error(code: DiagnosticCode, message: impl Into<String>) -> Self {
+        Self::new(code, Severity::Error, message)
+    }
+
+    pub fn with_file(mut self, file: impl Into<String>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    /// Set the line and column of byte `offset` in `text`, the contents of the file.
+    pub fn at_offset(mut self, text: &str, offset: usize) -> Self {
+        let (line, column) = line_column(text, offset);
+        self.line = Some(line);
+        self.column = Some(column);
+        self
+    }
+
+    pub fn with_suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+
+    pub fn with_fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+
+    /// `file:line:column`, or as much of it as is known.
+    pub fn location(&self) -> Option<String> {
+        let file = self.file.as_deref()?;
+        Some(match (self.line, self.column) {
+            (Some(line), Some(column)) => format!("{}:{}:{}", file, line, column),
+            (Some(line), None) => format!("{}:{}", file, line),
+            _ => file.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckReport {
+    pub filename: String,
+    pub format: FileFormat,
+    pub problems: Vec<Diagnostic>,
+}
+
+impl CheckReport {
+    pub fn has_problems(&self) -> bool {
+        !self.problems.is_empty()
+    }
+
+    /// The most serious problem found, if any.
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.problems.iter().map(|problem| problem.severity).max()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct FixOutput {
+    /// The fixed book; empty on a dry run, and the original bytes when
+    /// `unchanged` is set.
     pub data: Vec<u8>,
     pub report: FixReport,
+    /// No fix applied, so there is nothing worth writing.
+    pub unchanged: bool,
+    /// Diffs of the changed files, when `FixOptions::diff` is set.
+    pub diffs: Vec<FileDiff>,
+}
+
+/// Unified diff between the original and fixed text of a file in a book.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileDiff {
+    /// Path of the file inside the book.
+    pub file: String,
+    pub diff: String,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum FileFormat {
     Epub,
     Mobi,
@@ -51,6 +353,18 @@ pub enum FileFormat {
     Unknown,
 }
 
+impl FileFormat {
+    /// Conventional file extension for this format, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Epub => "epub",
+            FileFormat::Mobi => "mobi",
+            FileFormat::Azw3 => "azw3",
+            FileFormat::Unknown => "",
+        }
+    }
+}
+
 impl fmt::Display for FileFormat {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -0,0 +1,75 @@
+mod helpers;
+
+use kindle_fix_core::{check_file, DiagnosticCode, FixOptions, Severity};
+
+#[test]
+fn reports_problems_without_fixing() {
+    let epub = helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/content.opf", &helpers::opf_with_language("en")),
+        ("OEBPS/chapter1.xhtml", "<html><body><img /></body></html>"),
+    ]);
+
+    let report = check_file(&epub, "book.epub", &FixOptions::default()).unwrap();
+    assert_eq!(report.filename, "book.epub");
+
+    let stray_img = report
+        .problems
+        .iter()
+        .find(|problem| problem.fix.as_deref() == Some("stray_img"))
+        .unwrap();
+    assert_eq!(stray_img.severity, Severity::Error);
+    assert_eq!(stray_img.code, DiagnosticCode::StrayImage);
+
+    let encoding = report
+        .problems
+        .iter()
+        .find(|problem| problem.fix.as_deref() == Some("encoding"))
+        .unwrap();
+    assert_eq!(encoding.severity, Severity::Warning);
+    assert_eq!(report.max_severity(), Some(Severity::Error));
+}
+
+#[test]
+fn clean_book_has_no_problems() {
+    let epub = helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        (
+            "OEBPS/content.opf",
+            &helpers::opf_with_items("en", &[("chapter1.xhtml", "application/xhtml+xml")]),
+        ),
+        (
+            "OEBPS/chapter1.xhtml",
+            r#"<?xml version="1.0" encoding="utf-8"?><html><body>Hi</body></html>"#,
+        ),
+    ]);
+
+    let report = check_file(&epub, "book.epub", &FixOptions::default()).unwrap();
+    assert!(!report.has_problems(), "{:?}", report.problems);
+    assert_eq!(report.max_severity(), None);
+}
+
+#[test]
+fn warnings_are_reported_as_problems() {
+    let epub = helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/content.opf", &helpers::opf_with_language("tlh")),
+    ]);
+
+    let report = check_file(&epub, "book.epub", &FixOptions::default()).unwrap();
+    let problem = &report.problems[0];
+    assert_eq!(problem.code, DiagnosticCode::UnsupportedLanguage);
+    assert_eq!(problem.severity, Severity::Warning);
+    assert_eq!(problem.fix.as_deref(), Some("language"));
+    assert!(problem.message.contains("tlh"));
+    assert_eq!(problem.location().as_deref(), Some("OEBPS/content.opf:5:5"));
+}
+
+#[test]
+fn checks_mobi_books() {
+    let data = helpers::build_mobi("No Language", &[], &[b"<p>Hello</p>"]);
+
+    let report = check_file(&data, "book.mobi", &FixOptions::default()).unwrap();
+    assert_eq!(report.problems.len(), 1);
+    assert_eq!(report.problems[0].fix.as_deref(), Some("language"));
+}
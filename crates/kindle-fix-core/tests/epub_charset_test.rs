@@ -0,0 +1,149 @@
+mod helpers;
+
+use encoding_rs::{UTF_16LE, UTF_8, WINDOWS_1252};
+use kindle_fix_core::formats::epub::charset::{decode_text, detect_encoding, try_decode_text};
+use kindle_fix_core::formats::epub::reader::EpubReader;
+use kindle_fix_core::{process_file, DiagnosticCode, FixOptions};
+
+#[test]
+fn detects_charset_from_bom() {
+    let bytes = [0xFF, 0xFE, b'<', 0, b'p', 0, b'>', 0];
+    assert_eq!(detect_encoding(&bytes), UTF_16LE);
+    assert_eq!(decode_text(&bytes).0, "<p>");
+
+    let (text, encoding) = decode_text(b"\xEF\xBB\xBF<p>caf\xC3\xA9</p>");
+    assert_eq!(encoding, UTF_8);
+    assert_eq!(text, "<p>café</p>");
+}
+
+#[test]
+fn detects_charset_from_declarations() {
+    let xml = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><p>caf\xE9</p>";
+    assert_eq!(detect_encoding(xml), WINDOWS_1252);
+    assert_eq!(
+        decode_text(xml).0,
+        "<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><p>café</p>"
+    );
+
+    let meta = b"<html><head><meta charset=\"windows-1252\"></head><p>\x93hi\x94</p></html>";
+    assert_eq!(
+        decode_text(meta).0,
+        "<html><head><meta charset=\"windows-1252\"></head><p>\u{201C}hi\u{201D}</p></html>"
+    );
+}
+
+#[test]
+fn falls_back_to_heuristics_for_mislabelled_files() {
+    let mislabelled =
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?><p>Ce texte français a été écrit à la main.</p>";
+    let (bytes, _, _) = WINDOWS_1252.encode(mislabelled);
+    assert_ne!(detect_encoding(&bytes), UTF_8);
+    assert_eq!(decode_text(&bytes).0, mislabelled);
+
+    assert_eq!(detect_encoding("<p>déjà vu</p>".as_bytes()), UTF_8);
+}
+
+#[test]
+fn reader_transcodes_latin1_entries() {
+    let epub = helpers::build_epub_bytes(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML.as_bytes()),
+        (
+            "OEBPS/chapter1.xhtml",
+            b"<?xml version=\"1.0\" encoding=\"iso-8859-1\"?><html><body>Caf\xE9</body></html>",
+        ),
+    ]);
+
+    let reader = EpubReader::from_bytes(&epub).unwrap();
+    assert!(reader.text_files()["OEBPS/chapter1.xhtml"].contains("Café"));
+    assert_eq!(
+        reader.transcoded(),
+        [(
+            "OEBPS/chapter1.xhtml".to_string(),
+            "windows-1252".to_string()
+        )]
+    );
+}
+
+#[test]
+fn fixing_transcodes_and_rewrites_declarations() {
+    let epub = helpers::build_epub_bytes(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML.as_bytes()),
+        (
+            "OEBPS/content.opf",
+            helpers::opf_with_items("fr", &[("chapter1.xhtml", "application/xhtml+xml")])
+                .as_bytes(),
+        ),
+        (
+            "OEBPS/chapter1.xhtml",
+            b"<?xml version=\"1.0\" encoding=\"iso-8859-1\"?><html><body>Caf\xE9</body></html>",
+        ),
+    ]);
+
+    let output = process_file(&epub, "book.epub", &FixOptions::default()).unwrap();
+    let names: Vec<&str> = output
+        .report
+        .fixes_applied
+        .iter()
+        .map(|f| f.name.as_str())
+        .collect();
+    assert_eq!(names, ["transcode", "encoding"]);
+    assert_eq!(
+        output.report.fixes_applied[0].details,
+        "Transcoded 1 file(s) from windows-1252 to UTF-8"
+    );
+
+    let reader = EpubReader::from_bytes(&output.data).unwrap();
+    assert!(reader.transcoded().is_empty());
+    assert_eq!(
+        reader.text_files()["OEBPS/chapter1.xhtml"],
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?><html><body>Café</body></html>"
+    );
+}
+
+#[test]
+fn prefers_valid_utf8_over_declared_single_byte_charset() {
+    let xml = "<?xml version=\"1.0\" encoding=\"iso-8859-1\"?><p>café</p>";
+    assert_eq!(detect_encoding(xml.as_bytes()), UTF_8);
+    assert_eq!(decode_text(xml.as_bytes()).0, xml);
+
+    // Plain ASCII keeps its declaration
+    let ascii = b"<?xml version=\"1.0\" encoding=\"iso-8859-1\"?><p>cafe</p>";
+    assert_eq!(detect_encoding(ascii), WINDOWS_1252);
+}
+
+#[test]
+fn decodes_nearly_utf8_text_as_utf8() {
+    let mut bytes = "<p>Déjà vu, café crème à la française.".as_bytes().to_vec();
+    bytes.push(0xA0);
+    bytes.extend_from_slice(b"</p>");
+    assert_eq!(detect_encoding(&bytes), UTF_8);
+
+    let decoded = try_decode_text(&bytes).unwrap();
+    assert_eq!(decoded.encoding, UTF_8);
+    assert_eq!(
+        decoded.text,
+        "<p>Déjà vu, café crème à la française.\u{FFFD}</p>"
+    );
+    assert!(decoded.guessed);
+}
+
+#[test]
+fn reader_warns_about_guessed_charsets() {
+    let (chapter, _, _) =
+        WINDOWS_1252.encode("<html><body>Ce texte français a été écrit à la main.</body></html>");
+    let epub = helpers::build_epub_bytes(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML.as_bytes()),
+        ("OEBPS/chapter1.xhtml", &chapter),
+        (
+            "OEBPS/chapter2.xhtml",
+            b"<?xml version=\"1.0\" encoding=\"iso-8859-1\"?><html><body>Caf\xE9</body></html>",
+        ),
+    ]);
+
+    let reader = EpubReader::from_bytes(&epub).unwrap();
+    assert_eq!(reader.warnings().len(), 1);
+    let warning = &reader.warnings()[0];
+    assert_eq!(warning.code, DiagnosticCode::GuessedCharset);
+    assert_eq!(warning.file.as_deref(), Some("OEBPS/chapter1.xhtml"));
+    assert!(warning.message.contains("windows-1252"));
+}
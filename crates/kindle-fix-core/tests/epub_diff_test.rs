@@ -0,0 +1,74 @@
+mod helpers;
+
+use std::collections::HashMap;
+
+use kindle_fix_core::formats::epub::diff::{diff_files, unified_diff};
+use kindle_fix_core::{process_file, FixOptions};
+
+fn build_book() -> Vec<u8> {
+    helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        (
+            "OEBPS/content.opf",
+            &helpers::opf_with_items(
+                "en",
+                &[
+                    ("chapter1.xhtml", "application/xhtml+xml"),
+                    ("style.css", "text/css"),
+                ],
+            ),
+        ),
+        (
+            "OEBPS/chapter1.xhtml",
+            "<html>\n<body><img/><p>Hi</p></body>\n</html>\n",
+        ),
+        ("OEBPS/style.css", "p { margin: 0 }\n"),
+    ])
+}
+
+#[test]
+fn diffs_changed_files_when_requested() {
+    let options = FixOptions {
+        dry_run: true,
+        diff: true,
+        ..Default::default()
+    };
+
+    let output = process_file(&build_book(), "book.epub", &options).unwrap();
+    assert_eq!(output.diffs.len(), 1);
+
+    let diff = &output.diffs[0];
+    assert_eq!(diff.file, "OEBPS/chapter1.xhtml");
+    assert!(diff
+        .diff
+        .starts_with("--- a/OEBPS/chapter1.xhtml\n+++ b/OEBPS/chapter1.xhtml\n"));
+    assert!(diff.diff.contains("-<body><img/><p>Hi</p></body>\n"));
+    assert!(diff.diff.contains("+<body><p>Hi</p></body>\n"));
+    assert!(diff
+        .diff
+        .contains("+<?xml version=\"1.0\" encoding=\"utf-8\"?>\n"));
+}
+
+#[test]
+fn no_diffs_unless_requested() {
+    let output = process_file(&build_book(), "book.epub", &FixOptions::default()).unwrap();
+    assert!(output.diffs.is_empty());
+}
+
+#[test]
+fn diffs_files_in_name_order() {
+    let before: HashMap<String, String> = [
+        ("b.xhtml".to_string(), "one\n".to_string()),
+        ("a.xhtml".to_string(), "same\n".to_string()),
+    ]
+    .into();
+    let mut after = before.clone();
+    after.insert("b.xhtml".to_string(), "two\n".to_string());
+    after.insert("c.xhtml".to_string(), "new\n".to_string());
+
+    let diffs = diff_files(&before, &after);
+    let names: Vec<&str> = diffs.iter().map(|diff| diff.file.as_str()).collect();
+    assert_eq!(names, ["b.xhtml", "c.xhtml"]);
+    assert_eq!(diffs[0].diff, unified_diff("b.xhtml", "one\n", "two\n"));
+    assert!(diffs[1].diff.contains("+new\n"));
+}
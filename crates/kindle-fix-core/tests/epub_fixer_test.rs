@@ -52,7 +52,10 @@ fn does_not_detect_random_zip() {
 fn clean_epub_produces_no_fixes() {
     let epub = helpers::build_epub(&[
         ("META-INF/container.xml", helpers::CONTAINER_XML),
-        ("OEBPS/content.opf", &helpers::opf_with_language("en")),
+        (
+            "OEBPS/content.opf",
+            &helpers::opf_with_items("en", &[("chapter1.xhtml", "application/xhtml+xml")]),
+        ),
         (
             "OEBPS/chapter1.xhtml",
             "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<html><body>Hello</body></html>",
@@ -79,3 +82,55 @@ fn output_is_valid_epub() {
         kindle_fix_core::formats::epub::reader::EpubReader::from_bytes(&output.data).unwrap();
     assert!(reader.text_files().contains_key("mimetype"));
 }
+
+#[test]
+fn undecodable_entry_does_not_block_other_fixes() {
+    use kindle_fix_core::formats::epub::reader::EpubReader;
+
+    let toc: &[u8] = b"<?xml version=\"1.0\" encoding=\"euc-jp\"?><ncx>\x8E\x20</ncx>";
+    let epub = helpers::build_epub_bytes(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML.as_bytes()),
+        ("OEBPS/content.opf", helpers::opf_with_language("en").as_bytes()),
+        ("OEBPS/toc.ncx", toc),
+        ("OEBPS/chapter1.xhtml", b"<html><body>Hello</body></html>"),
+    ]);
+
+    let output = EpubFixer.fix(&epub, &FixOptions::default()).unwrap();
+    assert!(output.report.fixes_applied.iter().any(|f| f.name == "encoding"));
+    assert!(output.report.warnings.iter().any(|w| w.file.as_deref() == Some("OEBPS/toc.ncx")));
+
+    let reader = EpubReader::from_bytes(&output.data).unwrap();
+    assert_eq!(reader.binary_files()["OEBPS/toc.ncx"], toc);
+}
+
+#[test]
+fn fix_descriptions_list_individual_changes() {
+    let epub = helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/content.opf", &helpers::opf_without_language()),
+        (
+            "OEBPS/chapter1.xhtml",
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<html><body><img/></body></html>",
+        ),
+    ]);
+
+    let output = EpubFixer.fix(&epub, &FixOptions::default()).unwrap();
+    let fix = |name: &str| {
+        output
+            .report
+            .fixes_applied
+            .iter()
+            .find(|fix| fix.name == name)
+            .unwrap()
+    };
+
+    let stray_img = &fix("stray_img").changes[0];
+    assert_eq!(stray_img.file, "OEBPS/chapter1.xhtml");
+    assert_eq!(stray_img.original, "<img/>");
+    assert_eq!((stray_img.line, stray_img.column), (Some(2), Some(13)));
+
+    let language = &fix("language").changes[0];
+    assert_eq!(language.file, "OEBPS/content.opf");
+    assert_eq!(language.replacement, "<dc:language>en</dc:language>");
+    assert!(language.line.is_some());
+}
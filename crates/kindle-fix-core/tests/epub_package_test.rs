@@ -0,0 +1,251 @@
+use kindle_fix_core::formats::epub::package::{
+    find_opf_path, Container, Item, ItemRef, Node, Package, OPF_MEDIA_TYPE,
+};
+
+const OPF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- made by hand -->
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="uid" xml:lang="en">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:title>Fish &amp; Chips</dc:title>
+    <dc:creator opf:role="aut" opf:file-as="Doe, Jane">Jane Doe</dc:creator>
+    <dc:language>en-GB</dc:language>
+    <dc:identifier id="uid">urn:uuid:1</dc:identifier>
+    <meta name="cover" content="cover"/>
+    <!-- generator notes -->
+    <x:custom xmlns:x="urn:example">kept</x:custom>
+  </metadata>
+  <manifest>
+    <item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+    <item id="cover" href="Images/cover.jpg" media-type="image/jpeg" properties="cover-image"/>
+    <item id="chapter1" href="Text/chapter1.xhtml" media-type="application/xhtml+xml" data-x="1"/>
+  </manifest>
+  <spine toc="ncx" page-progression-direction="ltr">
+    <itemref idref="chapter1"/>
+    <itemref idref="cover" linear="no"/>
+  </spine>
+  <guide>
+    <reference type="cover" title="Cover" href="Images/cover.jpg"/>
+  </guide>
+  <bindings>
+    <mediaType media-type="application/x-demo" handler="demo"/>
+  </bindings>
+</package>
+"#;
+
+#[test]
+fn container_lists_package_documents_in_order() {
+    let container = Container::parse(
+        r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="preview.pdf" media-type="application/pdf"/>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+    <rootfile full-path="alt/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#,
+    )
+    .unwrap();
+
+    assert_eq!(container.rootfiles.len(), 3);
+    assert_eq!(container.rootfiles[1].media_type, OPF_MEDIA_TYPE);
+    let paths: Vec<&str> = container.package_paths().collect();
+    assert_eq!(paths, ["OEBPS/content.opf", "alt/content.opf"]);
+    assert_eq!(container.opf_path(), Some("OEBPS/content.opf"));
+}
+
+#[test]
+fn find_opf_path_needs_a_package_rootfile() {
+    assert_eq!(
+        find_opf_path(
+            r#"<container><rootfiles><rootfile full-path="a.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#
+        ),
+        Some("a.opf".to_string())
+    );
+    assert_eq!(
+        find_opf_path(
+            r#"<container><rootfiles><rootfile full-path="a.pdf" media-type="application/pdf"/></rootfiles></container>"#
+        ),
+        None
+    );
+    assert_eq!(find_opf_path("not xml"), None);
+}
+
+#[test]
+fn parses_metadata_manifest_spine_and_guide() {
+    let package = Package::parse(OPF).unwrap();
+    assert_eq!(package.version(), Some("2.0"));
+    assert_eq!(package.attribute("xml:lang"), Some("en"));
+
+    let metadata = &package.metadata;
+    assert_eq!(metadata.dc_text("title").as_deref(), Some("Fish & Chips"));
+    assert_eq!(metadata.dc_text("language").as_deref(), Some("en-GB"));
+    let creator = metadata.dc("creator").next().unwrap();
+    assert_eq!(creator.attribute("opf:role"), Some("aut"));
+    assert_eq!(metadata.meta("cover"), Some("cover"));
+    assert_eq!(metadata.dc("custom").count(), 0);
+
+    let manifest = &package.manifest;
+    assert_eq!(manifest.items.len(), 3);
+    let cover = manifest.item("cover").unwrap();
+    assert_eq!(cover.href, "Images/cover.jpg");
+    assert_eq!(cover.properties.as_deref(), Some("cover-image"));
+    let chapter = manifest.item("chapter1").unwrap();
+    assert_eq!(
+        chapter.attributes,
+        [("data-x".to_string(), "1".to_string())]
+    );
+
+    let spine = &package.spine;
+    assert_eq!(spine.toc.as_deref(), Some("ncx"));
+    let order: Vec<(&str, bool)> = spine
+        .itemrefs
+        .iter()
+        .map(|itemref| (itemref.idref.as_str(), itemref.is_linear()))
+        .collect();
+    assert_eq!(order, [("chapter1", true), ("cover", false)]);
+
+    let guide = package.guide.as_ref().unwrap();
+    assert_eq!(guide.references[0].kind, "cover");
+    assert_eq!(guide.references[0].title.as_deref(), Some("Cover"));
+
+    assert!(matches!(&package.other[0], Node::Element(e) if e.name == "bindings"));
+}
+
+#[test]
+fn writes_unedited_package_back_unchanged() {
+    assert_eq!(Package::parse(OPF).unwrap().to_xml(), OPF);
+}
+
+#[test]
+fn writes_undefined_entities_back_as_they_were() {
+    let opf = OPF.replace("Fish &amp; Chips", "Tom &amp; Jerry&nbsp;Stories");
+    let mut package = Package::parse(&opf).unwrap();
+    assert_eq!(
+        package.metadata.dc_text("title").as_deref(),
+        Some("Tom & Jerry&nbsp;Stories")
+    );
+
+    package.metadata.add_dc("language", "fr");
+    let written = package.to_xml();
+    assert!(written.contains("<dc:title>Tom &amp; Jerry&nbsp;Stories</dc:title>"));
+}
+
+#[test]
+fn writes_undefined_entities_in_attributes_back_as_they_were() {
+    let opf = OPF.replace(
+        "opf:file-as=\"Doe, Jane\"",
+        "opf:file-as=\"Bront&euml;, Emily &amp; Anne\"",
+    );
+    let mut package = Package::parse(&opf).unwrap();
+    let creator = package.metadata.dc("creator").next().unwrap();
+    assert_eq!(
+        creator.attribute("opf:file-as"),
+        Some("Bront&euml;, Emily & Anne")
+    );
+
+    package.metadata.add_dc("language", "fr");
+    let written = package.to_xml();
+    assert!(written.contains("opf:file-as=\"Bront&euml;, Emily &amp; Anne\""));
+}
+
+#[test]
+fn keeps_package_children_in_their_order() {
+    let opf = OPF.replace(
+        "  </manifest>\n  <spine",
+        "  </manifest>\n  <!-- reading order -->\n  <spine",
+    );
+    let mut package = Package::parse(&opf).unwrap();
+    assert_eq!(package.to_xml(), opf);
+
+    package.guide = None;
+    package.other.push(Node::Comment(" added ".to_string()));
+    let written = package.to_xml();
+    assert!(written.contains("</manifest>\n  <!-- reading order -->\n  <spine"));
+    assert!(written.contains("</bindings>\n  <!-- added -->\n</package>"));
+}
+
+#[test]
+fn keeps_tab_indentation_and_prefixed_elements() {
+    let opf = "<opf:package xmlns:opf=\"http://www.idpf.org/2007/opf\" version=\"3.0\">\n\t<opf:metadata>\n\t\t<dc:title>T</dc:title>\n\t</opf:metadata>\n\t<opf:manifest/>\n\t<opf:spine/>\n</opf:package>";
+
+    let mut package = Package::parse(opf).unwrap();
+    assert_eq!(package.prefix, "opf:");
+    assert_eq!(package.to_xml(), opf);
+
+    package
+        .manifest
+        .items
+        .push(Item::new("c1", "c1.xhtml", "application/xhtml+xml"));
+    assert!(package.to_xml().contains(
+        "\t<opf:manifest>\n\t\t<opf:item id=\"c1\" href=\"c1.xhtml\" media-type=\"application/xhtml+xml\"/>\n\t</opf:manifest>"
+    ));
+}
+
+#[test]
+fn edits_survive_a_round_trip() {
+    let mut package = Package::parse(OPF).unwrap();
+
+    for language in package.metadata.dc_mut("language") {
+        language.set_text("fr");
+    }
+    package.metadata.add_dc("language", "en");
+    package.manifest.items.push(Item::new(
+        "chapter2",
+        "Text/chapter2.xhtml",
+        "application/xhtml+xml",
+    ));
+    package.spine.itemrefs.insert(1, ItemRef::new("chapter2"));
+    package.manifest.remove("ncx");
+    package.spine.toc = None;
+    package.set_attribute("xml:lang", "fr");
+
+    let written = package.to_xml();
+    assert!(written.contains(
+        "    <dc:language>fr</dc:language>\n    <dc:language>en</dc:language>\n    <dc:identifier"
+    ));
+    assert!(written.contains("<spine page-progression-direction=\"ltr\">"));
+    assert!(written.contains("<x:custom xmlns:x=\"urn:example\">kept</x:custom>"));
+    assert!(written.contains("<!-- generator notes -->"));
+
+    let reread = Package::parse(&written).unwrap();
+    let languages: Vec<String> = reread.metadata.dc("language").map(|e| e.text()).collect();
+    assert_eq!(languages, ["fr", "en"]);
+    assert!(reread.manifest.item("ncx").is_none());
+    let order: Vec<&str> = reread
+        .spine
+        .itemrefs
+        .iter()
+        .map(|i| i.idref.as_str())
+        .collect();
+    assert_eq!(order, ["chapter1", "chapter2", "cover"]);
+    assert_eq!(reread.attribute("xml:lang"), Some("fr"));
+    assert_eq!(reread.other, package.other);
+}
+
+#[test]
+fn only_dublin_core_elements_are_dc() {
+    let package = Package::parse(
+        r#"<package xmlns:purl="http://purl.org/dc/elements/1.1/">
+  <metadata xmlns:x="urn:example">
+    <x:language>xx</x:language>
+    <purl:language>de</purl:language>
+  </metadata>
+  <manifest/>
+  <spine/>
+</package>"#,
+    )
+    .unwrap();
+
+    assert_eq!(package.metadata.dc_text("language").as_deref(), Some("de"));
+    assert_eq!(package.metadata.dc("language").count(), 1);
+
+    let mut package = package;
+    package.metadata.add_dc("title", "T");
+    assert!(package.to_xml().contains("<purl:title>T</purl:title>"));
+}
+
+#[test]
+fn rejects_documents_that_are_not_packages() {
+    assert!(Package::parse("<html><body/></html>").is_err());
+    assert!(Package::parse("").is_err());
+}
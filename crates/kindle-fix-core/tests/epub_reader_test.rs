@@ -1,6 +1,7 @@
 mod helpers;
 
 use kindle_fix_core::formats::epub::reader::EpubReader;
+use kindle_fix_core::DiagnosticCode;
 
 #[test]
 fn reads_text_files_from_epub() {
@@ -46,3 +47,19 @@ fn separates_binary_files() {
     assert!(reader.binary_files().contains_key("OEBPS/image.png"));
     assert!(!reader.text_files().contains_key("OEBPS/image.png"));
 }
+
+#[test]
+fn keeps_undecodable_text_entries_as_binary() {
+    let stylesheet: &[u8] = b"@charset \"shift_jis\";\np { content: \"\x81\x20\" }";
+    let epub = helpers::build_epub_bytes(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML.as_bytes()),
+        ("OEBPS/style.css", stylesheet),
+    ]);
+
+    let reader = EpubReader::from_bytes(&epub).unwrap();
+    assert!(!reader.text_files().contains_key("OEBPS/style.css"));
+    assert_eq!(reader.binary_files()["OEBPS/style.css"], stylesheet);
+    assert_eq!(reader.warnings().len(), 1);
+    assert_eq!(reader.warnings()[0].code, DiagnosticCode::UndecodableText);
+    assert_eq!(reader.warnings()[0].file.as_deref(), Some("OEBPS/style.css"));
+}
@@ -0,0 +1,199 @@
+mod helpers;
+
+use std::io::{Cursor, Read};
+
+use kindle_fix_core::formats::epub::stamp::{read_stamp, write_stamp, Stamp};
+use kindle_fix_core::{process_file, version, FixOptions};
+
+fn stamp(fixes: &[&str]) -> Stamp {
+    Stamp {
+        version: "1.2.3".into(),
+        fixes: fixes.iter().map(|id| id.to_string()).collect(),
+    }
+}
+
+fn read_entry(epub: &[u8], name: &str) -> String {
+    let mut archive = zip::ZipArchive::new(Cursor::new(epub)).unwrap();
+    let mut content = String::new();
+    archive
+        .by_name(name)
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    content
+}
+
+fn book_without_language() -> Vec<u8> {
+    helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/content.opf", &helpers::opf_without_language()),
+        (
+            "OEBPS/chapter1.xhtml",
+            r#"<?xml version="1.0" encoding="UTF-8"?><html><body>Hi</body></html>"#,
+        ),
+    ])
+}
+
+#[test]
+fn stamp_round_trips_and_replaces_itself() {
+    let opf = helpers::opf_with_language("en");
+
+    let stamped = write_stamp(&opf, &stamp(&["body_id", "language"])).unwrap();
+    assert!(stamped.contains(
+        "    <meta name=\"kindle-file-fix:fixes\" content=\"body_id,language\"/>\n  </metadata>"
+    ));
+    assert_eq!(read_stamp(&stamped), Some(stamp(&["body_id", "language"])));
+
+    let restamped = write_stamp(&stamped, &stamp(&["encoding"])).unwrap();
+    assert_eq!(restamped.matches("kindle-file-fix:version").count(), 1);
+    assert_eq!(read_stamp(&restamped), Some(stamp(&["encoding"])));
+    assert_eq!(
+        write_stamp(&restamped, &stamp(&[]))
+            .unwrap()
+            .lines()
+            .count(),
+        opf.lines().count() + 2
+    );
+}
+
+#[test]
+fn stamp_uses_prefix_of_metadata_element() {
+    let opf = r#"<opf:package><opf:metadata><dc:title>T</dc:title></opf:metadata></opf:package>"#;
+
+    let stamped = write_stamp(opf, &stamp(&["language"])).unwrap();
+    assert!(stamped.contains(
+        "<opf:meta name=\"kindle-file-fix:version\" content=\"1.2.3\"/>\n    <opf:meta name=\"kindle-file-fix:fixes\" content=\"language\"/>\n  </opf:metadata>"
+    ));
+    assert_eq!(read_stamp(&stamped), Some(stamp(&["language"])));
+}
+
+#[test]
+fn unstamped_opf_has_no_stamp() {
+    assert_eq!(read_stamp(&helpers::opf_with_language("en")), None);
+    assert_eq!(write_stamp("<html/>", &stamp(&[])), None);
+}
+
+#[test]
+fn changed_book_is_stamped_only_when_asked() {
+    let epub = book_without_language();
+
+    let plain = process_file(&epub, "book.epub", &FixOptions::default()).unwrap();
+    assert!(!read_entry(&plain.data, "OEBPS/content.opf").contains("kindle-file-fix"));
+
+    let options = FixOptions {
+        stamp: true,
+        ..Default::default()
+    };
+    let output = process_file(&epub, "book.epub", &options).unwrap();
+    let stamp = read_stamp(&read_entry(&output.data, "OEBPS/content.opf")).unwrap();
+    assert_eq!(stamp.version, version());
+    assert_eq!(
+        stamp.fixes,
+        [
+            "body_id",
+            "broken_links",
+            "encoding",
+            "language",
+            "manifest",
+            "stray_img"
+        ]
+    );
+}
+
+#[test]
+fn rerun_on_stamped_book_is_a_no_op() {
+    let options = FixOptions {
+        stamp: true,
+        ..Default::default()
+    };
+    let first = process_file(&book_without_language(), "book.epub", &options).unwrap();
+
+    let second = process_file(&first.data, "book.epub", &options).unwrap();
+    assert!(second.unchanged);
+    assert_eq!(second.data, first.data);
+    assert_eq!(second.report.stamp.unwrap().version, version());
+    assert_eq!(
+        second.report.already_applied,
+        [
+            "body_id",
+            "broken_links",
+            "language",
+            "stray_img",
+            "encoding",
+            "manifest"
+        ]
+    );
+}
+
+#[test]
+fn rerun_only_runs_fixes_missing_from_stamp() {
+    let first = FixOptions {
+        stamp: true,
+        only: Some(vec!["body_id".into()]),
+        ..Default::default()
+    };
+    let epub = helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/content.opf", &helpers::opf_without_language()),
+        (
+            "OEBPS/chapter1.xhtml",
+            r#"<html><body id="b"><a href="chapter1.xhtml#b">Top</a></body></html>"#,
+        ),
+    ]);
+    let first = process_file(&epub, "book.epub", &first).unwrap();
+
+    let options = FixOptions {
+        stamp: true,
+        ..Default::default()
+    };
+    let second = process_file(&first.data, "book.epub", &options).unwrap();
+    assert_eq!(second.report.already_applied, ["body_id"]);
+    let names: Vec<&str> = second
+        .report
+        .fixes_applied
+        .iter()
+        .map(|fix| fix.name.as_str())
+        .collect();
+    assert_eq!(names, ["language", "encoding", "manifest"]);
+
+    let stamp = read_stamp(&read_entry(&second.data, "OEBPS/content.opf")).unwrap();
+    assert_eq!(
+        stamp.fixes,
+        [
+            "body_id",
+            "broken_links",
+            "encoding",
+            "language",
+            "manifest",
+            "stray_img"
+        ]
+    );
+}
+
+#[test]
+fn force_ignores_stamp() {
+    let opf = write_stamp(&helpers::opf_without_language(), &stamp(&["language"])).unwrap();
+    let epub = helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/content.opf", &opf),
+    ]);
+
+    let skipped = process_file(&epub, "book.epub", &FixOptions::default()).unwrap();
+    assert!(!skipped
+        .report
+        .fixes_applied
+        .iter()
+        .any(|f| f.name == "language"));
+
+    let options = FixOptions {
+        force: true,
+        ..Default::default()
+    };
+    let forced = process_file(&epub, "book.epub", &options).unwrap();
+    assert!(forced
+        .report
+        .fixes_applied
+        .iter()
+        .any(|f| f.name == "language"));
+    assert!(forced.report.already_applied.is_empty());
+}
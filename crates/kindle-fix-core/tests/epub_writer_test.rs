@@ -43,3 +43,140 @@ fn mimetype_is_first_and_uncompressed() {
         zip::CompressionMethod::Stored
     );
 }
+
+fn build_archive_with_metadata(year: u16) -> Vec<u8> {
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::{DateTime, ZipWriter};
+
+    let time = DateTime::from_date_and_time(year, 6, 1, 12, 30, 0).unwrap();
+    let stored = SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .last_modified_time(time);
+    let deflated = SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .last_modified_time(time);
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    zip.start_file("mimetype", stored).unwrap();
+    zip.write_all(b"application/epub+zip").unwrap();
+    zip.add_directory("OEBPS/", stored).unwrap();
+    zip.start_file("OEBPS/z-last.xhtml", deflated.unix_permissions(0o600)).unwrap();
+    zip.write_all(b"<html><body>Z</body></html>").unwrap();
+    zip.start_file("OEBPS/cover.jpg", stored).unwrap();
+    zip.write_all(&[0xFF, 0xD8, 0xFF, 0xE0]).unwrap();
+    zip.start_file("META-INF/container.xml", deflated).unwrap();
+    zip.write_all(helpers::CONTAINER_XML.as_bytes()).unwrap();
+    zip.start_file("OEBPS/content.opf", deflated).unwrap();
+    zip.write_all(helpers::opf_with_language("en").as_bytes()).unwrap();
+    zip.set_comment("archived copy");
+    zip.finish().unwrap().into_inner()
+}
+
+#[test]
+fn preserves_entry_order_and_metadata() {
+    let original = build_archive_with_metadata(2015);
+
+    let reader = EpubReader::from_bytes(&original).unwrap();
+    let manifest = reader.manifest().clone();
+    let (mut text_files, binary_files) = reader.into_parts();
+    text_files.insert("OEBPS/a-new.css".to_string(), "p {}".to_string());
+    let output = EpubWriter::write_with_manifest(&text_files, &binary_files, &manifest).unwrap();
+
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(&output)).unwrap();
+    let names: Vec<String> = (0..archive.len())
+        .map(|i| archive.by_index(i).unwrap().name().to_string())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "mimetype",
+            "OEBPS/",
+            "OEBPS/z-last.xhtml",
+            "OEBPS/cover.jpg",
+            "META-INF/container.xml",
+            "OEBPS/content.opf",
+            "OEBPS/a-new.css",
+        ]
+    );
+    assert_eq!(archive.comment(), b"archived copy");
+
+    let chapter = archive.by_name("OEBPS/z-last.xhtml").unwrap();
+    assert_eq!(chapter.unix_mode().unwrap() & 0o777, 0o600);
+    let modified = chapter.last_modified().unwrap();
+    assert_eq!((modified.year(), modified.month(), modified.day()), (2015, 6, 1));
+    drop(chapter);
+
+    let cover = archive.by_name("OEBPS/cover.jpg").unwrap();
+    assert_eq!(cover.compression(), zip::CompressionMethod::Stored);
+}
+
+#[test]
+fn fixing_identical_input_gives_identical_output() {
+    use kindle_fix_core::{process_file, FixOptions};
+
+    let original = build_archive_with_metadata(2015);
+
+    let first = process_file(&original, "book.epub", &FixOptions::default()).unwrap();
+    let second = process_file(&original, "book.epub", &FixOptions::default()).unwrap();
+    assert!(first.report.has_fixes());
+    assert_eq!(first.data, second.data);
+}
+
+#[test]
+fn reproducible_output_ignores_original_timestamps_and_order() {
+    use kindle_fix_core::formats::epub::writer::{zip_timestamp, EntryOrder, Reproducible};
+
+    let settings = Reproducible {
+        order: EntryOrder::Sorted,
+        timestamp: zip_timestamp(1_700_000_000),
+    };
+    let write = |epub: &[u8]| {
+        let reader = EpubReader::from_bytes(epub).unwrap();
+        let manifest = reader.manifest().clone();
+        let (text_files, binary_files) = reader.into_parts();
+        EpubWriter::write_reproducible(&text_files, &binary_files, &manifest, &settings).unwrap()
+    };
+
+    let first = write(&build_archive_with_metadata(2015));
+    let second = write(&build_archive_with_metadata(2020));
+    assert_eq!(first, second);
+
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(&first)).unwrap();
+    let names: Vec<String> = (0..archive.len())
+        .map(|i| archive.by_index(i).unwrap().name().to_string())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "mimetype",
+            "META-INF/container.xml",
+            "OEBPS/",
+            "OEBPS/content.opf",
+            "OEBPS/cover.jpg",
+            "OEBPS/z-last.xhtml",
+        ]
+    );
+    let chapter = archive.by_name("OEBPS/z-last.xhtml").unwrap();
+    assert_eq!(chapter.unix_mode().unwrap() & 0o777, 0o644);
+    assert_eq!(chapter.compression(), zip::CompressionMethod::Deflated);
+    let modified = chapter.last_modified().unwrap();
+    assert_eq!(
+        (modified.year(), modified.month(), modified.day(), modified.hour()),
+        (2023, 11, 14, 22)
+    );
+    drop(chapter);
+
+    assert_eq!(write(&first), first);
+}
+
+#[test]
+fn zip_timestamp_clamps_to_zip_range() {
+    use kindle_fix_core::formats::epub::writer::zip_timestamp;
+
+    let early = zip_timestamp(0);
+    assert_eq!((early.year(), early.month(), early.day()), (1980, 1, 1));
+    let leap = zip_timestamp(951_782_400);
+    assert_eq!((leap.year(), leap.month(), leap.day()), (2000, 2, 29));
+    assert_eq!(zip_timestamp(i64::from(u32::MAX) * 2).year(), 2107);
+}
@@ -17,6 +17,10 @@ fn replaces_body_id_references() {
 
     let fixes = fix_body_id_links(&mut files);
     assert_eq!(fixes.len(), 1);
+    assert_eq!(fixes[0].file, "OEBPS/toc.ncx");
+    assert_eq!(fixes[0].original, "chapter1.xhtml#chapter1body");
+    assert_eq!(fixes[0].replacement, "chapter1.xhtml");
+    assert_eq!((fixes[0].line, fixes[0].column), (Some(1), Some(25)));
     assert!(files["OEBPS/toc.ncx"].contains("chapter1.xhtml\""));
     assert!(!files["OEBPS/toc.ncx"].contains("#chapter1body"));
 }
@@ -71,3 +75,86 @@ fn handles_multiple_files_with_body_ids() {
     assert!(files["OEBPS/toc.ncx"].contains("ch1.xhtml\""));
     assert!(files["OEBPS/toc.ncx"].contains("ch2.xhtml\""));
 }
+
+#[test]
+fn resolves_relative_paths_across_directories() {
+    let mut files = HashMap::new();
+    files.insert(
+        "OEBPS/Text/ch1.xhtml".to_string(),
+        r#"<html><body id="body1">Ch1</body></html>"#.to_string(),
+    );
+    files.insert(
+        "OEBPS/Misc/notes.xhtml".to_string(),
+        r#"<html><body><a href="../Text/ch1.xhtml#body1">Back</a></body></html>"#.to_string(),
+    );
+    files.insert(
+        "OEBPS/toc.ncx".to_string(),
+        r#"<ncx><navPoint><content src="Text/ch1.xhtml#body1"/></navPoint></ncx>"#.to_string(),
+    );
+    files.insert(
+        "OEBPS/nav.xhtml".to_string(),
+        r#"<html><body><nav><a href="Text/ch1.xhtml#body1">One</a></nav></body></html>"#
+            .to_string(),
+    );
+
+    let fixes = fix_body_id_links(&mut files);
+    assert_eq!(fixes.len(), 3);
+    assert!(files["OEBPS/Misc/notes.xhtml"].contains(r#"href="../Text/ch1.xhtml""#));
+    assert!(files["OEBPS/toc.ncx"].contains(r#"src="Text/ch1.xhtml""#));
+    assert!(files["OEBPS/nav.xhtml"].contains(r#"href="Text/ch1.xhtml""#));
+}
+
+#[test]
+fn leaves_same_basename_in_other_directory_alone() {
+    let mut files = HashMap::new();
+    files.insert(
+        "OEBPS/a/ch.xhtml".to_string(),
+        r#"<html><body id="top">A</body></html>"#.to_string(),
+    );
+    files.insert(
+        "OEBPS/b/ch.xhtml".to_string(),
+        r#"<html><body><div id="top">B</div></body></html>"#.to_string(),
+    );
+    files.insert(
+        "OEBPS/b/index.xhtml".to_string(),
+        r#"<html><body><a href="ch.xhtml#top">B top</a></body></html>"#.to_string(),
+    );
+
+    let fixes = fix_body_id_links(&mut files);
+    assert!(fixes.is_empty());
+    assert!(files["OEBPS/b/index.xhtml"].contains("ch.xhtml#top"));
+}
+
+#[test]
+fn only_rewrites_attributes() {
+    let content = r#"<html><body id="b1"><p>See ch1.xhtml#b1 for details.</p><!-- <a href="ch1.xhtml#b1"> --><a title="href=ch1.xhtml#b1" href='ch1.xhtml#b1'>x</a></body></html>"#;
+    let mut files = HashMap::new();
+    files.insert("OEBPS/ch1.xhtml".to_string(), content.to_string());
+
+    let fixes = fix_body_id_links(&mut files);
+    assert_eq!(fixes.len(), 1);
+    assert_eq!(
+        files["OEBPS/ch1.xhtml"],
+        content.replace("href='ch1.xhtml#b1'", "href='ch1.xhtml'")
+    );
+}
+
+#[test]
+fn rewrites_same_document_and_encoded_links() {
+    let mut files = HashMap::new();
+    files.insert(
+        "OEBPS/My Chapter.xhtml".to_string(),
+        r##"<html><body id="start"><a href="#start">Top</a></body></html>"##.to_string(),
+    );
+    files.insert(
+        "OEBPS/toc.xhtml".to_string(),
+        r#"<html><body><a href="My%20Chapter.xhtml#start">Ch</a><a href="http://example.com/My%20Chapter.xhtml#start">Web</a></body></html>"#
+            .to_string(),
+    );
+
+    let fixes = fix_body_id_links(&mut files);
+    assert_eq!(fixes.len(), 2);
+    assert!(files["OEBPS/My Chapter.xhtml"].contains(r#"href="My%20Chapter.xhtml""#));
+    assert!(files["OEBPS/toc.xhtml"].contains(r#"href="My%20Chapter.xhtml""#));
+    assert!(files["OEBPS/toc.xhtml"].contains("example.com/My%20Chapter.xhtml#start"));
+}
@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+
+use kindle_fix_core::formats::epub::fixes::broken_links::fix_broken_links;
+use kindle_fix_core::DiagnosticCode;
+
+fn book(files: &[(&str, &str)]) -> HashMap<String, String> {
+    files
+        .iter()
+        .map(|(name, content)| (name.to_string(), content.to_string()))
+        .collect()
+}
+
+fn names(files: &HashMap<String, String>, binary: &[&str]) -> HashSet<String> {
+    files
+        .keys()
+        .cloned()
+        .chain(binary.iter().map(|name| name.to_string()))
+        .collect()
+}
+
+#[test]
+fn leaves_valid_and_external_links_alone() {
+    let mut files = book(&[
+        (
+            "OEBPS/a.xhtml",
+            r##"<html><body><p id="p1"><a href="b.xhtml#s1">B</a><a href="#p1">Here</a><a href="https://example.com/x#y">Web</a><a href="../cover.jpg">Cover</a></p></body></html>"##,
+        ),
+        (
+            "OEBPS/b.xhtml",
+            r#"<html><body><h1 id="s1">B</h1></body></html>"#,
+        ),
+    ]);
+    let all = names(&files, &["cover.jpg"]);
+    let before = files.clone();
+
+    let (changes, warnings) = fix_broken_links(&mut files, &all);
+    assert!(changes.is_empty());
+    assert!(warnings.is_empty());
+    assert_eq!(files, before);
+}
+
+#[test]
+fn repairs_file_name_case() {
+    let mut files = book(&[
+        (
+            "OEBPS/a.xhtml",
+            r#"<html><body><a href="Chapter2.XHTML#s1">Next</a></body></html>"#,
+        ),
+        (
+            "OEBPS/chapter2.xhtml",
+            r#"<html><body><h1 id="s1">Two</h1></body></html>"#,
+        ),
+    ]);
+    let all = names(&files, &[]);
+
+    let (changes, warnings) = fix_broken_links(&mut files, &all);
+    assert!(warnings.is_empty());
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].original, "Chapter2.XHTML#s1");
+    assert_eq!(changes[0].replacement, "chapter2.xhtml#s1");
+    assert_eq!((changes[0].line, changes[0].column), (Some(1), Some(22)));
+    assert!(files["OEBPS/a.xhtml"].contains(r#"<a href="chapter2.xhtml#s1">Next</a>"#));
+}
+
+#[test]
+fn repairs_wrong_directory() {
+    let mut files = book(&[
+        (
+            "OEBPS/Text/a.xhtml",
+            r#"<html><body><a href="notes.xhtml">Notes</a></body></html>"#,
+        ),
+        (
+            "OEBPS/Notes/notes.xhtml",
+            r#"<html><body>Notes</body></html>"#,
+        ),
+    ]);
+    let all = names(&files, &[]);
+
+    let (changes, _) = fix_broken_links(&mut files, &all);
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].replacement, "../Notes/notes.xhtml");
+}
+
+#[test]
+fn retargets_fragment_defined_in_another_document() {
+    let mut files = book(&[
+        (
+            "OEBPS/a.xhtml",
+            r##"<html><body><a href="b.xhtml#Note-1">1</a><a href="#ch3">Three</a></body></html>"##,
+        ),
+        ("OEBPS/b.xhtml", r#"<html><body>B</body></html>"#),
+        (
+            "OEBPS/c.xhtml",
+            r#"<html><body><p id="note-1">Note</p></body></html>"#,
+        ),
+        (
+            "OEBPS/d.xhtml",
+            r#"<html><body id="ch3">Three</body></html>"#,
+        ),
+    ]);
+    let all = names(&files, &[]);
+
+    let (changes, warnings) = fix_broken_links(&mut files, &all);
+    assert!(warnings.is_empty());
+    let replacements: Vec<&str> = changes.iter().map(|c| c.replacement.as_str()).collect();
+    // A body ID is a link to the document itself
+    assert_eq!(replacements, ["c.xhtml#note-1", "d.xhtml"]);
+}
+
+#[test]
+fn unwraps_links_without_a_unique_match() {
+    let mut files = book(&[
+        (
+            "OEBPS/a.xhtml",
+            r#"<html><body><p>See <a class="x" href="gone.xhtml">this <i>page</i></a> and <a href="dup.xhtml">that</a>.</p></body></html>"#,
+        ),
+        ("OEBPS/one/dup.xhtml", "<html><body/></html>"),
+        ("OEBPS/two/dup.xhtml", "<html><body/></html>"),
+    ]);
+    let all = names(&files, &[]);
+
+    let (changes, warnings) = fix_broken_links(&mut files, &all);
+    assert_eq!(changes.len(), 4);
+    assert!(changes.iter().all(|change| change.replacement.is_empty()));
+    assert_eq!(
+        files["OEBPS/a.xhtml"],
+        "<html><body><p>See this <i>page</i> and that.</p></body></html>"
+    );
+
+    assert_eq!(warnings.len(), 2);
+    assert_eq!(warnings[0].code, DiagnosticCode::BrokenLink);
+    assert_eq!(warnings[0].file.as_deref(), Some("OEBPS/a.xhtml"));
+    assert!(warnings[0].message.contains("gone.xhtml"));
+    assert!(warnings[0].message.ends_with("removed it"));
+}
+
+#[test]
+fn reports_links_it_cannot_remove_as_left_alone() {
+    let original = r#"<html><body><a id="fn1" src="missing.xhtml">1</a></body></html>"#;
+    let mut files = book(&[("OEBPS/a.xhtml", original)]);
+    let all = names(&files, &[]);
+
+    let (changes, warnings) = fix_broken_links(&mut files, &all);
+    assert!(changes.is_empty());
+    assert_eq!(files["OEBPS/a.xhtml"], original);
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].message.ends_with("left it as it was"));
+}
+
+#[test]
+fn keeps_anchors_that_are_link_targets() {
+    let mut files = book(&[(
+        "OEBPS/a.xhtml",
+        r#"<html><body><a id="fn1" href="missing.xhtml#x" class="note">1</a></body></html>"#,
+    )]);
+    let all = names(&files, &[]);
+
+    let (_, warnings) = fix_broken_links(&mut files, &all);
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(
+        files["OEBPS/a.xhtml"],
+        r#"<html><body><a id="fn1" class="note">1</a></body></html>"#
+    );
+}
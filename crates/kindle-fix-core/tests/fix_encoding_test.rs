@@ -77,3 +77,58 @@ fn handles_html_extension() {
     let fixes = fix_encoding(&mut files);
     assert_eq!(fixes.len(), 1);
 }
+
+#[test]
+fn rewrites_non_utf8_xml_declaration() {
+    let mut files = HashMap::new();
+    files.insert(
+        "chapter1.xhtml".to_string(),
+        "<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\n<html/>".to_string(),
+    );
+    files.insert(
+        "content.opf".to_string(),
+        "<?xml version='1.0' encoding='windows-1252'?><package/>".to_string(),
+    );
+    files.insert("toc.ncx".to_string(), "<?xml version=\"1.0\"?><ncx/>".to_string());
+
+    let fixes = fix_encoding(&mut files);
+    let fixed: Vec<&str> = fixes.iter().map(|change| change.file.as_str()).collect();
+    assert_eq!(fixed, ["chapter1.xhtml", "content.opf"]);
+    assert_eq!(fixes[0].original, "<?xml version=\"1.0\" encoding=\"ISO-8859-1\"");
+    assert_eq!(fixes[0].replacement, "<?xml version=\"1.0\" encoding=\"utf-8\"");
+    assert_eq!(files["chapter1.xhtml"], "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<html/>");
+    assert_eq!(files["content.opf"], "<?xml version='1.0' encoding='utf-8'?><package/>");
+    assert_eq!(files["toc.ncx"], "<?xml version=\"1.0\"?><ncx/>");
+}
+
+#[test]
+fn adds_encoding_to_declaration_without_one() {
+    let mut files = HashMap::new();
+    files.insert("chapter1.xhtml".to_string(), "<?xml version=\"1.0\"?>\n<html/>".to_string());
+    let fixes = fix_encoding(&mut files);
+    assert_eq!(fixes.len(), 1);
+    assert_eq!(files["chapter1.xhtml"], "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<html/>");
+}
+
+#[test]
+fn rewrites_meta_and_css_charsets() {
+    let mut files = HashMap::new();
+    files.insert(
+        "chapter1.xhtml".to_string(),
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<html><head>",
+            "<meta http-equiv=\"Content-Type\" content=\"text/html; charset=iso-8859-1\"/>",
+            "<meta charset=\"UTF-8\"/></head></html>"
+        )
+        .to_string(),
+    );
+    files.insert("style.css".to_string(), "@charset \"latin1\";\np {}".to_string());
+
+    let fixes = fix_encoding(&mut files);
+    assert_eq!(fixes.len(), 2);
+    assert_eq!((fixes[0].line, fixes[0].column), (Some(2), Some(13)));
+    assert!(fixes[0].replacement.ends_with("charset=utf-8"));
+    assert!(files["chapter1.xhtml"].contains("content=\"text/html; charset=utf-8\""));
+    assert!(files["chapter1.xhtml"].contains("<meta charset=\"UTF-8\"/>"));
+    assert_eq!(files["style.css"], "@charset \"utf-8\";\np {}");
+}
@@ -0,0 +1,65 @@
+mod helpers;
+
+use kindle_fix_core::formats::epub::fixes::registry;
+use kindle_fix_core::{process_file, FixOptions};
+
+/// A book with a problem for every fix in the registry.
+fn broken_epub() -> Vec<u8> {
+    helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/content.opf", &helpers::opf_without_language()),
+        (
+            "OEBPS/chapter1.xhtml",
+            r#"<html><body id="body1"><p>Text</p><img/><a href="chapter1.xhtml#body1">Top</a><a href="Chapter1.xhtml#Note">Note</a><a href="gone.xhtml">Gone</a><p id="note">See</p></body></html>"#,
+        ),
+        (
+            "OEBPS/toc.ncx",
+            r#"<?xml version="1.0" encoding="UTF-8"?><ncx><navPoint><content src="chapter1.xhtml#body1"/></navPoint></ncx>"#,
+        ),
+    ])
+}
+
+fn only(id: &str) -> FixOptions {
+    FixOptions {
+        only: Some(vec![id.to_string()]),
+        force: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn every_fix_is_idempotent_on_its_own_output() {
+    let epub = broken_epub();
+
+    for fix in registry() {
+        let first = process_file(&epub, "book.epub", &only(fix.id())).unwrap();
+        assert!(
+            first.report.has_fixes(),
+            "{} found nothing to fix in the test book",
+            fix.id()
+        );
+
+        let second = process_file(&first.data, "book.epub", &only(fix.id())).unwrap();
+        assert!(
+            second.unchanged,
+            "{} changed its own output again: {:?}",
+            fix.id(),
+            second.report.fixes_applied
+        );
+        assert_eq!(second.data, first.data);
+    }
+}
+
+#[test]
+fn all_fixes_together_are_idempotent() {
+    let options = FixOptions {
+        force: true,
+        ..Default::default()
+    };
+
+    let first = process_file(&broken_epub(), "book.epub", &options).unwrap();
+    assert!(!first.unchanged);
+
+    let second = process_file(&first.data, "book.epub", &options).unwrap();
+    assert!(second.unchanged, "{:?}", second.report.fixes_applied);
+}
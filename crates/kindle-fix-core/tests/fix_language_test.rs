@@ -2,8 +2,9 @@ mod helpers;
 
 use std::collections::HashMap;
 use kindle_fix_core::formats::epub::fixes::language::{
-    fix_language, LanguageFixResult, SUPPORTED_LANGUAGES,
+    fix_language, fix_languages, LanguageFixResult, SUPPORTED_LANGUAGES,
 };
+use kindle_fix_core::formats::epub::package::Package;
 
 #[test]
 fn detects_missing_language() {
@@ -144,3 +145,132 @@ fn supported_languages_includes_common_codes() {
     assert!(SUPPORTED_LANGUAGES.contains(&"eng"));
     assert!(SUPPORTED_LANGUAGES.contains(&"fra"));
 }
+
+fn book(opf: &str) -> HashMap<String, String> {
+    let mut files = HashMap::new();
+    files.insert(
+        "META-INF/container.xml".to_string(),
+        helpers::CONTAINER_XML.to_string(),
+    );
+    files.insert("OEBPS/content.opf".to_string(), opf.to_string());
+    files
+}
+
+fn languages(opf: &str) -> Vec<String> {
+    Package::parse(opf)
+        .unwrap()
+        .metadata
+        .dc("language")
+        .map(|e| e.text())
+        .collect()
+}
+
+#[test]
+fn promotes_first_supported_secondary_language() {
+    let mut files = book(&helpers::opf_with_language(
+        "xx</dc:language>\n    <dc:language>fr",
+    ));
+
+    match fix_language(&mut files, None) {
+        LanguageFixResult::Changed { from, to } => {
+            assert_eq!((from, to), ("xx".into(), "fr".into()))
+        }
+        other => panic!("Expected Changed, got {:?}", other),
+    }
+    assert_eq!(languages(&files["OEBPS/content.opf"]), ["fr", "xx"]);
+}
+
+#[test]
+fn override_drops_empty_and_repeated_entries() {
+    let mut files = book(&helpers::opf_with_language(
+        "</dc:language>\n    <dc:language>xx</dc:language>\n    <dc:language>EN",
+    ));
+
+    let packages = fix_languages(&mut files, Some("en".to_string()));
+    assert!(matches!(
+        packages[0].result,
+        LanguageFixResult::Changed { ref from, ref to } if from == "xx" && to == "en"
+    ));
+    assert_eq!(languages(&files["OEBPS/content.opf"]), ["en"]);
+    assert_eq!(packages[0].changes.len(), 2);
+    assert!(packages[0].changes.iter().all(|c| c.replacement.is_empty()));
+}
+
+#[test]
+fn ignores_language_elements_outside_dublin_core() {
+    let opf = helpers::opf_without_language().replace(
+        "<dc:title>",
+        "<x:language xmlns:x=\"urn:example\">xx</x:language>\n    <dc:title>",
+    );
+    let mut files = book(&opf);
+
+    match fix_language(&mut files, None) {
+        LanguageFixResult::Added(lang) => assert_eq!(lang, "en"),
+        other => panic!("Expected Added, got {:?}", other),
+    }
+    let opf = &files["OEBPS/content.opf"];
+    assert!(opf.contains("<x:language xmlns:x=\"urn:example\">xx</x:language>"));
+    assert_eq!(languages(opf), ["en"]);
+}
+
+#[test]
+fn fixes_every_package_document() {
+    let mut files = book(&helpers::opf_with_language("en"));
+    files.insert(
+        "META-INF/container.xml".to_string(),
+        helpers::CONTAINER_XML.replace(
+            "  </rootfiles>",
+            "    <rootfile full-path=\"alt/content.opf\" media-type=\"application/oebps-package+xml\"/>\n  </rootfiles>",
+        ),
+    );
+    files.insert(
+        "alt/content.opf".to_string(),
+        helpers::opf_without_language(),
+    );
+
+    let packages = fix_languages(&mut files, Some("de".to_string()));
+    assert_eq!(packages.len(), 2);
+    assert_eq!(packages[0].opf_path.as_deref(), Some("OEBPS/content.opf"));
+    assert!(matches!(packages[0].result, LanguageFixResult::Valid(_)));
+    assert!(matches!(&packages[1].result, LanguageFixResult::Added(lang) if lang == "de"));
+    assert_eq!(languages(&files["alt/content.opf"]), ["de"]);
+}
+
+#[test]
+fn keeps_lang_attributes_consistent() {
+    let opf = helpers::opf_with_items(
+        "xx",
+        &[
+            ("Text/a.xhtml", "application/xhtml+xml"),
+            ("Text/b.xhtml", "application/xhtml+xml"),
+            ("Text/c.xhtml", "application/xhtml+xml"),
+        ],
+    )
+    .replace("version=\"3.0\"", "version=\"3.0\" xml:lang=\"xx\"");
+    let mut files = book(&opf);
+    let a = "<html xmlns=\"http://www.w3.org/1999/xhtml\" xml:lang=\"xx\" lang=''><body/></html>";
+    let b = "<html xmlns=\"http://www.w3.org/1999/xhtml\" xml:lang=\"en\" lang=\"en-US\"><body/></html>";
+    let c = "<html xmlns=\"http://www.w3.org/1999/xhtml\" lang=\"la\"><body/></html>";
+    files.insert("OEBPS/Text/a.xhtml".to_string(), a.to_string());
+    files.insert("OEBPS/Text/b.xhtml".to_string(), b.to_string());
+    files.insert("OEBPS/Text/c.xhtml".to_string(), c.to_string());
+
+    let packages = fix_languages(&mut files, Some("en".to_string()));
+    let package = Package::parse(&files["OEBPS/content.opf"]).unwrap();
+    assert_eq!(package.attribute("xml:lang"), Some("en"));
+    assert_eq!(
+        files["OEBPS/Text/a.xhtml"],
+        a.replace("xx", "en").replace("''", "'en'")
+    );
+    assert_eq!(files["OEBPS/Text/b.xhtml"], b.replace("en-US", "en"));
+    // A document in another language is left alone
+    assert_eq!(files["OEBPS/Text/c.xhtml"], c);
+
+    let changes = &packages[0].changes;
+    assert_eq!(changes.len(), 4);
+    let a_change = changes
+        .iter()
+        .find(|c| c.file == "OEBPS/Text/a.xhtml")
+        .unwrap();
+    assert_eq!((a_change.line, a_change.column), (Some(1), Some(54)));
+}
@@ -0,0 +1,188 @@
+mod helpers;
+
+use std::collections::{HashMap, HashSet};
+
+use kindle_fix_core::formats::epub::fixes::manifest::fix_manifest;
+use kindle_fix_core::DiagnosticCode;
+
+const OPF: &str = "OEBPS/content.opf";
+
+fn opf(manifest: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="uid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="uid">urn:uuid:1</dc:identifier>
+    <meta name="cover" content="cover"/>
+  </metadata>
+{manifest}
+  <spine toc="ncx">
+    <itemref idref="chapter1"/>
+  </spine>
+</package>"#
+    )
+}
+
+fn book(opf: &str, others: &[&str]) -> (HashMap<String, String>, HashSet<String>) {
+    let mut files = HashMap::new();
+    files.insert(
+        "META-INF/container.xml".to_string(),
+        helpers::CONTAINER_XML.to_string(),
+    );
+    files.insert("mimetype".to_string(), "application/epub+zip".to_string());
+    files.insert(OPF.to_string(), opf.to_string());
+    let mut names: HashSet<String> = files.keys().cloned().collect();
+    names.extend(others.iter().map(|name| name.to_string()));
+    (files, names)
+}
+
+#[test]
+fn leaves_consistent_manifest_alone() {
+    let opf = opf(r#"  <manifest>
+    <item id="chapter1" href="Text/chapter%201.xhtml" media-type="application/xhtml+xml"/>
+    <item id="font" href="../fonts/a.ttf" media-type="application/x-font-ttf"/>
+  </manifest>"#);
+    let (mut files, names) = book(&opf, &["OEBPS/Text/chapter 1.xhtml", "fonts/a.ttf"]);
+
+    let result = fix_manifest(&mut files, &names);
+    assert!(result.is_empty());
+    assert!(result.warnings.is_empty());
+    assert_eq!(files[OPF], opf);
+}
+
+#[test]
+fn adds_items_for_unlisted_files() {
+    let opf = opf(r#"  <manifest>
+    <item id="chapter1" href="chapter1.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>"#);
+    let (mut files, names) = book(
+        &opf,
+        &[
+            "OEBPS/chapter1.xhtml",
+            "OEBPS/Images/cover.JPG",
+            "OEBPS/styles/1 main.css",
+            "OEBPS/chapter1.xhtml.bak",
+        ],
+    );
+
+    let result = fix_manifest(&mut files, &names);
+    assert_eq!(result.added.len(), 2);
+    assert!(files[OPF].contains(
+        r#"    <item id="chapter1" href="chapter1.xhtml" media-type="application/xhtml+xml"/>
+    <item id="cover.JPG" href="Images/cover.JPG" media-type="image/jpeg"/>
+    <item id="x1_main.css" href="styles/1%20main.css" media-type="text/css"/>
+  </manifest>"#
+    ));
+
+    assert_eq!(result.warnings.len(), 1);
+    assert_eq!(result.warnings[0].code, DiagnosticCode::ManifestMismatch);
+    assert!(result.warnings[0].message.contains("chapter1.xhtml.bak"));
+}
+
+#[test]
+fn ignores_packaging_leftovers() {
+    let opf = opf(r#"  <manifest>
+    <item id="chapter1" href="chapter1.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>"#);
+    let (mut files, names) = book(
+        &opf,
+        &[
+            "OEBPS/chapter1.xhtml",
+            ".DS_Store",
+            "OEBPS/.DS_Store",
+            "__MACOSX/OEBPS/._chapter1.xhtml",
+            "OEBPS/Images/Thumbs.db",
+            "iTunesMetadata.plist",
+        ],
+    );
+
+    let result = fix_manifest(&mut files, &names);
+    assert!(result.is_empty());
+    assert!(result.warnings.is_empty());
+    assert_eq!(files[OPF], opf);
+}
+
+#[test]
+fn fills_empty_manifest_element() {
+    let (mut files, names) = book(&opf("  <manifest/>"), &["OEBPS/chapter1.xhtml"]);
+
+    fix_manifest(&mut files, &names);
+    assert!(files[OPF].contains(
+        r#"  <manifest>
+    <item id="chapter1.xhtml" href="chapter1.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>"#
+    ));
+}
+
+#[test]
+fn removes_dangling_items_nothing_refers_to() {
+    let opf = opf(r#"  <manifest>
+    <item id="chapter1" href="chapter1.xhtml" media-type="application/xhtml+xml"/>
+    <item id="gone" href="gone.xhtml" media-type="application/xhtml+xml"/>
+    <item id="cover" href="cover.jpg" media-type="image/jpeg"/>
+  </manifest>"#);
+    let (mut files, names) = book(&opf, &[]);
+
+    let result = fix_manifest(&mut files, &names);
+    assert_eq!(result.removed.len(), 1);
+    assert!(!files[OPF].contains("gone.xhtml"));
+    assert!(files[OPF].contains("media-type=\"application/xhtml+xml\"/>\n    <item id=\"cover\""));
+
+    // The spine and the cover meta still point at these
+    assert_eq!(result.warnings.len(), 2);
+    assert!(files[OPF].contains(r#"href="chapter1.xhtml""#));
+    assert!(files[OPF].contains(r#"href="cover.jpg""#));
+}
+
+#[test]
+fn corrects_media_types_and_href_case() {
+    let opf = opf(r#"  <manifest>
+    <item id="chapter1" href="chapter1.html" media-type="text/html"/>
+    <item id="cover" href="images/cover.jpg" media-type="image/png"/>
+    <item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml; charset=utf-8"/>
+  </manifest>"#);
+    let (mut files, names) = book(
+        &opf,
+        &[
+            "OEBPS/chapter1.html",
+            "OEBPS/Images/Cover.jpg",
+            "OEBPS/toc.ncx",
+        ],
+    );
+
+    let result = fix_manifest(&mut files, &names);
+    assert_eq!(result.retyped.len(), 2);
+    assert_eq!(result.retyped[0].original, "text/html");
+    assert_eq!(result.retyped[0].replacement, "application/xhtml+xml");
+    assert_eq!(result.relinked.len(), 1);
+    assert!(result.added.is_empty());
+    assert!(files[OPF]
+        .contains(r#"<item id="cover" href="Images/Cover.jpg" media-type="image/jpeg"/>"#));
+}
+
+#[test]
+fn deduplicates_item_ids() {
+    let opf = opf(r#"  <manifest>
+    <item id="chapter1" href="chapter1.xhtml" media-type="application/xhtml+xml"/>
+    <item id="chapter1" href="chapter1.xhtml" media-type="application/xhtml+xml"/>
+    <item id="chapter1" href="chapter2.xhtml" media-type="application/xhtml+xml"/>
+    <item id="uid" href="chapter3.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>"#);
+    let (mut files, names) = book(
+        &opf,
+        &[
+            "OEBPS/chapter1.xhtml",
+            "OEBPS/chapter2.xhtml",
+            "OEBPS/chapter3.xhtml",
+        ],
+    );
+
+    let result = fix_manifest(&mut files, &names);
+    assert_eq!(result.removed.len(), 1);
+    assert_eq!(result.renamed.len(), 1);
+    assert!(files[OPF].contains(
+        r#"    <item id="chapter1" href="chapter1.xhtml" media-type="application/xhtml+xml"/>
+    <item id="chapter1-2" href="chapter2.xhtml" media-type="application/xhtml+xml"/>
+    <item id="uid" href="chapter3.xhtml""#
+    ));
+}
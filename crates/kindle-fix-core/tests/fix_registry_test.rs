@@ -0,0 +1,69 @@
+mod helpers;
+
+use std::collections::HashSet;
+
+use kindle_fix_core::formats::epub::fixes::{find, registry};
+use kindle_fix_core::{process_file, FixOptions};
+
+fn build_broken_epub() -> Vec<u8> {
+    helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/content.opf", &helpers::opf_with_language("en")),
+        (
+            "OEBPS/chapter1.xhtml",
+            r#"<html><body id="body1"><p>Hi</p><img /></body></html>"#,
+        ),
+        (
+            "OEBPS/chapter2.xhtml",
+            r#"<?xml version="1.0" encoding="utf-8"?><html><body><a href="chapter1.xhtml#body1">Back</a></body></html>"#,
+        ),
+    ])
+}
+
+fn applied(options: &FixOptions) -> Vec<String> {
+    let output = process_file(&build_broken_epub(), "book.epub", options).unwrap();
+    output
+        .report
+        .fixes_applied
+        .into_iter()
+        .map(|fix| fix.name)
+        .collect()
+}
+
+#[test]
+fn registry_ids_are_unique() {
+    let ids: HashSet<_> = registry().iter().map(|fix| fix.id()).collect();
+    assert_eq!(ids.len(), registry().len());
+    for id in ["body_id", "language", "stray_img", "encoding"] {
+        assert!(find(id).is_some(), "missing fix {}", id);
+    }
+    assert!(find("nope").is_none());
+}
+
+#[test]
+fn runs_every_default_fix() {
+    let names = applied(&FixOptions::default());
+    assert!(names.contains(&"body_id".to_string()));
+    assert!(names.contains(&"stray_img".to_string()));
+    assert!(names.contains(&"encoding".to_string()));
+}
+
+#[test]
+fn skip_disables_a_fix() {
+    let options = FixOptions {
+        skip: vec!["encoding".to_string()],
+        ..Default::default()
+    };
+    let names = applied(&options);
+    assert!(!names.contains(&"encoding".to_string()));
+    assert!(names.contains(&"body_id".to_string()));
+}
+
+#[test]
+fn only_runs_selected_fixes() {
+    let options = FixOptions {
+        only: Some(vec!["body_id".to_string()]),
+        ..Default::default()
+    };
+    assert_eq!(applied(&options), vec!["body_id".to_string()]);
+}
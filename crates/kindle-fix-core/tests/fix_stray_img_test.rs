@@ -35,7 +35,9 @@ fn handles_multiple_stray_images() {
         r#"<?xml version="1.0" encoding="utf-8"?><html><body><img/><img src="ok.png"/><img/></body></html>"#.to_string(),
     );
     let fixes = fix_stray_images(&mut files);
-    assert_eq!(fixes.len(), 1);
+    assert_eq!(fixes.len(), 2);
+    assert!(fixes.iter().all(|change| change.original == "<img/>"));
+    assert!(fixes.iter().all(|change| change.replacement.is_empty()));
     assert!(files["chapter.xhtml"].contains("ok.png"));
 }
 
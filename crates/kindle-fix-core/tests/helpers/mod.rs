@@ -1,31 +1,41 @@
+#![allow(dead_code)]
+
 use std::io::{Cursor, Write};
 use zip::write::SimpleFileOptions;
 use zip::ZipWriter;
 
 pub fn build_epub(files: &[(&str, &str)]) -> Vec<u8> {
+    let files: Vec<(&str, &[u8])> = files
+        .iter()
+        .map(|(name, content)| (*name, content.as_bytes()))
+        .collect();
+    build_epub_bytes(&files)
+}
+
+/// Like `build_epub`, for entries that are not valid UTF-8.
+pub fn build_epub_bytes(files: &[(&str, &[u8])]) -> Vec<u8> {
     let buf = Cursor::new(Vec::new());
     let mut zip = ZipWriter::new(buf);
 
     let has_mimetype = files.iter().any(|(name, _)| *name == "mimetype");
     if !has_mimetype {
-        let options = SimpleFileOptions::default()
-            .compression_method(zip::CompressionMethod::Stored);
+        let options =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
         zip.start_file("mimetype", options).unwrap();
         zip.write_all(b"application/epub+zip").unwrap();
     }
 
-    let options = SimpleFileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
 
     for (name, content) in files {
         if *name == "mimetype" {
-            let stored = SimpleFileOptions::default()
-                .compression_method(zip::CompressionMethod::Stored);
+            let stored =
+                SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
             zip.start_file(*name, stored).unwrap();
         } else {
             zip.start_file(*name, options).unwrap();
         }
-        zip.write_all(content.as_bytes()).unwrap();
+        zip.write_all(content).unwrap();
     }
 
     zip.finish().unwrap().into_inner()
@@ -52,6 +62,27 @@ pub fn opf_with_language(lang: &str) -> String {
     )
 }
 
+/// Like `opf_with_language`, with a manifest listing `items` as
+/// `(href, media-type)` pairs.
+pub fn opf_with_items(lang: &str, items: &[(&str, &str)]) -> String {
+    let items: String = items
+        .iter()
+        .enumerate()
+        .map(|(i, (href, media_type))| {
+            format!(
+                "    <item id=\"item{}\" href=\"{}\" media-type=\"{}\"/>\n",
+                i + 1,
+                href,
+                media_type
+            )
+        })
+        .collect();
+    opf_with_language(lang).replace(
+        "  <manifest/>\n",
+        &format!("  <manifest>\n{items}  </manifest>\n"),
+    )
+}
+
 pub fn opf_without_language() -> String {
     r#"<?xml version="1.0" encoding="UTF-8"?>
 <package xmlns="http://www.idpf.org/2007/opf" version="3.0">
@@ -63,3 +94,305 @@ pub fn opf_without_language() -> String {
 </package>"#
         .to_string()
 }
+
+/// Build a PalmDB file with the given records, named `name`.
+pub fn build_palmdb(name: &str, records: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = vec![0u8; 78];
+    let name_bytes = name.as_bytes();
+    let len = name_bytes.len().min(31);
+    out[..len].copy_from_slice(&name_bytes[..len]);
+    out[60..68].copy_from_slice(b"BOOKMOBI");
+    out[76..78].copy_from_slice(&(records.len() as u16).to_be_bytes());
+
+    let mut offset = 78 + records.len() * 8 + 2;
+    for (i, record) in records.iter().enumerate() {
+        out.extend_from_slice(&(offset as u32).to_be_bytes());
+        out.extend_from_slice(&((2 * i) as u32).to_be_bytes());
+        offset += record.len();
+    }
+    out.extend_from_slice(&[0, 0]);
+
+    for record in records {
+        out.extend_from_slice(record);
+    }
+    out
+}
+
+/// Build an EXTH block from `(type, payload)` pairs.
+pub fn build_exth(records: &[(u32, &[u8])]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (kind, data) in records {
+        body.extend_from_slice(&kind.to_be_bytes());
+        body.extend_from_slice(&((data.len() + 8) as u32).to_be_bytes());
+        body.extend_from_slice(data);
+    }
+    let padding = (4 - body.len() % 4) % 4;
+
+    let mut out = b"EXTH".to_vec();
+    out.extend_from_slice(&((body.len() + 12) as u32).to_be_bytes());
+    out.extend_from_slice(&(records.len() as u32).to_be_bytes());
+    out.extend_from_slice(&body);
+    out.extend(std::iter::repeat_n(0, padding));
+    out
+}
+
+/// Build record 0 of a MOBI book: PalmDOC header, MOBI header, EXTH and full name.
+pub fn build_mobi_record0(
+    title: &str,
+    file_version: u32,
+    exth: &[(u32, &[u8])],
+    text_records: &[&[u8]],
+) -> Vec<u8> {
+    const HEADER_LEN: usize = 0xE8;
+    let text_length: usize = text_records.iter().map(|r| r.len()).sum();
+
+    let mut rec = vec![0u8; 16 + HEADER_LEN];
+    let put = |rec: &mut Vec<u8>, offset: usize, value: u32| {
+        rec[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    };
+
+    rec[0..2].copy_from_slice(&1u16.to_be_bytes());
+    put(&mut rec, 4, text_length as u32);
+    rec[8..10].copy_from_slice(&(text_records.len() as u16).to_be_bytes());
+    rec[10..12].copy_from_slice(&4096u16.to_be_bytes());
+
+    rec[16..20].copy_from_slice(b"MOBI");
+    put(&mut rec, 0x14, HEADER_LEN as u32);
+    put(&mut rec, 0x18, 2);
+    put(&mut rec, 0x1C, 65001);
+    put(&mut rec, 0x20, 0x1234);
+    put(&mut rec, 0x24, file_version);
+    for offset in (0x28..0x50).step_by(4) {
+        put(&mut rec, offset, 0xFFFF_FFFF);
+    }
+    put(&mut rec, 0x50, text_records.len() as u32 + 1);
+    put(&mut rec, 0x5C, 9);
+    put(&mut rec, 0x68, file_version);
+    put(&mut rec, 0x6C, 0xFFFF_FFFF);
+    put(&mut rec, 0xF4, 0xFFFF_FFFF);
+
+    if !exth.is_empty() {
+        put(&mut rec, 0x80, 0x40);
+        rec.extend_from_slice(&build_exth(exth));
+    }
+
+    let name_offset = rec.len() as u32;
+    put(&mut rec, 0x54, name_offset);
+    put(&mut rec, 0x58, title.len() as u32);
+    rec.extend_from_slice(title.as_bytes());
+    rec.extend_from_slice(&[0, 0]);
+    while !rec.len().is_multiple_of(4) {
+        rec.push(0);
+    }
+    rec
+}
+
+/// Build an uncompressed MOBI 6 book with the given EXTH records and text records.
+pub fn build_mobi(title: &str, exth: &[(u32, &[u8])], text_records: &[&[u8]]) -> Vec<u8> {
+    let mut records = vec![build_mobi_record0(title, 6, exth, text_records)];
+    records.extend(text_records.iter().map(|r| r.to_vec()));
+    records.push(vec![0xE9, 0x8E, 0x0D, 0x0A]);
+    build_palmdb(title, &records)
+}
+
+/// Build an uncompressed standalone KF8 (AZW3) book.
+pub fn build_azw3(title: &str, exth: &[(u32, &[u8])], text_records: &[&[u8]]) -> Vec<u8> {
+    let mut records = vec![build_mobi_record0(title, 8, exth, text_records)];
+    records.extend(text_records.iter().map(|r| r.to_vec()));
+    records.push(vec![0xE9, 0x8E, 0x0D, 0x0A]);
+    build_palmdb(title, &records)
+}
+
+/// Build a combo book: a MOBI 6 section, a BOUNDARY record and a KF8 section.
+/// The MOBI 6 header points at the KF8 header through EXTH record 121.
+pub fn build_combo_mobi(title: &str, text_records: &[&[u8]]) -> Vec<u8> {
+    let kf8_record = (text_records.len() + 2) as u32;
+    let boundary = kf8_record.to_be_bytes();
+
+    let mut records = vec![build_mobi_record0(
+        title,
+        6,
+        &[(121, &boundary)],
+        text_records,
+    )];
+    records.extend(text_records.iter().map(|r| r.to_vec()));
+    records.push(b"BOUNDARY".to_vec());
+    records.push(build_mobi_record0(title, 8, &[], text_records));
+    records.extend(text_records.iter().map(|r| r.to_vec()));
+    records.push(vec![0xE9, 0x8E, 0x0D, 0x0A]);
+    build_palmdb(title, &records)
+}
+
+/// Overwrite a big-endian u32 field in a record.
+pub fn set_u32(record: &mut [u8], offset: usize, value: u32) {
+    record[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Encode text as a valid PalmDOC stream using only literals.
+pub fn palmdoc_literal(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for chunk in data.chunks(8) {
+        if chunk.iter().all(|&b| (0x09..=0x7F).contains(&b)) {
+            out.extend_from_slice(chunk);
+        } else {
+            out.push(chunk.len() as u8);
+            out.extend_from_slice(chunk);
+        }
+    }
+    out
+}
+
+/// Build a MOBI 6 book whose text is followed by image records. The MOBI
+/// header's first image index points at the first image.
+pub fn build_mobi_with_images(
+    title: &str,
+    exth: &[(u32, &[u8])],
+    text_records: &[&[u8]],
+    images: &[&[u8]],
+) -> Vec<u8> {
+    let mut record0 = build_mobi_record0(title, 6, exth, text_records);
+    set_u32(&mut record0, 0x6C, text_records.len() as u32 + 1);
+
+    let mut records = vec![record0];
+    records.extend(text_records.iter().map(|r| r.to_vec()));
+    records.extend(images.iter().map(|r| r.to_vec()));
+    records.push(vec![0xE9, 0x8E, 0x0D, 0x0A]);
+    build_palmdb(title, &records)
+}
+
+/// Encode a forward variable-width integer as used in INDX entries.
+pub fn index_varint(value: u32) -> Vec<u8> {
+    let mut bytes = vec![(value & 0x7F) as u8 | 0x80];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        bytes.insert(0, (rest & 0x7F) as u8);
+        rest >>= 7;
+    }
+    bytes
+}
+
+/// Build an INDX header record followed by one entry record. `tagx` holds
+/// `[tag, values per entry, bitmask, end flag]` rows; each entry is an
+/// identifier plus its already-encoded control byte and tag values.
+pub fn build_indx(tagx: &[[u8; 4]], entries: &[(&str, Vec<u8>)]) -> Vec<Vec<u8>> {
+    const HEADER_LEN: u32 = 0xC0;
+    let indx_header = |start: u32, count: u32| {
+        let mut rec = b"INDX".to_vec();
+        rec.resize(HEADER_LEN as usize, 0);
+        set_u32(&mut rec, 4, HEADER_LEN);
+        set_u32(&mut rec, 20, start);
+        set_u32(&mut rec, 24, count);
+        rec
+    };
+
+    let mut header = indx_header(0, 1);
+    header.extend_from_slice(b"TAGX");
+    header.extend_from_slice(&(12 + tagx.len() as u32 * 4).to_be_bytes());
+    header.extend_from_slice(&1u32.to_be_bytes());
+    for row in tagx {
+        header.extend_from_slice(row);
+    }
+
+    let mut body = Vec::new();
+    let mut offsets = Vec::new();
+    for (ident, data) in entries {
+        offsets.push(HEADER_LEN as usize + body.len());
+        body.push(ident.len() as u8);
+        body.extend_from_slice(ident.as_bytes());
+        body.extend_from_slice(data);
+    }
+    let idxt = HEADER_LEN as usize + body.len();
+    let mut record = indx_header(idxt as u32, entries.len() as u32);
+    record.extend_from_slice(&body);
+    record.extend_from_slice(b"IDXT");
+    for offset in offsets {
+        record.extend_from_slice(&(offset as u16).to_be_bytes());
+    }
+
+    vec![header, record]
+}
+
+/// Build a standalone KF8 book from a list of files, each split into a
+/// skeleton (with an insertion marker `{}`) and the fragment inserted there.
+/// `css` becomes a second flow; `images` follow as resource records.
+pub fn build_kf8_book(title: &str, files: &[(&str, &str)], css: &str, images: &[&[u8]]) -> Vec<u8> {
+    let mut text = Vec::new();
+    let mut skeletons = Vec::new();
+    let mut fragments = Vec::new();
+    for (i, (skeleton, fragment)) in files.iter().enumerate() {
+        let start = text.len() as u32;
+        let insert_at = skeleton.find("{}").unwrap();
+        let skeleton = skeleton.replace("{}", "");
+        text.extend_from_slice(skeleton.as_bytes());
+        text.extend_from_slice(fragment.as_bytes());
+
+        let mut skel = vec![0x03];
+        skel.extend(index_varint(1));
+        skel.extend(index_varint(start));
+        skel.extend(index_varint(skeleton.len() as u32));
+        skeletons.push((format!("SKEL{:07}", i), skel));
+
+        let mut frag = vec![0x0C];
+        frag.extend(index_varint(i as u32));
+        frag.extend(index_varint(start + skeleton.len() as u32));
+        frag.extend(index_varint(fragment.len() as u32));
+        fragments.push((format!("{:010}", start as usize + insert_at), frag));
+    }
+    let flow0_end = text.len() as u32;
+    text.extend_from_slice(css.as_bytes());
+
+    let text_records: Vec<&[u8]> = vec![&text];
+    let mut record0 = build_mobi_record0(title, 8, &[], &text_records);
+    record0.truncate(16 + 0xE8);
+    record0.resize(16 + 0x108, 0);
+    set_u32(&mut record0, 0x14, 0x108);
+    set_u32(&mut record0, 0x54, 0);
+    set_u32(&mut record0, 0x58, 0);
+    for offset in (0xC0..0x118).step_by(4) {
+        set_u32(&mut record0, offset, 0xFFFF_FFFF);
+    }
+    set_u32(&mut record0, 0xF0, 0);
+
+    let skel_tagx = [[1, 1, 0x01, 0], [6, 2, 0x02, 0], [0, 0, 0, 1]];
+    let frag_tagx = [[3, 1, 0x04, 0], [6, 2, 0x08, 0], [0, 0, 0, 1]];
+    let skel_entries: Vec<(&str, Vec<u8>)> = skeletons
+        .iter()
+        .map(|(n, d)| (n.as_str(), d.clone()))
+        .collect();
+    let frag_entries: Vec<(&str, Vec<u8>)> = fragments
+        .iter()
+        .map(|(n, d)| (n.as_str(), d.clone()))
+        .collect();
+
+    let mut records = vec![Vec::new(), text.clone()];
+    let first_image = records.len() as u32;
+    records.extend(images.iter().map(|r| r.to_vec()));
+    let skeleton_index = records.len() as u32;
+    records.extend(build_indx(&skel_tagx, &skel_entries));
+    let fragment_index = records.len() as u32;
+    records.extend(build_indx(&frag_tagx, &frag_entries));
+    let fdst_index = records.len() as u32;
+    let mut fdst = b"FDST".to_vec();
+    fdst.extend_from_slice(&12u32.to_be_bytes());
+    fdst.extend_from_slice(&2u32.to_be_bytes());
+    for (start, end) in [(0, flow0_end), (flow0_end, text.len() as u32)] {
+        fdst.extend_from_slice(&start.to_be_bytes());
+        fdst.extend_from_slice(&end.to_be_bytes());
+    }
+    records.push(fdst);
+    records.push(vec![0xE9, 0x8E, 0x0D, 0x0A]);
+
+    set_u32(&mut record0, 0x6C, first_image);
+    set_u32(&mut record0, 0xFC, skeleton_index);
+    set_u32(&mut record0, 0xF8, fragment_index);
+    set_u32(&mut record0, 0xC0, fdst_index);
+    set_u32(&mut record0, 0x50, first_image);
+    let name_offset = record0.len() as u32;
+    record0.extend_from_slice(title.as_bytes());
+    record0.extend_from_slice(&[0, 0]);
+    set_u32(&mut record0, 0x54, name_offset);
+    set_u32(&mut record0, 0x58, title.len() as u32);
+    records[0] = record0;
+
+    build_palmdb(title, &records)
+}
@@ -0,0 +1,252 @@
+mod helpers;
+
+use kindle_fix_core::formats::epub::reader::EpubReader;
+use kindle_fix_core::formats::mobi::book::MobiBook;
+use kindle_fix_core::formats::mobi::compression::{palmdoc_decompress, HuffCdicReader};
+use kindle_fix_core::formats::mobi::convert::convert_to_epub;
+use kindle_fix_core::formats::mobi::exth::{EXTH_AUTHOR, EXTH_COVER_OFFSET, EXTH_LANGUAGE};
+use kindle_fix_core::{process_file, FileFormat, FixOptions, KindleFixError};
+
+const JPEG: &[u8] = &[0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, b'J', b'F', b'I', b'F'];
+
+fn convert_options() -> FixOptions {
+    FixOptions {
+        convert_to_epub: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn palmdoc_decompresses_literals_spaces_and_back_references() {
+    let compressed = [b'a', b'b', b'c', 0x80, 0x18, 0xC1, 0x02, 0xC8, 0xC9];
+    let text = palmdoc_decompress(&compressed).unwrap();
+    assert_eq!(text, b"abcabc A\xC8\xC9");
+}
+
+#[test]
+fn palmdoc_rejects_reference_before_start() {
+    assert!(palmdoc_decompress(&[b'a', 0x80, 0x18]).is_err());
+}
+
+/// A HUFF table where every 8-bit code is terminal with max code 255, so
+/// code `c` maps to phrase `255 - c`.
+fn build_huff() -> Vec<u8> {
+    let mut huff = b"HUFF\x00\x00\x00\x18".to_vec();
+    huff.extend_from_slice(&24u32.to_be_bytes());
+    huff.extend_from_slice(&(24u32 + 256 * 4).to_be_bytes());
+    huff.resize(24, 0);
+    for _ in 0..256 {
+        let entry: u32 = (255 << 8) | 0x80 | 8;
+        huff.extend_from_slice(&entry.to_be_bytes());
+    }
+    huff.extend_from_slice(&[0; 64 * 4]);
+    huff
+}
+
+fn build_cdic(phrases: &[&[u8]]) -> Vec<u8> {
+    let mut cdic = b"CDIC\x00\x00\x00\x10".to_vec();
+    cdic.extend_from_slice(&(phrases.len() as u32).to_be_bytes());
+    cdic.extend_from_slice(&8u32.to_be_bytes());
+    let mut offsets = Vec::new();
+    let mut body = Vec::new();
+    let table_len = phrases.len() * 2;
+    for phrase in phrases {
+        offsets.push((table_len + body.len()) as u16);
+        body.extend_from_slice(&(phrase.len() as u16 | 0x8000).to_be_bytes());
+        body.extend_from_slice(phrase);
+    }
+    for offset in offsets {
+        cdic.extend_from_slice(&offset.to_be_bytes());
+    }
+    cdic.extend_from_slice(&body);
+    cdic
+}
+
+#[test]
+fn huffcdic_decodes_phrases() {
+    let huff = build_huff();
+    let cdic = build_cdic(&[b"Hello ", b"World"]);
+
+    let mut reader = HuffCdicReader::new(&huff, &[&cdic]).unwrap();
+    assert_eq!(reader.unpack(&[255, 254]).unwrap(), b"Hello World");
+    assert_eq!(
+        reader.unpack(&[254, 254, 255]).unwrap(),
+        b"WorldWorldHello "
+    );
+}
+
+#[test]
+fn huffcdic_rejects_bad_header() {
+    let cdic = build_cdic(&[b"x"]);
+    assert!(HuffCdicReader::new(b"NOTHUFF!", &[&cdic]).is_err());
+}
+
+#[test]
+fn converts_compressed_mobi6_text() {
+    let text = "<html><head><guide></guide></head><body><p>Caf\u{e9}</p></body></html>";
+    let compressed = helpers::palmdoc_literal(text.as_bytes());
+    let mut data = helpers::build_mobi("Compressed", &[], &[&compressed]);
+
+    // Switch the PalmDOC header to compression type 2 and fix the text length.
+    let record0 = 78 + 3 * 8 + 2;
+    data[record0..record0 + 2].copy_from_slice(&2u16.to_be_bytes());
+    helpers::set_u32(&mut data, record0 + 4, text.len() as u32);
+
+    let book = MobiBook::parse(&data).unwrap();
+    let epub = convert_to_epub(&book).unwrap();
+    let reader = EpubReader::from_bytes(&epub).unwrap();
+    assert!(reader.text_files()["OEBPS/text/part0000.xhtml"].contains("<p>Café</p>"));
+}
+
+#[test]
+fn converts_mobi6_chapters_links_and_images() {
+    let text = concat!(
+        "<html><head><guide></guide></head><body>",
+        "<h1>One</h1><p>See <a filepos=0000000099>chapter two</a><br>",
+        "<img recindex=\"00001\"><mbp:pagebreak/>",
+        "<h1>Two</h1><p>Fish &amp; chips &nbsp;done",
+        "</body></html>"
+    );
+    let target = text.find("<h1>Two").unwrap();
+    let text = text.replace("0000000099", &format!("{:010}", target));
+
+    let data = helpers::build_mobi_with_images(
+        "My Book",
+        &[
+            (EXTH_AUTHOR, b"Jane Doe"),
+            (EXTH_LANGUAGE, b"en"),
+            (EXTH_COVER_OFFSET, &0u32.to_be_bytes()),
+        ],
+        &[text.as_bytes()],
+        &[JPEG],
+    );
+
+    let output = process_file(&data, "book.mobi", &convert_options()).unwrap();
+    assert_eq!(output.report.format, FileFormat::Mobi);
+    assert_eq!(output.report.output_format, FileFormat::Epub);
+    assert_eq!(output.report.fixes_applied[0].name, "convert");
+
+    let reader = EpubReader::from_bytes(&output.data).unwrap();
+    let texts = reader.text_files();
+    let ch1 = &texts["OEBPS/text/part0000.xhtml"];
+    let ch2 = &texts["OEBPS/text/part0001.xhtml"];
+
+    assert!(ch1.contains(&format!(r#"<a href="part0001.xhtml#filepos{}">"#, target)));
+    assert!(ch1.contains("<br/>"));
+    assert!(ch1.contains(r#"<img src="../images/image00001.jpg"/>"#));
+    assert!(ch2.contains(&format!(r#"<a id="filepos{}"></a><h1>Two</h1>"#, target)));
+    assert!(ch2.contains("Fish &amp; chips &#160;done</p>"));
+    assert!(!ch1.contains("mbp:"));
+
+    let opf = &texts["OEBPS/content.opf"];
+    assert!(opf.contains("<dc:title>My Book</dc:title>"));
+    assert!(opf.contains(">Jane Doe</dc:creator>"));
+    assert!(opf.contains("<dc:language>en</dc:language>"));
+    assert!(opf.contains(r#"<meta name="cover" content="cover-image"/>"#));
+    assert!(opf.contains(r#"<item id="cover-image" href="images/image00001.jpg""#));
+
+    let ncx = &texts["OEBPS/toc.ncx"];
+    assert!(ncx.contains("<text>One</text>"));
+    assert!(ncx.contains(r#"<content src="text/part0001.xhtml"/>"#));
+
+    assert_eq!(reader.binary_files()["OEBPS/images/image00001.jpg"], JPEG);
+}
+
+#[test]
+fn converted_epub_goes_through_epub_fixes() {
+    let text = "<html><body><p>Hello</p><img alt=\"broken\"></body></html>";
+    let data = helpers::build_mobi("No Language", &[], &[text.as_bytes()]);
+
+    let output = process_file(&data, "book.mobi", &convert_options()).unwrap();
+    let names: Vec<&str> = output
+        .report
+        .fixes_applied
+        .iter()
+        .map(|f| f.name.as_str())
+        .collect();
+    assert!(names.contains(&"language"));
+    assert!(names.contains(&"stray_img"));
+}
+
+#[test]
+fn converts_kf8_skeletons_fragments_and_flows() {
+    let data = helpers::build_kf8_book(
+        "KF8 Book",
+        &[
+            (
+                "<html xmlns=\"http://www.w3.org/1999/xhtml\"><head><link href=\"kindle:flow:0001?mime=text/css\" rel=\"stylesheet\" type=\"text/css\"/></head><body aid=\"0\">{}</body></html>",
+                "<p aid=\"1\">First <a href=\"kindle:pos:fid:0001:off:0000000000\">next</a></p>",
+            ),
+            (
+                "<html xmlns=\"http://www.w3.org/1999/xhtml\"><head></head><body>{}</body></html>",
+                "<h2 id=\"two\">Second</h2><img src=\"kindle:embed:0001?mime=image/jpeg\"/>",
+            ),
+        ],
+        "p { margin: 0 }",
+        &[JPEG],
+    );
+
+    let output = process_file(&data, "book.azw3", &convert_options()).unwrap();
+    assert_eq!(output.report.format, FileFormat::Azw3);
+    assert_eq!(output.report.output_format, FileFormat::Epub);
+
+    let reader = EpubReader::from_bytes(&output.data).unwrap();
+    let texts = reader.text_files();
+    let part0 = &texts["OEBPS/text/part0000.xhtml"];
+    let part1 = &texts["OEBPS/text/part0001.xhtml"];
+
+    assert!(part0.contains(r#"<body><p>First <a href="part0001.xhtml#two">next</a></p></body>"#));
+    assert!(part0.contains(r#"href="../styles/flow0001.css""#));
+    assert!(!part0.contains("aid="));
+    assert!(part1.contains(r#"<img src="../images/image00001.jpg"/>"#));
+    assert_eq!(texts["OEBPS/styles/flow0001.css"], "p { margin: 0 }");
+    assert!(
+        texts["OEBPS/content.opf"].contains(r#"href="styles/flow0001.css" media-type="text/css""#)
+    );
+    assert!(reader
+        .binary_files()
+        .contains_key("OEBPS/images/image00001.jpg"));
+}
+
+#[test]
+fn rejects_malformed_index() {
+    let book = || {
+        helpers::build_kf8_book(
+            "KF8 Book",
+            &[("<html><body>{}</body></html>", "<p>Text</p>")],
+            "",
+            &[],
+        )
+    };
+    let skeleton = |data: &[u8]| data.windows(4).position(|w| w == b"INDX").unwrap();
+
+    let mut long_header = book();
+    let at = skeleton(&long_header);
+    helpers::set_u32(&mut long_header, at + 4, 0xFFFF);
+    let mut huge_count = book();
+    let at = skeleton(&huge_count);
+    helpers::set_u32(&mut huge_count, at + 24, u32::MAX);
+    helpers::set_u32(&mut huge_count, at + 52, 1);
+    let mut huge_entry_count = book();
+    let at = skeleton(&huge_entry_count) + 4;
+    let at = at + skeleton(&huge_entry_count[at..]);
+    helpers::set_u32(&mut huge_entry_count, at + 24, u32::MAX);
+
+    for data in [long_header, huge_count, huge_entry_count] {
+        let result = process_file(&data, "book.azw3", &convert_options());
+        assert!(
+            matches!(result, Err(KindleFixError::InvalidMobi(_))),
+            "{:?}",
+            result.map(|output| output.report)
+        );
+    }
+}
+
+#[test]
+fn without_convert_option_mobi_keeps_its_format() {
+    let data = helpers::build_mobi("Old Book", &[(EXTH_LANGUAGE, b"en")], &[b"<p>Hello</p>"]);
+
+    let output = process_file(&data, "book.mobi", &FixOptions::default()).unwrap();
+    assert_eq!(output.report.output_format, FileFormat::Mobi);
+    assert_eq!(output.data, data);
+}
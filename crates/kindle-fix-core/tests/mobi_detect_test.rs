@@ -0,0 +1,77 @@
+mod helpers;
+
+use kindle_fix_core::formats::azw3::Azw3Fixer;
+use kindle_fix_core::formats::mobi::book::{MobiBook, MobiKind};
+use kindle_fix_core::formats::mobi::exth::EXTH_KF8_BOUNDARY;
+use kindle_fix_core::formats::mobi::MobiFixer;
+use kindle_fix_core::formats::FileFixer;
+use kindle_fix_core::{process_file, FileFormat, FixOptions};
+
+#[test]
+fn detects_legacy_mobi() {
+    let data = helpers::build_mobi("Old Book", &[], &[b"<p>Hello</p>"]);
+
+    assert_eq!(MobiKind::detect(&data), Some(MobiKind::Mobi6));
+    assert!(MobiFixer::detect(&data));
+    assert!(!Azw3Fixer::detect(&data));
+}
+
+#[test]
+fn detects_standalone_kf8_as_azw3() {
+    let data = helpers::build_azw3("New Book", &[], &[b"<p>Hello</p>"]);
+
+    assert_eq!(MobiKind::detect(&data), Some(MobiKind::Kf8));
+    assert!(Azw3Fixer::detect(&data));
+    assert!(!MobiFixer::detect(&data));
+}
+
+#[test]
+fn detects_combo_file() {
+    let data = helpers::build_combo_mobi("Combo Book", &[b"<p>Hello</p>"]);
+
+    let kind = MobiKind::detect(&data).unwrap();
+    assert_eq!(kind, MobiKind::Combo { kf8_record: 3 });
+    assert!(kind.has_kf8());
+    assert_eq!(kind.format(), FileFormat::Mobi);
+    assert!(MobiFixer::detect(&data));
+    assert!(!Azw3Fixer::detect(&data));
+}
+
+#[test]
+fn ignores_boundary_record_pointing_at_non_kf8_header() {
+    let bogus = 1u32.to_be_bytes();
+    let data = helpers::build_mobi("Odd Book", &[(EXTH_KF8_BOUNDARY, &bogus)], &[b"<p>Hi</p>"]);
+
+    assert_eq!(MobiKind::detect(&data), Some(MobiKind::Mobi6));
+}
+
+#[test]
+fn does_not_detect_non_mobi_data() {
+    assert_eq!(MobiKind::detect(b"this is not an ebook"), None);
+    assert!(!Azw3Fixer::detect(b"this is not an ebook"));
+}
+
+#[test]
+fn parsed_book_records_its_kind() {
+    let data = helpers::build_combo_mobi("Combo Book", &[b"<p>Hello</p>"]);
+
+    let book = MobiBook::parse(&data).unwrap();
+    assert_eq!(book.kind, MobiKind::Combo { kf8_record: 3 });
+}
+
+#[test]
+fn process_file_reports_azw3() {
+    let data = helpers::build_azw3("New Book", &[], &[b"<p>Hello</p>"]);
+
+    let output = process_file(&data, "book.azw3", &FixOptions::default()).unwrap();
+    assert_eq!(output.report.format, FileFormat::Azw3);
+    assert_eq!(output.report.filename, "book.azw3");
+}
+
+#[test]
+fn process_file_reports_mobi() {
+    let data = helpers::build_mobi("Old Book", &[], &[b"<p>Hello</p>"]);
+
+    let output = process_file(&data, "book.mobi", &FixOptions::default()).unwrap();
+    assert_eq!(output.report.format, FileFormat::Mobi);
+}
@@ -0,0 +1,203 @@
+mod helpers;
+
+use kindle_fix_core::formats::mobi::book::{MobiBook, MobiKind};
+use kindle_fix_core::formats::mobi::exth::{
+    EXTH_ASIN, EXTH_AUTHOR, EXTH_CDE_TYPE, EXTH_COVER_OFFSET, EXTH_LANGUAGE, EXTH_UPDATED_TITLE,
+};
+use kindle_fix_core::formats::mobi::metadata::{edit_metadata, MetadataEdit};
+use kindle_fix_core::formats::mobi::palmdb::PalmDb;
+use kindle_fix_core::{process_file, DiagnosticCode, FixOptions};
+
+#[test]
+fn palmdb_round_trips_through_to_bytes() {
+    let data = helpers::build_mobi("Round Trip", &[(EXTH_AUTHOR, b"Jane")], &[b"<p>Hi</p>"]);
+
+    let db = PalmDb::parse(&data).unwrap();
+    assert_eq!(db.to_bytes(), data);
+}
+
+#[test]
+fn edits_exth_metadata_and_full_name() {
+    let data = helpers::build_mobi(
+        "Old Title",
+        &[
+            (EXTH_AUTHOR, b"Old Author"),
+            (EXTH_AUTHOR, b"Second Author"),
+            (EXTH_UPDATED_TITLE, b"Old Title"),
+        ],
+        &[b"<p>Hello</p>", b"<p>World</p>"],
+    );
+    let edit = MetadataEdit {
+        title: Some("A Much Longer New Title".to_string()),
+        author: Some("New Author".to_string()),
+        language: Some("de".to_string()),
+        asin: Some("B000000000".to_string()),
+        cde_type: Some("EBOK".to_string()),
+        cover_offset: Some(2),
+    };
+
+    let edited = edit_metadata(&data, &edit).unwrap();
+    let book = MobiBook::parse(&edited).unwrap();
+    let exth = book.exth.as_ref().unwrap();
+
+    assert_eq!(book.full_name, "A Much Longer New Title");
+    assert_eq!(book.title(), "A Much Longer New Title");
+    assert_eq!(exth.get_all(EXTH_AUTHOR).count(), 1);
+    assert_eq!(book.author().as_deref(), Some("New Author"));
+    assert_eq!(book.language().as_deref(), Some("de"));
+    assert_eq!(book.exth_string(EXTH_ASIN).as_deref(), Some("B000000000"));
+    assert_eq!(book.exth_string(EXTH_CDE_TYPE).as_deref(), Some("EBOK"));
+    assert_eq!(exth.get_u32(EXTH_COVER_OFFSET), Some(2));
+
+    // Content records are untouched even though record 0 grew.
+    assert_eq!(book.palmdb.record(1).unwrap(), b"<p>Hello</p>");
+    assert_eq!(book.palmdb.record(2).unwrap(), b"<p>World</p>");
+}
+
+#[test]
+fn adds_exth_block_to_book_without_one() {
+    let data = helpers::build_mobi("No Exth", &[], &[b"<p>Hello</p>"]);
+    let edit = MetadataEdit {
+        language: Some("fr".to_string()),
+        ..Default::default()
+    };
+
+    let edited = edit_metadata(&data, &edit).unwrap();
+    let book = MobiBook::parse(&edited).unwrap();
+    assert!(book.mobi.has_exth());
+    assert_eq!(book.language().as_deref(), Some("fr"));
+    assert_eq!(book.full_name, "No Exth");
+}
+
+#[test]
+fn edits_both_headers_of_combo_files() {
+    let data = helpers::build_combo_mobi("Combo", &[b"<p>Hello</p>"]);
+    let edit = MetadataEdit {
+        author: Some("Jane Doe".to_string()),
+        ..Default::default()
+    };
+
+    let edited = edit_metadata(&data, &edit).unwrap();
+    let kind = MobiKind::detect(&edited).unwrap();
+    let MobiKind::Combo { kf8_record } = kind else {
+        panic!("expected a combo file, got {:?}", kind);
+    };
+
+    let book = MobiBook::parse(&edited).unwrap();
+    assert_eq!(book.author().as_deref(), Some("Jane Doe"));
+
+    let kf8_header = book.palmdb.record(kf8_record).unwrap();
+    let kf8_book = helpers::build_palmdb("KF8", &[kf8_header.to_vec()]);
+    let kf8 = MobiBook::parse(&kf8_book).unwrap();
+    assert_eq!(kf8.author().as_deref(), Some("Jane Doe"));
+}
+
+#[test]
+fn adds_missing_language_to_mobi() {
+    let data = helpers::build_mobi("No Language", &[], &[b"<p>Hello</p>"]);
+
+    let output = process_file(&data, "book.mobi", &FixOptions::default()).unwrap();
+    assert_eq!(output.report.fixes_applied[0].name, "language");
+    let book = MobiBook::parse(&output.data).unwrap();
+    assert_eq!(book.language().as_deref(), Some("en"));
+}
+
+#[test]
+fn warns_about_unsupported_mobi_language() {
+    let data = helpers::build_azw3("Klingon", &[(EXTH_LANGUAGE, b"tlh")], &[b"<p>Hello</p>"]);
+
+    let output = process_file(&data, "book.azw3", &FixOptions::default()).unwrap();
+    assert!(output.report.fixes_applied.is_empty());
+    assert_eq!(
+        output.report.warnings[0].code,
+        DiagnosticCode::UnsupportedLanguage
+    );
+    // MOBI books have no files to point at
+    assert!(output.report.warnings[0].file.is_none());
+    assert_eq!(output.data, data);
+}
+
+#[test]
+fn overrides_unsupported_mobi_language() {
+    let data = helpers::build_azw3("Klingon", &[(EXTH_LANGUAGE, b"tlh")], &[b"<p>Hello</p>"]);
+    let options = FixOptions {
+        language: Some("en".to_string()),
+        ..Default::default()
+    };
+
+    let output = process_file(&data, "book.azw3", &options).unwrap();
+    assert_eq!(
+        output.report.fixes_applied[0].details,
+        "Changed language from tlh to en"
+    );
+    let book = MobiBook::parse(&output.data).unwrap();
+    assert_eq!(book.language().as_deref(), Some("en"));
+}
+
+#[test]
+fn leaves_encrypted_books_alone() {
+    let mut data = helpers::build_mobi("Locked", &[], &[b"<p>Hello</p>"]);
+    let record0 = u32::from_be_bytes(data[78..82].try_into().unwrap()) as usize;
+    data[record0 + 12..record0 + 14].copy_from_slice(&2u16.to_be_bytes());
+
+    let edit = MetadataEdit {
+        language: Some("en".to_string()),
+        ..Default::default()
+    };
+    assert!(edit_metadata(&data, &edit).is_err());
+
+    let output = process_file(&data, "book.mobi", &FixOptions::default()).unwrap();
+    assert!(output.report.fixes_applied.is_empty());
+    assert_eq!(output.report.warnings[0].code, DiagnosticCode::DrmProtected);
+    assert_eq!(output.data, data);
+}
+
+#[test]
+fn refuses_to_drop_unknown_header_record_data() {
+    let mut record0 = helpers::build_mobi_record0("Odd", 6, &[], &[b"<p>Hi</p>"]);
+    record0.extend_from_slice(b"DATA");
+    let data = helpers::build_palmdb("Odd", &[record0, b"<p>Hi</p>".to_vec()]);
+
+    let edit = MetadataEdit {
+        language: Some("en".to_string()),
+        ..Default::default()
+    };
+    assert!(edit_metadata(&data, &edit).is_err());
+}
+
+#[test]
+fn keeps_database_name_bytes() {
+    let mut data = helpers::build_mobi("Caf", &[], &[b"<p>Hello</p>"]);
+    data[3] = 0xE9;
+
+    let edit = MetadataEdit {
+        language: Some("fr".to_string()),
+        ..Default::default()
+    };
+    let edited = edit_metadata(&data, &edit).unwrap();
+    assert_eq!(edited[..32], data[..32]);
+}
+
+#[test]
+fn cp1252_metadata_uses_windows_1252() {
+    let mut data = helpers::build_mobi(
+        "Quotes",
+        &[(EXTH_AUTHOR, b"\x93Smart\x94 \x80")],
+        &[b"<p>Hello</p>"],
+    );
+    let record0 = u32::from_be_bytes(data[78..82].try_into().unwrap()) as usize;
+    helpers::set_u32(&mut data, record0 + 0x1C, 1252);
+    assert_eq!(
+        MobiBook::parse(&data).unwrap().author().as_deref(),
+        Some("\u{201C}Smart\u{201D} \u{20AC}")
+    );
+
+    let edit = MetadataEdit {
+        author: Some("\u{2018}Ω\u{2019} é".to_string()),
+        ..Default::default()
+    };
+    let edited = edit_metadata(&data, &edit).unwrap();
+    let book = MobiBook::parse(&edited).unwrap();
+    let author = book.exth.as_ref().unwrap().get(EXTH_AUTHOR).unwrap();
+    assert_eq!(author.data, b"\x91?\x92 \xE9");
+}
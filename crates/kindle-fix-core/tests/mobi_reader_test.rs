@@ -0,0 +1,98 @@
+mod helpers;
+
+use kindle_fix_core::formats::mobi::book::MobiBook;
+use kindle_fix_core::formats::mobi::exth::{Exth, EXTH_AUTHOR, EXTH_COVER_OFFSET, EXTH_LANGUAGE};
+use kindle_fix_core::formats::mobi::header::{Compression, ENCODING_UTF8};
+use kindle_fix_core::formats::mobi::palmdb::PalmDb;
+use kindle_fix_core::formats::mobi::MobiFixer;
+use kindle_fix_core::formats::FileFixer;
+use kindle_fix_core::types::FixOptions;
+use kindle_fix_core::KindleFixError;
+
+#[test]
+fn parses_palmdb_record_table() {
+    let data = helpers::build_mobi("Test Book", &[], &[b"<p>Hello</p>", b"<p>World</p>"]);
+
+    let db = PalmDb::parse(&data).unwrap();
+    assert_eq!(db.header.name(), "Test Book");
+    assert_eq!(&db.header.type_code, b"BOOK");
+    assert_eq!(&db.header.creator, b"MOBI");
+    assert_eq!(db.records.len(), 4);
+    assert_eq!(db.record(1).unwrap(), b"<p>Hello</p>");
+    assert_eq!(db.record(2).unwrap(), b"<p>World</p>");
+    assert_eq!(db.records[2].unique_id, 4);
+}
+
+#[test]
+fn parses_palmdoc_and_mobi_headers() {
+    let data = helpers::build_mobi("Test Book", &[], &[b"<p>Hello</p>"]);
+
+    let book = MobiBook::parse(&data).unwrap();
+    assert_eq!(book.palmdoc.compression, Compression::None);
+    assert_eq!(book.palmdoc.text_length, 12);
+    assert_eq!(book.palmdoc.text_record_count, 1);
+    assert_eq!(book.mobi.header_length, 0xE8);
+    assert_eq!(book.mobi.text_encoding, ENCODING_UTF8);
+    assert_eq!(book.mobi.file_version, 6);
+    assert_eq!(book.mobi.first_non_book_index, 2);
+    assert_eq!(book.full_name, "Test Book");
+    assert!(book.exth.is_none());
+    assert!(!book.is_encrypted());
+}
+
+#[test]
+fn parses_exth_records() {
+    let data = helpers::build_mobi(
+        "Test Book",
+        &[
+            (EXTH_AUTHOR, b"Jane Doe"),
+            (EXTH_LANGUAGE, b"fr"),
+            (EXTH_COVER_OFFSET, &3u32.to_be_bytes()),
+        ],
+        &[b"<p>Bonjour</p>"],
+    );
+
+    let book = MobiBook::parse(&data).unwrap();
+    let exth = book.exth.as_ref().unwrap();
+    assert_eq!(exth.records.len(), 3);
+    assert_eq!(exth.get_u32(EXTH_COVER_OFFSET), Some(3));
+    assert_eq!(book.author().as_deref(), Some("Jane Doe"));
+    assert_eq!(book.language().as_deref(), Some("fr"));
+    assert_eq!(book.title(), "Test Book");
+}
+
+#[test]
+fn rejects_exth_with_bogus_record_count() {
+    let mut block = b"EXTH".to_vec();
+    block.extend_from_slice(&20u32.to_be_bytes());
+    block.extend_from_slice(&u32::MAX.to_be_bytes());
+    block.extend_from_slice(&[0u8; 8]);
+
+    let result = Exth::parse(&block);
+    assert!(matches!(result, Err(KindleFixError::InvalidMobi(_))));
+}
+
+#[test]
+fn rejects_truncated_file() {
+    let data = helpers::build_mobi("Test Book", &[], &[b"<p>Hello</p>"]);
+
+    let result = MobiBook::parse(&data[..100]);
+    assert!(matches!(result, Err(KindleFixError::InvalidMobi(_))));
+}
+
+#[test]
+fn rejects_record0_without_mobi_header() {
+    let data = helpers::build_palmdb("Broken", &[vec![0u8; 64]]);
+
+    let result = MobiBook::parse(&data);
+    assert!(matches!(result, Err(KindleFixError::InvalidMobi(_))));
+}
+
+#[test]
+fn mobi_fixer_returns_report() {
+    let data = helpers::build_mobi("Test Book", &[(EXTH_LANGUAGE, b"en")], &[b"<p>Hi</p>"]);
+
+    let output = MobiFixer.fix(&data, &FixOptions::default()).unwrap();
+    assert!(output.report.warnings.is_empty());
+    assert_eq!(output.data, data);
+}
@@ -0,0 +1,140 @@
+mod helpers;
+
+use kindle_fix_core::formats::mobi::exth::EXTH_AUTHOR;
+use kindle_fix_core::{
+    process_file, BookMetadata, FileFormat, FixOptions, FixReport, KindleFixError, NameTemplate,
+};
+
+const OPF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>War &amp; Peace: Volume 1/2</dc:title>
+    <dc:creator>Leo Tolstoy</dc:creator>
+    <dc:creator>Translator</dc:creator>
+    <dc:language>en</dc:language>
+    <dc:date>1869-01-01T00:00:00Z</dc:date>
+  </metadata>
+  <manifest/>
+  <spine/>
+</package>"#;
+
+fn report(format: FileFormat, metadata: BookMetadata) -> FixReport {
+    let mut report = FixReport::new("book.epub".into(), format);
+    report.metadata = metadata;
+    report
+}
+
+#[test]
+fn default_template_adds_fixed_prefix() {
+    let report = report(FileFormat::Epub, BookMetadata::default());
+    assert_eq!(
+        NameTemplate::default().render("book.epub", &report),
+        "(fixed) book.epub"
+    );
+    assert_eq!(
+        NameTemplate::keep_name().render("book.epub", &report),
+        "book.epub"
+    );
+}
+
+#[test]
+fn ext_follows_output_format() {
+    let mut report = report(FileFormat::Mobi, BookMetadata::default());
+    report.output_format = FileFormat::Epub;
+
+    assert_eq!(
+        NameTemplate::default().render("book.mobi", &report),
+        "(fixed) book.epub"
+    );
+}
+
+#[test]
+fn renders_metadata_from_epub() {
+    let epub = helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/content.opf", OPF),
+    ]);
+    let output = process_file(&epub, "wp.epub", &FixOptions::default()).unwrap();
+
+    let template = NameTemplate::parse("{author}/{title} ({date}) [{lang}].{ext}").unwrap();
+    assert_eq!(
+        template.render("wp.epub", &output.report),
+        "Leo Tolstoy/War & Peace_ Volume 1_2 (1869-01-01) [en].epub"
+    );
+}
+
+#[test]
+fn missing_metadata_falls_back() {
+    let report = report(FileFormat::Epub, BookMetadata::default());
+    let template = NameTemplate::parse("{author} - {title} [{lang}]{date}.{ext}").unwrap();
+
+    assert_eq!(
+        template.render("book.epub", &report),
+        "Unknown - book [und].epub"
+    );
+}
+
+#[test]
+fn metadata_cannot_escape_directory() {
+    let metadata = BookMetadata {
+        title: Some("..".into()),
+        author: Some("../../etc".into()),
+        ..Default::default()
+    };
+    let template = NameTemplate::parse("{author}/{title}.{ext}").unwrap();
+
+    assert_eq!(
+        template.render("book.epub", &report(FileFormat::Epub, metadata)),
+        ".._.._etc/book.epub"
+    );
+}
+
+#[test]
+fn escaped_braces_are_literal() {
+    let template = NameTemplate::parse("{{{stem}}}.{ext}").unwrap();
+    let report = report(FileFormat::Epub, BookMetadata::default());
+
+    assert_eq!(template.render("book.epub", &report), "{book}.epub");
+}
+
+#[test]
+fn rejects_invalid_templates() {
+    for template in [
+        "",
+        "{name}.epub",
+        "{stem.epub",
+        "stem}.epub",
+        "/abs/{stem}",
+        "\\abs\\{stem}",
+        "../{stem}.{ext}",
+        "{lang}/../../{stem}.{ext}",
+        "{lang}\\..\\{stem}.{ext}",
+        "C:\\x\\{stem}.{ext}",
+        "c:{stem}.{ext}",
+    ] {
+        assert!(
+            matches!(
+                NameTemplate::parse(template),
+                Err(KindleFixError::InvalidTemplate(_))
+            ),
+            "{template:?} should be rejected"
+        );
+    }
+}
+
+#[test]
+fn accepts_relative_templates_with_dots() {
+    for template in ["{lang}/{title}.{ext}", "./{stem}..{ext}", "..{stem}.{ext}"] {
+        assert!(NameTemplate::parse(template).is_ok(), "{template:?}");
+    }
+}
+
+#[test]
+fn mobi_report_carries_exth_metadata() {
+    let mobi = helpers::build_mobi("Mobi Title", &[(EXTH_AUTHOR, b"Jane Doe")], &[b"<p>Hi</p>"]);
+    let output = process_file(&mobi, "book.mobi", &FixOptions::default()).unwrap();
+
+    assert_eq!(output.report.metadata.title.as_deref(), Some("Mobi Title"));
+    assert_eq!(output.report.metadata.author.as_deref(), Some("Jane Doe"));
+    assert_eq!(output.report.metadata.language.as_deref(), Some("en"));
+}
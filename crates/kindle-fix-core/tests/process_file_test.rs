@@ -1,5 +1,6 @@
 mod helpers;
 
+use kindle_fix_core::formats::epub::reader::EpubReader;
 use kindle_fix_core::{process_file, FileFormat, FixOptions};
 
 #[test]
@@ -22,3 +23,70 @@ fn process_unknown_format() {
     let result = process_file(data, "test.txt", &FixOptions::default());
     assert!(result.is_err());
 }
+
+#[test]
+fn report_serializes_to_json() {
+    let epub = helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/content.opf", &helpers::opf_with_language("en")),
+        ("OEBPS/chapter1.xhtml", "<html><body>Hello</body></html>"),
+    ]);
+
+    let output = process_file(&epub, "test.epub", &FixOptions::default()).unwrap();
+    let json = serde_json::to_value(&output.report).unwrap();
+    assert_eq!(json["filename"], "test.epub");
+    assert_eq!(json["format"], "epub");
+    assert_eq!(json["fixes_applied"][0]["name"], "encoding");
+    assert_eq!(json["fixes_applied"][0]["files_affected"], 1);
+    assert!(json["warnings"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn clean_book_is_returned_unchanged() {
+    let epub = helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        (
+            "OEBPS/content.opf",
+            &helpers::opf_with_items("en", &[("chapter1.xhtml", "application/xhtml+xml")]),
+        ),
+        (
+            "OEBPS/chapter1.xhtml",
+            r#"<?xml version="1.0" encoding="UTF-8"?><html><body>Hello</body></html>"#,
+        ),
+    ]);
+
+    let output = process_file(&epub, "test.epub", &FixOptions::default()).unwrap();
+    assert!(output.unchanged, "{:?}", output.report.fixes_applied);
+    assert_eq!(output.data, epub);
+}
+
+#[test]
+fn fixed_book_is_not_unchanged() {
+    let epub = helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/content.opf", &helpers::opf_without_language()),
+    ]);
+
+    let output = process_file(&epub, "test.epub", &FixOptions::default()).unwrap();
+    assert!(!output.unchanged);
+    assert_ne!(output.data, epub);
+}
+
+#[test]
+fn language_fix_keeps_title_entities() {
+    let opf = helpers::opf_without_language().replace(
+        "<dc:title>Test Book</dc:title>",
+        "<dc:title>Tom &amp; Jerry&nbsp;Stories</dc:title>",
+    );
+    let epub = helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/content.opf", &opf),
+        ("OEBPS/chapter1.xhtml", "<html><body>Hello</body></html>"),
+    ]);
+
+    let output = process_file(&epub, "test.epub", &FixOptions::default()).unwrap();
+    let reader = EpubReader::from_bytes(&output.data).unwrap();
+    let opf = &reader.text_files()["OEBPS/content.opf"];
+    assert!(opf.contains("<dc:language>en</dc:language>"));
+    assert!(opf.contains("<dc:title>Tom &amp; Jerry&nbsp;Stories</dc:title>"));
+}
@@ -0,0 +1,46 @@
+mod helpers;
+
+use kindle_fix_core::{process_file, validate_file, FileFormat, FixOptions, KindleFixError};
+
+#[test]
+fn fixed_epub_validates() {
+    let epub = helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/content.opf", &helpers::opf_with_language("en")),
+        ("OEBPS/chapter1.xhtml", "<html><body>Hello</body></html>"),
+    ]);
+
+    let output = process_file(&epub, "test.epub", &FixOptions::default()).unwrap();
+    assert!(validate_file(&output.data, FileFormat::Epub).is_ok());
+}
+
+#[test]
+fn epub_without_package_document_is_invalid() {
+    let epub = helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/chapter1.xhtml", "<html><body>Hello</body></html>"),
+    ]);
+
+    let err = validate_file(&epub, FileFormat::Epub).unwrap_err();
+    assert!(matches!(err, KindleFixError::InvalidEpub(_)));
+    assert!(err.to_string().contains("OEBPS/content.opf"));
+}
+
+#[test]
+fn truncated_epub_is_invalid() {
+    let epub = helpers::build_epub(&[
+        ("META-INF/container.xml", helpers::CONTAINER_XML),
+        ("OEBPS/content.opf", &helpers::opf_with_language("en")),
+    ]);
+
+    assert!(validate_file(&epub[..epub.len() / 2], FileFormat::Epub).is_err());
+}
+
+#[test]
+fn mobi_validates_against_its_own_format() {
+    let mobi = helpers::build_mobi("Test", &[], &[b"<html><body>Hello</body></html>"]);
+
+    assert!(validate_file(&mobi, FileFormat::Mobi).is_ok());
+    assert!(validate_file(&mobi, FileFormat::Epub).is_err());
+    assert!(validate_file(b"not a book", FileFormat::Mobi).is_err());
+}
@@ -1,34 +1,60 @@
 use kindle_fix_core::formats::epub::fixes::language::SUPPORTED_LANGUAGES;
-use kindle_fix_core::{process_file, FixOptions};
+use kindle_fix_core::formats::epub::fixes::registry;
+use kindle_fix_core::{process_file, FixOptions, FixReport, NameTemplate};
 use serde::Serialize;
+use std::collections::HashSet;
 use std::fs;
+use std::path::{Path, PathBuf};
 
 #[derive(Serialize)]
 pub struct GuiFixReport {
+    pub path: String,
     pub filename: String,
-    pub format: String,
-    pub fixes: Vec<String>,
-    pub warnings: Vec<String>,
-    pub has_fixes: bool,
+    pub output_path: Option<String>,
+    pub report: Option<FixReport>,
+    /// Nothing needed fixing, so no file was written.
+    pub unchanged: bool,
     pub error: Option<String>,
 }
 
+#[derive(Serialize)]
+pub struct GuiFix {
+    pub id: String,
+    pub description: String,
+    pub enabled_by_default: bool,
+}
+
 #[tauri::command]
 pub fn process_files(
     paths: Vec<String>,
     language: Option<String>,
     keep_name: bool,
-) -> Vec<GuiFixReport> {
+    convert_to_epub: bool,
+    fixes: Option<Vec<String>>,
+    name_template: Option<String>,
+) -> Result<Vec<GuiFixReport>, String> {
+    let template = match name_template.filter(|t| !t.trim().is_empty()) {
+        Some(t) => NameTemplate::parse(&t).map_err(|e| e.to_string())?,
+        None if keep_name => NameTemplate::keep_name(),
+        None => NameTemplate::default(),
+    };
+
     let options = FixOptions {
         language,
         keep_name,
         dry_run: false,
+        convert_to_epub,
+        only: fixes,
+        ..Default::default()
     };
 
-    paths
+    // Where books have been written so far, so that no two are saved to the same path
+    let mut saved = HashSet::new();
+
+    Ok(paths
         .iter()
         .map(|path| {
-            let filename = std::path::Path::new(path)
+            let filename = Path::new(path)
                 .file_name()
                 .unwrap_or_default()
                 .to_string_lossy()
@@ -37,63 +63,79 @@ pub fn process_files(
             match fs::read(path) {
                 Ok(data) => match process_file(&data, &filename, &options) {
                     Ok(output) => {
-                        let output_path = if keep_name {
-                            path.clone()
-                        } else {
-                            let p = std::path::Path::new(path);
-                            let parent = p.parent().unwrap_or(std::path::Path::new("."));
-                            parent
-                                .join(format!("(fixed) {}", filename))
-                                .to_string_lossy()
-                                .to_string()
-                        };
+                        let output_name = template.render(&filename, &output.report);
+                        let parent = Path::new(path).parent().unwrap_or(Path::new("."));
+                        let output_path = parent.join(output_name);
 
-                        if !output.data.is_empty() {
-                            fs::write(&output_path, &output.data).ok();
+                        let mut written = None;
+                        let mut error = None;
+                        if !output.unchanged && !output.data.is_empty() {
+                            match write_output(&output_path, &output.data, &mut saved) {
+                                Ok(()) => written = Some(output_path.to_string_lossy().to_string()),
+                                Err(e) => error = Some(e),
+                            }
                         }
 
-                        let has_fixes = output.report.has_fixes();
-                        let format = output.report.format.to_string();
-                        let fixes: Vec<String> = output
-                            .report
-                            .fixes_applied
-                            .iter()
-                            .map(|f| f.details.clone())
-                            .collect();
-                        let warnings = output.report.warnings;
-
                         GuiFixReport {
+                            path: path.clone(),
                             filename,
-                            format,
-                            fixes,
-                            warnings,
-                            has_fixes,
-                            error: None,
+                            output_path: written,
+                            report: Some(output.report),
+                            unchanged: output.unchanged,
+                            error,
                         }
                     }
                     Err(e) => GuiFixReport {
+                        path: path.clone(),
                         filename,
-                        format: "Unknown".into(),
-                        fixes: vec![],
-                        warnings: vec![],
-                        has_fixes: false,
+                        output_path: None,
+                        report: None,
+                        unchanged: false,
                         error: Some(e.to_string()),
                     },
                 },
                 Err(e) => GuiFixReport {
+                    path: path.clone(),
                     filename,
-                    format: "Unknown".into(),
-                    fixes: vec![],
-                    warnings: vec![],
-                    has_fixes: false,
+                    output_path: None,
+                    report: None,
+                    unchanged: false,
                     error: Some(format!("Could not read file: {}", e)),
                 },
             }
         })
-        .collect()
+        .collect())
+}
+
+/// Write a fixed book to `path`, refusing to overwrite one written earlier
+/// in the same run.
+fn write_output(path: &Path, data: &[u8], saved: &mut HashSet<PathBuf>) -> Result<(), String> {
+    if !saved.insert(path.to_path_buf()) {
+        return Err(format!(
+            "Not writing {}: another book in this run was already saved there",
+            path.display()
+        ));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    }
+    fs::write(path, data).map_err(|e| format!("Could not write {}: {}", path.display(), e))
 }
 
 #[tauri::command]
 pub fn get_supported_languages() -> Vec<String> {
     SUPPORTED_LANGUAGES.iter().map(|s| s.to_string()).collect()
 }
+
+#[tauri::command]
+pub fn list_fixes() -> Vec<GuiFix> {
+    registry()
+        .iter()
+        .map(|fix| GuiFix {
+            id: fix.id().to_string(),
+            description: fix.description().to_string(),
+            enabled_by_default: fix.enabled_by_default(),
+        })
+        .collect()
+}
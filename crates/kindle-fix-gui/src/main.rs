@@ -9,6 +9,7 @@ fn main() {
         .invoke_handler(tauri::generate_handler![
             commands::process_files,
             commands::get_supported_languages,
+            commands::list_fixes,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
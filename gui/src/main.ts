@@ -10,20 +10,97 @@ declare global {
     }
 }
 
+interface Change {
+    file: string;
+    original: string;
+    replacement: string;
+    line: number | null;
+    column: number | null;
+}
+
+interface FixDescription {
+    name: string;
+    details: string;
+    files_affected: number;
+    changes: Change[];
+}
+
+interface Diagnostic {
+    code: string;
+    severity: "warning" | "error";
+    message: string;
+    file: string | null;
+    line: number | null;
+    column: number | null;
+    suggestion: string | null;
+    fix: string | null;
+}
+
 interface FixReport {
     filename: string;
     format: string;
-    fixes: string[];
-    warnings: string[];
-    has_fixes: boolean;
+    output_format: string;
+    fixes_applied: FixDescription[];
+    warnings: Diagnostic[];
+}
+
+interface FileResult {
+    path: string;
+    filename: string;
+    output_path: string | null;
+    report: FixReport | null;
+    unchanged: boolean;
     error: string | null;
 }
 
+interface Fix {
+    id: string;
+    description: string;
+    enabled_by_default: boolean;
+}
+
 const dropzone = document.getElementById("dropzone")!;
 const selectBtn = document.getElementById("selectBtn")!;
 const resultsEl = document.getElementById("results")!;
 const statusEl = document.getElementById("status")! as HTMLDivElement;
 const keepName = document.getElementById("keepName") as HTMLInputElement;
+const convertToEpub = document.getElementById("convertToEpub") as HTMLInputElement;
+const nameTemplate = document.getElementById("nameTemplate") as HTMLInputElement;
+const fixesEl = document.getElementById("fixes")!;
+
+async function loadFixes() {
+    const { invoke } = window.__TAURI__.core;
+    const fixes = await invoke<Fix[]>("list_fixes");
+
+    for (const fix of fixes) {
+        const label = document.createElement("label");
+        label.title = fix.description;
+        const checkbox = document.createElement("input");
+        checkbox.type = "checkbox";
+        checkbox.value = fix.id;
+        checkbox.checked = fix.enabled_by_default;
+        label.append(checkbox, ` ${fix.description}`);
+        fixesEl.appendChild(label);
+    }
+}
+
+function selectedFixes(): string[] {
+    const boxes = fixesEl.querySelectorAll<HTMLInputElement>("input[type=checkbox]");
+    return Array.from(boxes)
+        .filter((box) => box.checked)
+        .map((box) => box.value);
+}
+
+loadFixes().catch((err) => console.error("Could not load fixes:", err));
+
+// The output name template is remembered between sessions
+nameTemplate.value = localStorage.getItem("nameTemplate") ?? "";
+nameTemplate.addEventListener("change", () => {
+    localStorage.setItem("nameTemplate", nameTemplate.value.trim());
+});
+keepName.addEventListener("change", () => {
+    nameTemplate.disabled = keepName.checked;
+});
 
 function showStatus(message: string) {
     statusEl.textContent = message;
@@ -80,17 +157,20 @@ dropzone.addEventListener("drop", async (e) => {
     }
 });
 
-async function processFiles(paths: string[]) {
+async function processFiles(paths: string[], language: string | null = null) {
     const { invoke } = window.__TAURI__.core;
 
     showStatus(`Processing ${paths.length} file(s)...`);
     resultsEl.innerHTML = "";
 
     try {
-        const reports = await invoke<FixReport[]>("process_files", {
+        const reports = await invoke<FileResult[]>("process_files", {
             paths,
-            language: null,
+            language,
             keepName: keepName.checked,
+            convertToEpub: convertToEpub.checked,
+            fixes: selectedFixes(),
+            nameTemplate: keepName.checked ? null : nameTemplate.value.trim() || null,
         });
 
         hideStatus();
@@ -101,31 +181,83 @@ async function processFiles(paths: string[]) {
     }
 }
 
-function renderResults(reports: FixReport[]) {
+function renderResults(results: FileResult[]) {
     resultsEl.innerHTML = "";
 
-    for (const report of reports) {
+    for (const result of results) {
         const div = document.createElement("div");
         div.className = "result-item";
 
+        const report = result.report;
         let statusHtml = "";
-        if (report.error) {
-            statusHtml = `<p class="error">${escapeHtml(report.error)}</p>`;
-        } else if (report.fixes.length > 0) {
-            statusHtml = `<ul>${report.fixes.map((f) => `<li class="fix">${escapeHtml(f)}</li>`).join("")}</ul>`;
+        if (result.error || !report) {
+            statusHtml = `<p class="error">${escapeHtml(result.error ?? "Unknown error")}</p>`;
+        } else if (report.fixes_applied.length > 0) {
+            statusHtml = `<ul>${report.fixes_applied.map(renderFix).join("")}</ul>`;
         } else {
-            statusHtml = `<p class="ok">No issues found. File repacked successfully.</p>`;
+            statusHtml = `<p class="ok">No issues found. File left unchanged.</p>`;
         }
 
-        if (report.warnings.length > 0) {
-            statusHtml += `<ul>${report.warnings.map((w) => `<li class="warning">${escapeHtml(w)}</li>`).join("")}</ul>`;
+        if (report && report.warnings.length > 0) {
+            statusHtml += `<ul>${report.warnings.map(renderDiagnostic).join("")}</ul>`;
+        }
+
+        const format = report ? ` <small>(${escapeHtml(report.format.toUpperCase())})</small>` : "";
+        div.innerHTML = `<h3>${escapeHtml(result.filename)}${format}</h3>${statusHtml}`;
+
+        if (report?.warnings.some((w) => w.code === "unsupported-language")) {
+            div.appendChild(languagePrompt(result.path));
         }
 
-        div.innerHTML = `<h3>${escapeHtml(report.filename)} <small>(${escapeHtml(report.format)})</small></h3>${statusHtml}`;
         resultsEl.appendChild(div);
     }
 }
 
+function renderFix(fix: FixDescription): string {
+    if (fix.changes.length === 0) {
+        return `<li class="fix">${escapeHtml(fix.details)}</li>`;
+    }
+
+    const changes = fix.changes
+        .map((change) => {
+            const location = [change.file, change.line, change.column].filter((part) => part != null).join(":");
+            return `<li><small>${escapeHtml(location)}</small> <del>${escapeHtml(change.original)}</del> <ins>${escapeHtml(change.replacement)}</ins></li>`;
+        })
+        .join("");
+    return `<li class="fix"><details><summary>${escapeHtml(fix.details)}</summary><ul class="changes">${changes}</ul></details></li>`;
+}
+
+function renderDiagnostic(diagnostic: Diagnostic): string {
+    const location = diagnostic.file
+        ? ` <small>(${escapeHtml([diagnostic.file, diagnostic.line, diagnostic.column].filter((part) => part != null).join(":"))})</small>`
+        : "";
+    const suggestion = diagnostic.suggestion ? ` ${escapeHtml(diagnostic.suggestion)}` : "";
+    return `<li class="${diagnostic.severity}">${escapeHtml(diagnostic.message)}${suggestion}${location}</li>`;
+}
+
+/** Lets the user pick a supported language and process the file again. */
+function languagePrompt(path: string): HTMLElement {
+    const form = document.createElement("form");
+    form.className = "language-prompt";
+
+    const input = document.createElement("input");
+    input.placeholder = "Language code (e.g. en)";
+    input.value = "en";
+    const button = document.createElement("button");
+    button.type = "submit";
+    button.textContent = "Set language";
+    form.append(input, button);
+
+    form.addEventListener("submit", async (e) => {
+        e.preventDefault();
+        const language = input.value.trim();
+        if (language) {
+            await processFiles([path], language);
+        }
+    });
+    return form;
+}
+
 function escapeHtml(text: string): string {
     const div = document.createElement("div");
     div.textContent = text;